@@ -0,0 +1,113 @@
+use rand::Rng;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// The permission level granted to an API client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// May query chain, UTXO and telemetry state.
+    Read,
+    /// May additionally access the wallet and control the miner and the transaction generator.
+    Admin,
+}
+
+/// Bearer tokens accepted by the API server. Authentication is disabled when no token is
+/// configured, in which case every client is treated as an admin.
+#[derive(Clone, Default)]
+pub struct Auth {
+    tokens: Vec<(String, Role)>,
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_token(&mut self, token: &str, role: Role) {
+        self.tokens.push((token.to_string(), role));
+    }
+
+    /// Generate a random admin token and write it to the given path, readable only by the
+    /// current user.
+    pub fn generate_cookie<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let mut rng = rand::thread_rng();
+        let bytes: [u8; 32] = rng.gen();
+        let token = hex::encode(&bytes);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(token.as_bytes())?;
+        self.add_token(&token, Role::Admin);
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Return the role granted by the value of an `Authorization` header, or `None` if the
+    /// client is not authenticated.
+    pub fn authorize(&self, header: Option<&str>) -> Option<Role> {
+        if !self.is_enabled() {
+            return Some(Role::Admin);
+        }
+        let header = header?.trim();
+        match header.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("bearer ") => {}
+            _ => return None,
+        }
+        let token = header[7..].trim();
+        self.tokens
+            .iter()
+            .filter(|(t, _)| {
+                ring::constant_time::verify_slices_are_equal(t.as_bytes(), token.as_bytes()).is_ok()
+            })
+            .map(|(_, role)| *role)
+            .max()
+    }
+}
+
+/// Return the role required to access the given API endpoint.
+pub fn required_role(path: &str) -> Role {
+    match path {
+        "/blockchain/snapshot"
+        | "/utxo/snapshot"
+        | "/telematics/snapshot"
+        | "/metrics"
+        | "/status"
+        | "/peers"
+        | "/chain/block"
+        | "/chain/tx"
+        | "/chain/confidence"
+        | "/chain/miners" => Role::Read,
+        _ => Role::Admin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorize() {
+        let mut auth = Auth::new();
+        assert_eq!(auth.authorize(None), Some(Role::Admin));
+        auth.add_token("reader", Role::Read);
+        auth.add_token("root", Role::Admin);
+        assert_eq!(auth.authorize(None), None);
+        assert_eq!(auth.authorize(Some("Bearer reader")), Some(Role::Read));
+        assert_eq!(auth.authorize(Some("bearer root")), Some(Role::Admin));
+        assert_eq!(auth.authorize(Some("Bearer roo")), None);
+        assert_eq!(auth.authorize(Some("Basic root")), None);
+        assert!(Role::Admin >= required_role("/miner/start"));
+        assert!(Role::Read < required_role("/wallet/balance"));
+        assert!(Role::Read >= required_role("/metrics"));
+        assert!(Role::Read < required_role("/peers/connect"));
+        assert!(Role::Read >= required_role("/chain/tx"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::{error, fmt, io};
+
+/// A minimal blocking client for the API server of a running node.
+pub struct Client {
+    addr: String,
+    token: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    IOError(io::Error),
+    MalformedResponse,
+    /// The server responded with a non-success status code and the given message.
+    Rejected(u16, String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::IOError(ref e) => e.fmt(f),
+            ClientError::MalformedResponse => write!(f, "malformed response from the node"),
+            ClientError::Rejected(status, ref message) if status >= 300 => {
+                write!(f, "request rejected with status {}: {}", status, message)
+            }
+            ClientError::Rejected(_, ref message) => write!(f, "request failed: {}", message),
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ClientError::IOError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::IOError(err)
+    }
+}
+
+impl Client {
+    pub fn new(addr: &str, token: Option<String>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            token,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Result<serde_json::Value, ClientError> {
+        self.request("GET", path)
+    }
+
+    pub fn post(&self, path: &str) -> Result<serde_json::Value, ClientError> {
+        self.request("POST", path)
+    }
+
+    fn request(&self, method: &str, path: &str) -> Result<serde_json::Value, ClientError> {
+        let mut stream = TcpStream::connect(&self.addr)?;
+        let mut request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: 0\r\n",
+            method, path, &self.addr
+        );
+        if let Some(token) = &self.token {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        let response = String::from_utf8_lossy(&response);
+
+        // parse the status line and split the headers from the body
+        let status: u16 = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or(ClientError::MalformedResponse)?;
+        let body = match response.find("\r\n\r\n") {
+            Some(pos) => &response[pos + 4..],
+            None => return Err(ClientError::MalformedResponse),
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(body).map_err(|_| ClientError::MalformedResponse)?;
+        // plain API responses carry a success flag
+        let success = value
+            .get("success")
+            .and_then(|s| s.as_bool())
+            .unwrap_or(true);
+        if status >= 300 || !success {
+            let message = value
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("")
+                .to_string();
+            return Err(ClientError::Rejected(status, message));
+        }
+        Ok(value)
+    }
+}
@@ -1,19 +1,34 @@
+pub mod auth;
+pub mod client;
+
+use crate::block::Content;
 use crate::blockchain::BlockChain;
+use crate::blockdb::BlockDatabase;
+use crate::crypto::hash::{Hashable, H256};
+use crate::experiment::attribution::{Attribution, MinerShare};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::experiment::transaction_generator;
+use crate::handler::new_transaction;
 use crate::miner::memory_pool::MemoryPool;
 use crate::miner::Handle as MinerHandle;
+use crate::miner::Strategy as MinerStrategy;
 use crate::network::server::Handle as ServerHandle;
+use crate::transaction::address::Bech32Address;
 use crate::utxodb::UtxoDatabase;
-use crate::wallet::Wallet;
+use crate::wallet::{HistoryEntry, Wallet};
 
-use log::info;
+use auth::Auth;
+use log::{error, info, warn};
 use std::collections::HashMap;
+use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::SystemTime;
 use tiny_http::Header;
+use tiny_http::Method;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
+use tiny_http::{ServerConfig, SslConfig};
 use url::Url;
 
 pub struct Server {
@@ -23,8 +38,15 @@ pub struct Server {
     wallet: Arc<Wallet>,
     utxodb: Arc<UtxoDatabase>,
     blockchain: Arc<BlockChain>,
+    blockdb: Arc<BlockDatabase>,
+    server: ServerHandle,
+    mempool: Arc<Mutex<MemoryPool>>,
+    auth: Arc<Auth>,
 }
 
+/// The node is considered to be syncing if its best proposer block is older than this (in ms).
+const SYNC_TIP_AGE: u128 = 60_000;
+
 #[derive(Serialize)]
 struct ApiResponse {
     success: bool,
@@ -36,6 +58,124 @@ struct WalletBalanceResponse {
     balance: u64,
 }
 
+#[derive(Serialize)]
+struct WalletAddressesResponse {
+    addresses: Vec<Bech32Address>,
+}
+
+#[derive(Serialize)]
+struct WalletNewAddressResponse {
+    address: Bech32Address,
+}
+
+#[derive(Serialize)]
+struct WalletSendResponse {
+    transaction: String,
+}
+
+#[derive(Serialize)]
+struct WalletHistoryEntryResponse {
+    /// Either "received" or "sent".
+    direction: String,
+    value: u64,
+    address: Bech32Address,
+    /// The received coin, or the hash of the sent transaction.
+    reference: String,
+}
+
+#[derive(Serialize)]
+struct WalletHistoryResponse {
+    history: Vec<WalletHistoryEntryResponse>,
+}
+
+#[derive(Serialize)]
+struct BlockResponse {
+    hash: String,
+    block_type: String,
+    parent: String,
+    timestamp: u64,
+    nonce: u32,
+    miner: Option<Bech32Address>,
+    tag: Option<String>,
+    proposer_level: Option<u64>,
+    transaction_refs: Vec<String>,
+    proposer_refs: Vec<String>,
+    chain_number: Option<u16>,
+    voter_parent: Option<String>,
+    votes: Vec<String>,
+    transactions: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct MinerSharesResponse {
+    /// Number of levels of each chain the blocks are counted in.
+    levels: u64,
+    miners: Vec<MinerShare>,
+}
+
+#[derive(Serialize)]
+struct TransactionInputResponse {
+    coin: String,
+    value: u64,
+    owner: Bech32Address,
+}
+
+#[derive(Serialize)]
+struct TransactionOutputResponse {
+    value: u64,
+    recipient: Bech32Address,
+}
+
+#[derive(Serialize)]
+struct TransactionResponse {
+    hash: String,
+    /// Either "pending" (in the memory pool) or "confirmed" (in the ledger).
+    status: String,
+    transaction_block: Option<String>,
+    proposer: Option<String>,
+    inputs: Vec<TransactionInputResponse>,
+    outputs: Vec<TransactionOutputResponse>,
+}
+
+#[derive(Serialize)]
+struct ConfidenceResponse {
+    level: u64,
+    /// The block most likely to become the leader of the level.
+    candidate: Option<String>,
+    candidate_lcb: f32,
+    runner_up_ucb: f32,
+    /// Difference between the LCB of the candidate and the UCB of any other block.
+    margin: f32,
+    /// Estimated probability that the candidate will not be the final leader.
+    epsilon: f64,
+    /// For a transaction block, whether the candidate refers to it.
+    referred_by_candidate: Option<bool>,
+}
+
+/// A superblock template for external miners. A block is mined when the SHA256 hash of the
+/// serialized header, with the nonce (and optionally the timestamp) replaced, is below the
+/// difficulty when both are read as big-endian numbers.
+#[derive(Serialize)]
+struct MinerWorkResponse {
+    /// Version of the template, to be given back with a solution.
+    work: u64,
+    /// Hex-encoded serialization of the header.
+    header: String,
+    /// Byte offset of the timestamp, a 128-bit little-endian integer, in the header.
+    timestamp_offset: u64,
+    /// Byte offset of the nonce, a 32-bit little-endian integer, in the header.
+    nonce_offset: u64,
+    parent: String,
+    timestamp: u64,
+    content_merkle_root: String,
+    difficulty: String,
+}
+
+#[derive(Serialize)]
+struct MinerSubmitResponse {
+    hash: String,
+}
+
 #[derive(Serialize)]
 struct UtxoSnapshotResponse {
     checksum: String,
@@ -46,6 +186,23 @@ struct BlockchainSnapshotResponse {
     leaders: Vec<String>,
 }
 
+#[derive(Serialize)]
+struct VoterTipResponse {
+    hash: String,
+    level: u64,
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    best_proposer: String,
+    best_proposer_level: u64,
+    voter_tips: Vec<VoterTipResponse>,
+    ledger_tip_level: u64,
+    mempool_size: usize,
+    peers: usize,
+    sync_state: String,
+}
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -59,6 +216,20 @@ macro_rules! respond_result {
     }};
 }
 
+macro_rules! respond_status {
+    ( $req:expr, $status:expr, $message:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let payload = ApiResponse {
+            success: false,
+            message: $message.to_string(),
+        };
+        let resp = Response::from_string(serde_json::to_string_pretty(&payload).unwrap())
+            .with_header(content_type)
+            .with_status_code($status);
+        $req.respond(resp).unwrap();
+    }};
+}
+
 macro_rules! respond_json {
     ( $req:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -73,13 +244,28 @@ impl Server {
         addr: std::net::SocketAddr,
         wallet: &Arc<Wallet>,
         blockchain: &Arc<BlockChain>,
+        blockdb: &Arc<BlockDatabase>,
         utxodb: &Arc<UtxoDatabase>,
-        _server: &ServerHandle,
+        server: &ServerHandle,
         miner: &MinerHandle,
-        _mempool: &Arc<Mutex<MemoryPool>>,
+        mempool: &Arc<Mutex<MemoryPool>>,
         txgen_control_chan: crossbeam::Sender<transaction_generator::ControlSignal>,
+        auth: Auth,
+        ssl: Option<SslConfig>,
     ) {
-        let handle = HTTPServer::http(&addr).unwrap();
+        if !auth.is_enabled() && !addr.ip().is_loopback() {
+            warn!(
+                "API server at {} accepts unauthenticated requests on a public interface",
+                &addr
+            );
+        }
+        let handle = match HTTPServer::new(ServerConfig { addr, ssl }) {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Error starting API server at {}: {}", &addr, e);
+                process::exit(1);
+            }
+        };
         let server = Self {
             handle,
             transaction_generator_handle: txgen_control_chan,
@@ -87,6 +273,10 @@ impl Server {
             wallet: Arc::clone(wallet),
             utxodb: Arc::clone(utxodb),
             blockchain: Arc::clone(blockchain),
+            blockdb: Arc::clone(blockdb),
+            server: server.clone(),
+            mempool: Arc::clone(mempool),
+            auth: Arc::new(auth),
         };
         thread::spawn(move || {
             for req in server.handle.incoming_requests() {
@@ -95,6 +285,10 @@ impl Server {
                 let wallet = Arc::clone(&server.wallet);
                 let utxodb = Arc::clone(&server.utxodb);
                 let blockchain = Arc::clone(&server.blockchain);
+                let blockdb = Arc::clone(&server.blockdb);
+                let p2p_server = server.server.clone();
+                let mempool = Arc::clone(&server.mempool);
+                let auth = Arc::clone(&server.auth);
                 thread::spawn(move || {
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
@@ -105,6 +299,35 @@ impl Server {
                             return;
                         }
                     };
+                    let authorization = req
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.equiv("Authorization"))
+                        .map(|h| h.value.as_str().to_string());
+                    match auth.authorize(authorization.as_ref().map(|x| x.as_str())) {
+                        None => {
+                            let content_type =
+                                "Content-Type: application/json".parse::<Header>().unwrap();
+                            let challenge = "WWW-Authenticate: Bearer".parse::<Header>().unwrap();
+                            let payload = ApiResponse {
+                                success: false,
+                                message: "missing or invalid token".to_string(),
+                            };
+                            let resp = Response::from_string(
+                                serde_json::to_string_pretty(&payload).unwrap(),
+                            )
+                            .with_header(content_type)
+                            .with_header(challenge)
+                            .with_status_code(401);
+                            req.respond(resp).unwrap();
+                            return;
+                        }
+                        Some(role) if role < auth::required_role(url.path()) => {
+                            respond_status!(req, 403, "insufficient permission");
+                            return;
+                        }
+                        _ => {}
+                    }
                     match url.path() {
                         "/blockchain/snapshot" => {
                             let leaders = blockchain.proposer_leaders().unwrap();
@@ -128,6 +351,438 @@ impl Server {
                             };
                             respond_json!(req, resp);
                         }
+                        "/wallet/addresses" => {
+                            let addresses = wallet.addresses().unwrap();
+                            let resp = WalletAddressesResponse {
+                                addresses: addresses.into_iter().map(Bech32Address).collect(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/wallet/new-address" => {
+                            if req.method() != &Method::Post {
+                                respond_status!(req, 405, "method not allowed");
+                                return;
+                            }
+                            match wallet.generate_keypair() {
+                                Ok(addr) => respond_json!(
+                                    req,
+                                    WalletNewAddressResponse {
+                                        address: Bech32Address(addr)
+                                    }
+                                ),
+                                Err(e) => respond_result!(
+                                    req,
+                                    false,
+                                    format!("error generating key pair: {}", e)
+                                ),
+                            }
+                        }
+                        "/wallet/send" => {
+                            if req.method() != &Method::Post {
+                                respond_status!(req, 405, "method not allowed");
+                                return;
+                            }
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let recipient = match params.get("recipient") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing recipient");
+                                    return;
+                                }
+                            };
+                            let recipient = match recipient.parse::<Bech32Address>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing recipient: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let value = match params.get("value") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing value");
+                                    return;
+                                }
+                            };
+                            let value = match value.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing value: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let transaction =
+                                match wallet.create_transaction(recipient.into(), value, None) {
+                                    Ok(t) => t,
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error creating transaction: {}", e)
+                                        );
+                                        return;
+                                    }
+                                };
+                            let hash = transaction.hash();
+                            new_transaction(transaction, &mempool, &p2p_server);
+                            respond_json!(
+                                req,
+                                WalletSendResponse {
+                                    transaction: hash.to_string()
+                                }
+                            );
+                        }
+                        "/wallet/history" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let limit = match params.get("limit") {
+                                Some(v) => match v.parse::<usize>() {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing limit: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                None => 20,
+                            };
+                            let history = wallet
+                                .history(limit)
+                                .unwrap()
+                                .into_iter()
+                                .map(|entry| match entry {
+                                    HistoryEntry::Received {
+                                        coin,
+                                        value,
+                                        recipient,
+                                    } => WalletHistoryEntryResponse {
+                                        direction: "received".to_string(),
+                                        value,
+                                        address: Bech32Address(recipient),
+                                        reference: format!("{}:{}", coin.hash, coin.index),
+                                    },
+                                    HistoryEntry::Sent {
+                                        transaction,
+                                        value,
+                                        recipient,
+                                    } => WalletHistoryEntryResponse {
+                                        direction: "sent".to_string(),
+                                        value,
+                                        address: Bech32Address(recipient),
+                                        reference: transaction.to_string(),
+                                    },
+                                })
+                                .collect();
+                            respond_json!(req, WalletHistoryResponse { history });
+                        }
+                        "/chain/block" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing hash");
+                                    return;
+                                }
+                            };
+                            let hash = match hash.parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let block = match blockdb.get(&hash).unwrap() {
+                                Some(b) => b,
+                                None => {
+                                    respond_status!(req, 404, "block not found");
+                                    return;
+                                }
+                            };
+                            let to_strings = |v: &[H256]| -> Vec<String> {
+                                v.iter().map(|h| h.to_string()).collect()
+                            };
+                            let mut resp = BlockResponse {
+                                hash: hash.to_string(),
+                                block_type: String::new(),
+                                parent: block.header.parent.to_string(),
+                                timestamp: block.header.timestamp as u64,
+                                nonce: block.header.nonce,
+                                miner: block.header.miner().map(Bech32Address),
+                                tag: block.header.tag(),
+                                proposer_level: None,
+                                transaction_refs: vec![],
+                                proposer_refs: vec![],
+                                chain_number: None,
+                                voter_parent: None,
+                                votes: vec![],
+                                transactions: vec![],
+                            };
+                            match &block.content {
+                                Content::Proposer(c) => {
+                                    resp.block_type = "proposer".to_string();
+                                    if blockchain.contains_proposer(&hash).unwrap() {
+                                        resp.proposer_level =
+                                            Some(blockchain.proposer_level(&hash).unwrap());
+                                    }
+                                    resp.transaction_refs = to_strings(&c.transaction_refs);
+                                    resp.proposer_refs = to_strings(&c.proposer_refs);
+                                }
+                                Content::Voter(c) => {
+                                    resp.block_type = "voter".to_string();
+                                    resp.chain_number = Some(c.chain_number);
+                                    resp.voter_parent = Some(c.voter_parent.to_string());
+                                    resp.votes = to_strings(&c.votes);
+                                }
+                                Content::Transaction(c) => {
+                                    resp.block_type = "transaction".to_string();
+                                    resp.transactions = c
+                                        .transactions
+                                        .iter()
+                                        .map(|t| t.hash().to_string())
+                                        .collect();
+                                }
+                            }
+                            respond_json!(req, resp);
+                        }
+                        "/chain/tx" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing hash");
+                                    return;
+                                }
+                            };
+                            let hash = match hash.parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            // number of ledger levels to search for confirmed transactions
+                            let depth = match params.get("depth") {
+                                Some(v) => match v.parse::<u64>() {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing depth: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                None => 100,
+                            };
+                            let pending = mempool
+                                .lock()
+                                .unwrap()
+                                .get(&hash)
+                                .map(|e| e.transaction.clone());
+                            let found = match pending {
+                                Some(t) => Some((t, "pending", None, None)),
+                                None => {
+                                    let mut found = None;
+                                    let ledger =
+                                        blockchain.proposer_transaction_in_ledger(depth).unwrap();
+                                    'search: for (proposer, tx_blocks) in ledger.iter().rev() {
+                                        for tx_block in tx_blocks {
+                                            let block = match blockdb.get(tx_block).unwrap() {
+                                                Some(b) => b,
+                                                None => continue,
+                                            };
+                                            if let Content::Transaction(c) = block.content {
+                                                if let Some(t) = c
+                                                    .transactions
+                                                    .into_iter()
+                                                    .find(|t| t.hash() == hash)
+                                                {
+                                                    found = Some((
+                                                        t,
+                                                        "confirmed",
+                                                        Some(tx_block.to_string()),
+                                                        Some(proposer.to_string()),
+                                                    ));
+                                                    break 'search;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    found
+                                }
+                            };
+                            let (transaction, status, transaction_block, proposer) = match found {
+                                Some(f) => f,
+                                None => {
+                                    respond_status!(req, 404, "transaction not found");
+                                    return;
+                                }
+                            };
+                            let resp = TransactionResponse {
+                                hash: hash.to_string(),
+                                status: status.to_string(),
+                                transaction_block,
+                                proposer,
+                                inputs: transaction
+                                    .input
+                                    .iter()
+                                    .map(|i| TransactionInputResponse {
+                                        coin: format!("{}:{}", i.coin.hash, i.coin.index),
+                                        value: i.value,
+                                        owner: Bech32Address(i.owner),
+                                    })
+                                    .collect(),
+                                outputs: transaction
+                                    .output
+                                    .iter()
+                                    .map(|o| TransactionOutputResponse {
+                                        value: o.value,
+                                        recipient: Bech32Address(o.recipient),
+                                    })
+                                    .collect(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/chain/confidence" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let result = match (params.get("level"), params.get("block")) {
+                                (Some(level), None) => match level.parse::<u64>() {
+                                    Ok(level) => blockchain
+                                        .level_confidence(level)
+                                        .unwrap()
+                                        .map(|c| (level, None, c)),
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing level: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                (None, Some(hash)) => {
+                                    let hash = match hash.parse::<H256>() {
+                                        Ok(v) => v,
+                                        Err(e) => {
+                                            respond_result!(
+                                                req,
+                                                false,
+                                                format!("error parsing hash: {}", e)
+                                            );
+                                            return;
+                                        }
+                                    };
+                                    // number of proposer levels to search for references
+                                    let depth = match params.get("depth") {
+                                        Some(v) => match v.parse::<u64>() {
+                                            Ok(v) => v,
+                                            Err(e) => {
+                                                respond_result!(
+                                                    req,
+                                                    false,
+                                                    format!("error parsing depth: {}", e)
+                                                );
+                                                return;
+                                            }
+                                        },
+                                        None => 100,
+                                    };
+                                    match blockchain
+                                        .transaction_block_confidence(&hash, depth)
+                                        .unwrap()
+                                    {
+                                        Some((level, referred, Some(c))) => {
+                                            Some((level, Some(referred), c))
+                                        }
+                                        Some((_, _, None)) => None,
+                                        None => {
+                                            respond_status!(
+                                                req,
+                                                404,
+                                                "no proposer block refers to this block"
+                                            );
+                                            return;
+                                        }
+                                    }
+                                }
+                                _ => {
+                                    respond_result!(req, false, "expecting either level or block");
+                                    return;
+                                }
+                            };
+                            let (level, referred_by_candidate, confidence) = match result {
+                                Some(r) => r,
+                                None => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        "the confirmation rule does not estimate confidence"
+                                    );
+                                    return;
+                                }
+                            };
+                            let resp = ConfidenceResponse {
+                                level,
+                                candidate: confidence.candidate.map(|c| c.to_string()),
+                                candidate_lcb: confidence.candidate_lcb,
+                                runner_up_ucb: confidence.runner_up_ucb,
+                                margin: confidence.candidate_lcb - confidence.runner_up_ucb,
+                                epsilon: confidence.epsilon,
+                                referred_by_candidate,
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/chain/miners" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let levels = match params.get("levels") {
+                                Some(v) => match v.parse::<u64>() {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing levels: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                None => 100,
+                            };
+                            let attribution =
+                                Attribution::collect(&blockchain, &blockdb, levels).unwrap();
+                            respond_json!(
+                                req,
+                                MinerSharesResponse {
+                                    levels,
+                                    miners: attribution.shares(),
+                                }
+                            );
+                        }
                         "/miner/start" => {
                             let params = url.query_pairs();
                             let params: HashMap<_, _> = params.into_owned().collect();
@@ -174,9 +829,244 @@ impl Server {
                             miner.step();
                             respond_result!(req, true, "ok");
                         }
+                        "/miner/adversary" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let mut strategy = MinerStrategy::default();
+                            for (name, switch) in [
+                                ("selfish", &mut strategy.selfish_mining),
+                                ("balance", &mut strategy.balance_votes),
+                            ]
+                            .iter_mut()
+                            {
+                                if let Some(v) = params.get(*name) {
+                                    match v.parse::<bool>() {
+                                        Ok(v) => **switch = v,
+                                        Err(e) => {
+                                            respond_result!(
+                                                req,
+                                                false,
+                                                format!("error parsing {} switch: {}", name, e)
+                                            );
+                                            return;
+                                        }
+                                    }
+                                }
+                            }
+                            if let Some(v) = params.get("censor") {
+                                for hash in v.split(',').filter(|h| !h.is_empty()) {
+                                    match hash.parse::<H256>() {
+                                        Ok(hash) => {
+                                            strategy.censored_transactions.insert(hash);
+                                        }
+                                        Err(e) => {
+                                            respond_result!(
+                                                req,
+                                                false,
+                                                format!(
+                                                    "error parsing censored transaction: {}",
+                                                    e
+                                                )
+                                            );
+                                            return;
+                                        }
+                                    }
+                                }
+                            }
+                            if let Some(v) = params.get("voter-delay") {
+                                match v.parse::<u64>() {
+                                    Ok(v) => strategy.voter_delay = Some(v),
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing voter delay: {}", e)
+                                        );
+                                        return;
+                                    }
+                                }
+                            }
+                            miner.set_strategy(strategy);
+                            respond_result!(req, true, "ok");
+                        }
+                        "/miner/work" => {
+                            let (work, header) = match miner.work() {
+                                Some(w) => w,
+                                None => {
+                                    respond_result!(req, false, "miner is not running");
+                                    return;
+                                }
+                            };
+                            let timestamp_offset =
+                                bincode::serialized_size(&header.parent).unwrap();
+                            let nonce_offset = timestamp_offset
+                                + bincode::serialized_size(&header.timestamp).unwrap();
+                            let resp = MinerWorkResponse {
+                                work,
+                                header: hex::encode(bincode::serialize(&header).unwrap()),
+                                timestamp_offset,
+                                nonce_offset,
+                                parent: header.parent.to_string(),
+                                timestamp: header.timestamp as u64,
+                                content_merkle_root: header.content_merkle_root.to_string(),
+                                difficulty: header.difficulty.to_string(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/miner/submit" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let work = match params.get("work") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing work");
+                                    return;
+                                }
+                            };
+                            let work = match work.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing work: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let nonce = match params.get("nonce") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing nonce");
+                                    return;
+                                }
+                            };
+                            let nonce = match nonce.parse::<u32>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing nonce: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let timestamp = match params.get("timestamp").map(|t| t.parse::<u128>())
+                            {
+                                None => None,
+                                Some(Ok(v)) => Some(v),
+                                Some(Err(e)) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing timestamp: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            match miner.submit(work, nonce, timestamp) {
+                                Ok(hash) => respond_json!(
+                                    req,
+                                    MinerSubmitResponse {
+                                        hash: hash.to_string()
+                                    }
+                                ),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
                         "/telematics/snapshot" => {
                             respond_json!(req, PERFORMANCE_COUNTER.snapshot());
                         }
+                        "/status" => {
+                            let best_proposer = blockchain.best_proposer().unwrap();
+                            let tip_timestamp = blockdb
+                                .get(&best_proposer)
+                                .unwrap()
+                                .map(|b| b.header.timestamp)
+                                .unwrap_or(0);
+                            let current_time = SystemTime::now()
+                                .duration_since(SystemTime::UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis();
+                            let peers = p2p_server.peers().len();
+                            let sync_state = if peers == 0 {
+                                "isolated"
+                            } else if current_time > tip_timestamp + SYNC_TIP_AGE {
+                                "syncing"
+                            } else {
+                                "synced"
+                            };
+                            let voter_tips = blockchain
+                                .voter_tips()
+                                .into_iter()
+                                .map(|(hash, level)| VoterTipResponse {
+                                    hash: hash.to_string(),
+                                    level,
+                                })
+                                .collect();
+                            let resp = StatusResponse {
+                                best_proposer: best_proposer.to_string(),
+                                best_proposer_level: blockchain.best_proposer_level(),
+                                voter_tips,
+                                ledger_tip_level: blockchain.ledger_tip_level(),
+                                mempool_size: mempool.lock().unwrap().len(),
+                                peers,
+                                sync_state: sync_state.to_string(),
+                            };
+                            respond_json!(req, resp);
+                        }
+                        "/peers" => {
+                            respond_json!(req, p2p_server.peers());
+                        }
+                        "/peers/connect" | "/peers/disconnect" => {
+                            if req.method() != &Method::Post {
+                                respond_status!(req, 405, "method not allowed");
+                                return;
+                            }
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let addr = match params.get("addr") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing addr");
+                                    return;
+                                }
+                            };
+                            let addr = match addr.parse::<std::net::SocketAddr>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing addr: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            if url.path() == "/peers/connect" {
+                                match p2p_server.connect(addr) {
+                                    Ok(_) => respond_result!(req, true, "ok"),
+                                    Err(e) => respond_result!(
+                                        req,
+                                        false,
+                                        format!("error connecting to peer: {}", e)
+                                    ),
+                                }
+                            } else if p2p_server.disconnect(addr) {
+                                respond_result!(req, true, "ok");
+                            } else {
+                                respond_result!(req, false, "peer not connected");
+                            }
+                        }
+                        "/metrics" => {
+                            let content_type = "Content-Type: text/plain; version=0.0.4"
+                                .parse::<Header>()
+                                .unwrap();
+                            let resp = Response::from_string(PERFORMANCE_COUNTER.prometheus())
+                                .with_header(content_type);
+                            req.respond(resp).unwrap();
+                        }
                         "/transaction-generator/start" => {
                             let params = url.query_pairs();
                             let params: HashMap<_, _> = params.into_owned().collect();
@@ -403,18 +1293,7 @@ impl Server {
                             }
                         }
                         _ => {
-                            let content_type =
-                                "Content-Type: application/json".parse::<Header>().unwrap();
-                            let payload = ApiResponse {
-                                success: false,
-                                message: "endpoint not found".to_string(),
-                            };
-                            let resp = Response::from_string(
-                                serde_json::to_string_pretty(&payload).unwrap(),
-                            )
-                            .with_header(content_type)
-                            .with_status_code(404);
-                            req.respond(resp).unwrap();
+                            respond_status!(req, 404, "endpoint not found");
                         }
                     }
                 });
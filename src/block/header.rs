@@ -1,5 +1,8 @@
 use crate::crypto::hash::{Hashable, H256};
-// TODO: Add the address of the miner
+use crate::transaction::Address;
+
+/// Maximum length of the operator tag of a block, in bytes.
+pub const MAX_TAG_LENGTH: usize = 32;
 
 /// The header of a block.
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Copy)]
@@ -12,14 +15,16 @@ pub struct Header {
     pub nonce: u32,
     /// Merkle root of the block content.
     pub content_merkle_root: H256,
-    /// Extra content for debugging purposes.
+    /// Extra content set by the operator of the miner. It holds a zero-padded UTF-8 tag.
     pub extra_content: [u8; 32],
+    /// Address of the miner, or zero if the block is not attributed to any miner.
+    pub miner: Address,
     /// Mining difficulty of this block.
     pub difficulty: H256,
 }
 
 impl Header {
-    /// Create a new block header.
+    /// Create a new block header, not attributed to any miner.
     pub fn new(
         parent: H256,
         timestamp: u128,
@@ -34,9 +39,44 @@ impl Header {
             nonce,
             content_merkle_root,
             extra_content,
+            miner: Address::default(),
             difficulty,
         }
     }
+
+    /// Get the address of the miner, if the block is attributed to one.
+    pub fn miner(&self) -> Option<Address> {
+        if self.miner == Address::default() {
+            None
+        } else {
+            Some(self.miner)
+        }
+    }
+
+    /// Get the operator tag in the extra content, if there is one.
+    pub fn tag(&self) -> Option<String> {
+        let len = self
+            .extra_content
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(MAX_TAG_LENGTH);
+        if len == 0 {
+            None
+        } else {
+            Some(String::from_utf8_lossy(&self.extra_content[..len]).into_owned())
+        }
+    }
+}
+
+/// Encode an operator tag as the extra content of a header. Return `None` if the tag is longer
+/// than `MAX_TAG_LENGTH` bytes.
+pub fn tag_content(tag: &str) -> Option<[u8; 32]> {
+    if tag.len() > MAX_TAG_LENGTH {
+        return None;
+    }
+    let mut content = [0; 32];
+    content[..tag.len()].copy_from_slice(tag.as_bytes());
+    Some(content)
 }
 
 impl Hashable for Header {
@@ -49,7 +89,7 @@ impl Hashable for Header {
 
 #[cfg(test)]
 pub mod tests {
-    use super::super::header::Header;
+    use super::super::header::{tag_content, Header};
 
     use crate::crypto::hash::{Hashable, H256};
 
@@ -61,6 +101,19 @@ pub mod tests {
         assert_eq!(header.hash(), header_hash_should_be);
     }
 
+    #[test]
+    fn tag() {
+        let mut header = sample_header();
+        assert_eq!(header.tag(), None);
+        assert_eq!(header.miner(), None);
+        header.extra_content = tag_content("pool-1").unwrap();
+        assert_eq!(header.tag(), Some("pool-1".to_string()));
+        let full = "x".repeat(32);
+        header.extra_content = tag_content(&full).unwrap();
+        assert_eq!(header.tag(), Some(full));
+        assert!(tag_content(&"x".repeat(33)).is_none());
+    }
+
     #[macro_export]
     macro_rules! gen_hashed_data {
         () => {{
@@ -106,7 +159,7 @@ pub mod tests {
 
     pub fn sample_header_hash_should_be() -> H256 {
         let header_hash_should_be =
-            (&hex!("1d6e0f5f11248d070c6b15d103ead72a8802279c600a60b40b38510b582aacbf")).into(); // Calculated on Mar 15, 2019
+            (&hex!("09c59d74e29473a42c5b0ed9628963eea69d5c889e9f43fadc19b165a9e092df")).into();
         header_hash_should_be
     }
 }
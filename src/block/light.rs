@@ -0,0 +1,98 @@
+use super::header::Header;
+use super::{proposer, voter, Block, Content};
+use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::merkle::MerkleTree;
+
+/// A block stripped down to what a light client tracks. It keeps the header and the sortition
+/// proof, so that the PoW and the block type can still be verified, but only carries the content
+/// of voter blocks and the proposer blocks referred by proposer blocks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LightBlock {
+    /// The header of the block.
+    pub header: Header,
+    /// The sortition proof of the content.
+    pub sortition_proof: Vec<H256>,
+    /// The content of a voter block, or the content hash of other blocks.
+    pub content: LightContent,
+}
+
+/// The content of a light block.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum LightContent {
+    /// Hash of the transaction block content.
+    Transaction(H256),
+    /// Proposer block content without the transaction blocks it refers.
+    Proposer {
+        /// Merkle root of the transaction blocks referred.
+        transaction_refs_root: H256,
+        /// Proposer blocks referred.
+        proposer_refs: Vec<H256>,
+        /// Commitment to the UTXO set.
+        utxo_commitment: Option<H256>,
+    },
+    /// Voter block content.
+    Voter(voter::Content),
+}
+
+impl LightBlock {
+    /// Strip a block down to a light block.
+    pub fn from_block(block: &Block) -> Self {
+        let content = match &block.content {
+            Content::Transaction(c) => LightContent::Transaction(c.hash()),
+            Content::Proposer(c) => LightContent::Proposer {
+                transaction_refs_root: MerkleTree::new(&c.transaction_refs).root(),
+                proposer_refs: c.proposer_refs.clone(),
+                utxo_commitment: c.utxo_commitment,
+            },
+            Content::Voter(c) => LightContent::Voter(c.clone()),
+        };
+        Self {
+            header: block.header,
+            sortition_proof: block.sortition_proof.clone(),
+            content,
+        }
+    }
+
+    /// Get the hash of the original content of the block.
+    pub fn content_hash(&self) -> H256 {
+        match &self.content {
+            LightContent::Transaction(h) => *h,
+            LightContent::Proposer {
+                transaction_refs_root,
+                proposer_refs,
+                utxo_commitment,
+            } => proposer::content_hash(transaction_refs_root, proposer_refs, utxo_commitment),
+            LightContent::Voter(c) => c.hash(),
+        }
+    }
+
+    /// Turn into a block to be inserted into the blockchain of a light client. Proposer blocks lose
+    /// their references to transaction blocks, and transaction blocks are not inserted at all. The resulting block no
+    /// longer matches its sortition proof, so it must be verified before the conversion.
+    pub fn into_block(self) -> Option<Block> {
+        let content = match self.content {
+            LightContent::Transaction(_) => return None,
+            LightContent::Proposer {
+                proposer_refs,
+                utxo_commitment,
+                ..
+            } => Content::Proposer(proposer::Content {
+                transaction_refs: vec![],
+                proposer_refs,
+                utxo_commitment,
+            }),
+            LightContent::Voter(c) => Content::Voter(c),
+        };
+        Some(Block::from_header(
+            self.header,
+            content,
+            self.sortition_proof,
+        ))
+    }
+}
+
+impl Hashable for LightBlock {
+    fn hash(&self) -> H256 {
+        self.header.hash()
+    }
+}
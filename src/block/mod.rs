@@ -1,4 +1,5 @@
 pub mod header;
+pub mod light;
 pub mod proposer;
 pub mod transaction;
 pub mod voter;
@@ -113,6 +114,7 @@ pub mod tests {
 
     use super::*;
     use crate::config;
+    use crate::crypto::merkle::MerkleTree;
     use crate::transaction::Transaction;
     use rand::Rng;
 
@@ -133,6 +135,7 @@ pub mod tests {
         let content = Content::Proposer(proposer::Content {
             transaction_refs,
             proposer_refs,
+            utxo_commitment: None,
         });
         let content_hash = content.hash();
         Block::new(
@@ -147,6 +150,63 @@ pub mod tests {
         )
     }
 
+    pub fn committing_proposer_block(parent: H256, utxo_commitment: H256) -> Block {
+        let content = Content::Proposer(proposer::Content {
+            transaction_refs: vec![],
+            proposer_refs: vec![],
+            utxo_commitment: Some(utxo_commitment),
+        });
+        let content_hash = content.hash();
+        Block::new(
+            parent,
+            0,
+            random_nonce!(),
+            content_hash,
+            vec![content_hash],
+            content,
+            [0u8; 32],
+            *config::DEFAULT_DIFFICULTY,
+        )
+    }
+
+    /// A block with the given content whose header hash passes the PoW and sortitions it to the
+    /// type of the content, with a valid sortition proof.
+    pub fn mined_block(parent: H256, content: Content, config: &config::BlockchainConfig) -> Block {
+        let index = match &content {
+            Content::Proposer(_) => config::PROPOSER_INDEX,
+            Content::Transaction(_) => config::TRANSACTION_INDEX,
+            Content::Voter(c) => c.chain_number + config::FIRST_VOTER_INDEX,
+        };
+        let mut contents: Vec<Content> = vec![
+            Content::Proposer(Default::default()),
+            Content::Transaction(Default::default()),
+        ];
+        for chain_number in 0..config.voter_chains {
+            contents.push(Content::Voter(voter::Content {
+                chain_number,
+                ..Default::default()
+            }));
+        }
+        contents[index as usize] = content.clone();
+        let tree = MerkleTree::new(&contents);
+        for nonce in 0.. {
+            let block = Block::new(
+                parent,
+                0,
+                nonce,
+                tree.root(),
+                tree.proof(index as usize),
+                content.clone(),
+                [0u8; 32],
+                *config::DEFAULT_DIFFICULTY,
+            );
+            if config.sortition_hash(&block.hash(), &block.header.difficulty) == Some(index) {
+                return block;
+            }
+        }
+        unreachable!()
+    }
+
     pub fn voter_block(
         parent: H256,
         timestamp: u128,
@@ -12,6 +12,10 @@ pub struct Content {
     pub transaction_refs: Vec<H256>,
     /// List of proposer blocks referred by this proposer block.
     pub proposer_refs: Vec<H256>,
+    /// Commitment to the UTXO set after applying the ledger up to `UTXO_COMMITMENT_DELAY` levels
+    /// below this block, if the miner knows it. It is not checked when the block arrives, since
+    /// that level of our ledger may still change, and is only trusted in confirmed leaders.
+    pub utxo_commitment: Option<H256>,
     // TODO: coinbase transaction, and maybe refer to voter blocks to include their coinbase
     // transactions.
 }
@@ -22,32 +26,55 @@ impl Content {
         Self {
             transaction_refs,
             proposer_refs,
+            utxo_commitment: None,
         }
     }
 }
 
 impl PayloadSize for Content {
     fn size(&self) -> usize {
+        let commitment_size = match self.utxo_commitment {
+            Some(_) => std::mem::size_of::<H256>(),
+            None => 0,
+        };
         std::mem::size_of::<H256>() * (self.transaction_refs.len() + self.proposer_refs.len())
+            + commitment_size
     }
 }
 
 impl Hashable for Content {
     fn hash(&self) -> H256 {
         let tx_merkle_tree = MerkleTree::new(&self.transaction_refs);
-        let prop_merkle_tree = MerkleTree::new(&self.proposer_refs);
-        let mut bytes = [0u8; 64];
-        bytes[..32].copy_from_slice(tx_merkle_tree.root().as_ref());
-        bytes[32..64].copy_from_slice(prop_merkle_tree.root().as_ref());
-        ring::digest::digest(&ring::digest::SHA256, &bytes).into()
+        content_hash(
+            &tx_merkle_tree.root(),
+            &self.proposer_refs,
+            &self.utxo_commitment,
+        )
     }
 }
 
+/// Compute the hash of proposer block content from the Merkle root of the transaction blocks it
+/// refers and the rest of the content.
+pub fn content_hash(
+    transaction_refs_root: &H256,
+    proposer_refs: &[H256],
+    utxo_commitment: &Option<H256>,
+) -> H256 {
+    let prop_merkle_tree = MerkleTree::new(proposer_refs);
+    let utxo_commitment = utxo_commitment.unwrap_or_default();
+    let mut bytes = [0u8; 96];
+    bytes[..32].copy_from_slice(transaction_refs_root.as_ref());
+    bytes[32..64].copy_from_slice(prop_merkle_tree.root().as_ref());
+    bytes[64..96].copy_from_slice(utxo_commitment.as_ref());
+    ring::digest::digest(&ring::digest::SHA256, &bytes).into()
+}
+
 /// Generate the genesis block of the proposer chain.
 pub fn genesis() -> Block {
     let content = Content {
         transaction_refs: vec![],
         proposer_refs: vec![],
+        utxo_commitment: None,
     };
     let all_zero: [u8; 32] = [0; 32];
     // TODO: this will not pass validation.
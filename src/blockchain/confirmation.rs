@@ -0,0 +1,537 @@
+//! Rules that decide which proposer block of a level, if any, is confirmed as the leader of that
+//! level.
+
+use crate::config::BlockchainConfig;
+use crate::crypto::hash::H256;
+use statrs::distribution::{Discrete, Poisson, Univariate};
+use std::{error, fmt, str};
+
+/// A vote cast on a proposer block by the main chain of a voter chain.
+#[derive(Debug, Clone)]
+pub struct Vote {
+    /// The voter chain that cast this vote.
+    pub chain: u16,
+    /// Number of main chain voter blocks from the vote to the tip of the voter chain, inclusive.
+    pub depth: u64,
+    /// Number of voter blocks, on any fork, mined at the levels from the vote to the tip.
+    pub blocks_after: u64,
+}
+
+/// The state of one proposer level as seen by a confirmation rule.
+#[derive(Debug, Clone)]
+pub struct LevelVotes {
+    pub level: u64,
+    /// Proposer blocks on this level, and the votes cast on each of them.
+    pub blocks: Vec<(H256, Vec<Vote>)>,
+    /// Total number of voter chains.
+    pub voter_chains: u16,
+    /// The block on this level that is an ancestor of the best proposer block.
+    pub main_chain: Option<H256>,
+    /// Number of proposer levels from this level to the best proposer block, inclusive.
+    pub proposer_depth: u64,
+}
+
+impl LevelVotes {
+    fn total_votes(&self) -> u64 {
+        self.blocks.iter().map(|(_, v)| v.len() as u64).sum()
+    }
+}
+
+/// How safe the leader candidate of a proposer level is. The bounds are taken at the confidence
+/// used to confirm leaders, while epsilon is the confidence at which the candidate would just be
+/// confirmed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Confidence {
+    /// The block with the highest lower confidence bound on its final votes, if any.
+    pub candidate: Option<H256>,
+    /// Lower confidence bound on the final votes of the candidate.
+    pub candidate_lcb: f32,
+    /// Highest upper confidence bound on the final votes of any other block, including blocks
+    /// not seen yet.
+    pub runner_up_ucb: f32,
+    /// Estimated probability that the candidate will not be the final leader.
+    pub epsilon: f64,
+}
+
+impl Confidence {
+    fn none(voter_chains: u16) -> Self {
+        Self {
+            candidate: None,
+            candidate_lcb: 0.0,
+            runner_up_ucb: f32::from(voter_chains),
+            epsilon: 1.0,
+        }
+    }
+}
+
+/// Upper end of the search for the quantile of a confidence, where epsilon is below 1e-300.
+const MAX_QUANTILE: f32 = 37.0;
+const QUANTILE_PRECISION: f32 = 0.01;
+
+/// Approximate the probability that a standard gaussian variable exceeds `x`.
+fn gaussian_tail(x: f64) -> f64 {
+    // this is the inverse of the approximation used to derive the quantiles from -log(epsilon)
+    // in the config, and it is only accurate for large x
+    let tail = (-x * x / 2.0).exp() / (x * (2.0 * std::f64::consts::PI).sqrt());
+    if tail.is_nan() || tail > 1.0 {
+        1.0
+    } else {
+        tail
+    }
+}
+
+pub trait ConfirmationRule: Send + Sync {
+    /// Return the leader of the given level, or `None` if no block can be confirmed yet.
+    /// `confirmed` tells whether the level currently has a leader, so that a rule may use a
+    /// lower threshold to keep a leader than to elect one.
+    fn leader(&self, level: &LevelVotes, confirmed: bool) -> Option<H256>;
+
+    /// Return a list of blocks of the given level that is certain to contain its eventual
+    /// leader, or `None` if the rule cannot narrow it down yet. By default, this is the leader
+    /// once one is confirmed.
+    fn candidates(&self, level: &LevelVotes) -> Option<Vec<H256>> {
+        self.leader(level, false).map(|leader| vec![leader])
+    }
+
+    /// Whether the leader of a level may change even if no vote on that level changes, e.g.
+    /// because the rule looks at vote depth or at the proposer tree. If so, return how many
+    /// levels below the ledger tip are re-evaluated together with all unconfirmed levels each
+    /// time the ledger is updated.
+    fn reevaluation_window(&self) -> Option<u64> {
+        None
+    }
+
+    /// Return how likely the leader candidate of the given level is to be final, if the rule
+    /// has a probabilistic model for it.
+    fn confidence(&self, _level: &LevelVotes) -> Option<Confidence> {
+        None
+    }
+}
+
+/// The available confirmation rules and their parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfirmationRuleKind {
+    /// The confirmation policy from https://arxiv.org/abs/1810.08092.
+    Prism,
+    /// Confirm the block that has votes from a majority of voter chains, counting only votes at
+    /// least the given number of blocks deep.
+    DepthMajority(u64),
+    /// Confirm the block on the longest proposer chain once it is the given number of levels deep.
+    LongestChain(u64),
+    /// Confirm a block as soon as no other block can outvote it, assuming no vote is reverted.
+    Optimistic,
+}
+
+impl ConfirmationRuleKind {
+    pub fn build(self, config: &BlockchainConfig) -> Box<dyn ConfirmationRule> {
+        match self {
+            ConfirmationRuleKind::Prism => Box::new(PrismRule {
+                adversary_ratio: config.adversary_ratio,
+                quantile_confirm: config.quantile_epsilon_confirm,
+                quantile_deconfirm: config.quantile_epsilon_deconfirm,
+            }),
+            ConfirmationRuleKind::DepthMajority(depth) => Box::new(DepthMajorityRule { depth }),
+            ConfirmationRuleKind::LongestChain(depth) => Box::new(LongestChainRule { depth }),
+            ConfirmationRuleKind::Optimistic => Box::new(OptimisticRule),
+        }
+    }
+}
+
+impl fmt::Display for ConfirmationRuleKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfirmationRuleKind::Prism => write!(f, "prism"),
+            ConfirmationRuleKind::DepthMajority(k) => write!(f, "majority:{}", k),
+            ConfirmationRuleKind::LongestChain(k) => write!(f, "longest-chain:{}", k),
+            ConfirmationRuleKind::Optimistic => write!(f, "optimistic"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseRuleError(String);
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown confirmation rule {}, expecting prism, majority:<depth>, longest-chain:<depth> or optimistic",
+            self.0
+        )
+    }
+}
+
+impl error::Error for ParseRuleError {}
+
+impl str::FromStr for ConfirmationRuleKind {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap();
+        let depth = parts.next().map(|d| d.parse::<u64>());
+        match (name, depth) {
+            ("prism", None) => Ok(ConfirmationRuleKind::Prism),
+            ("optimistic", None) => Ok(ConfirmationRuleKind::Optimistic),
+            ("majority", Some(Ok(k))) if k > 0 => Ok(ConfirmationRuleKind::DepthMajority(k)),
+            ("longest-chain", Some(Ok(k))) => Ok(ConfirmationRuleKind::LongestChain(k)),
+            _ => Err(ParseRuleError(s.to_string())),
+        }
+    }
+}
+
+/// Poisson adversary model with a Gaussian lower confidence bound on the votes of each block.
+pub struct PrismRule {
+    adversary_ratio: f32,
+    quantile_confirm: f32,
+    quantile_deconfirm: f32,
+}
+
+impl PrismRule {
+    /// Compute the lower confidence bound on the final votes of each block of the level, in the
+    /// order of `level.blocks`, or `None` if too few votes have been cast to tell.
+    fn votes_lcb(&self, level: &LevelVotes, quantile: f32) -> Option<Vec<f32>> {
+        // collect the total votes on all proposer blocks, and the number of
+        // voter blocks mined after those votes are casted
+        let total_vote_count = level.total_votes();
+        let total_vote_blocks: u64 = level
+            .blocks
+            .iter()
+            .flat_map(|(_, votes)| votes.iter())
+            .map(|v| v.blocks_after)
+            .sum();
+
+        // For debugging purpose only. This is very important for security.
+        // TODO: remove this check in the future
+        if u64::from(level.voter_chains) < total_vote_count {
+            panic!(
+                "voter_chains: {} total_votes:{}",
+                level.voter_chains, total_vote_count
+            )
+        }
+
+        // no point in going further if less than 3/5 votes are cast
+        if total_vote_count <= u64::from(level.voter_chains * 3 / 5) {
+            return None;
+        }
+        // calculate the average number of voter blocks mined after
+        // a vote is casted. we use this as an estimator of honest mining
+        // rate, and then derive the believed malicious mining rate
+        let avg_vote_blocks = total_vote_blocks as f32 / total_vote_count as f32;
+        // expected voter depth of an adversary
+        let adversary_expected_vote_depth =
+            avg_vote_blocks / (1.0 - self.adversary_ratio) * self.adversary_ratio;
+        let poisson = Poisson::new(f64::from(adversary_expected_vote_depth)).unwrap();
+
+        // for each block calculate the lower bound on the number of votes
+        let mut votes_lcb: Vec<f32> = vec![];
+        for (_, votes) in &level.blocks {
+            let mut block_votes_mean: f32 = 0.0; // mean E[X]
+            let mut block_votes_variance: f32 = 0.0; // Var[X]
+            let mut block_votes_lcb: f32 = 0.0;
+            for vote in votes {
+                let depth = vote.depth;
+                // probability that the adversary will remove this vote
+                let mut p: f32 = 1.0 - poisson.cdf((depth as f32 + 1.0).into()) as f32;
+                for k in 0..depth {
+                    // probability that the adversary has mined k blocks
+                    let p1 = poisson.pmf(k) as f32;
+                    // probability that the adversary will overtake 'depth-k' blocks
+                    let p2 = (self.adversary_ratio / (1.0 - self.adversary_ratio))
+                        .powi((depth - k + 1) as i32);
+                    p += p1 * p2;
+                }
+                block_votes_mean += 1.0 - p;
+                block_votes_variance += p * (1.0 - p);
+            }
+            // using gaussian approximation
+            let tmp = block_votes_mean - (block_votes_variance).sqrt() * quantile;
+            if tmp > 0.0 {
+                block_votes_lcb += tmp;
+            }
+            votes_lcb.push(block_votes_lcb);
+        }
+        Some(votes_lcb)
+    }
+
+    /// Return the block with the highest lower confidence bound and that bound, preferring the
+    /// lower hash in case of a tie.
+    fn max_lcb(level: &LevelVotes, votes_lcb: &[f32]) -> (Option<H256>, f32) {
+        let mut new_leader: Option<H256> = None;
+        let mut max_vote_lcb: f32 = 0.0;
+
+        for ((block, _), &block_votes_lcb) in level.blocks.iter().zip(votes_lcb) {
+            if max_vote_lcb < block_votes_lcb {
+                max_vote_lcb = block_votes_lcb;
+                new_leader = Some(*block);
+            }
+            // In case of a tie, choose block with lower hash.
+            if (max_vote_lcb - block_votes_lcb).abs() < std::f32::EPSILON {
+                if let Some(leader) = new_leader {
+                    if *block < leader {
+                        new_leader = Some(*block);
+                    }
+                }
+            }
+        }
+        (new_leader, max_vote_lcb)
+    }
+
+    fn leader_at(&self, level: &LevelVotes, quantile: f32) -> Option<H256> {
+        let votes_lcb = self.votes_lcb(level, quantile)?;
+        let total_votes_lcb: f32 = votes_lcb.iter().sum();
+        let (new_leader, max_vote_lcb) = Self::max_lcb(level, &votes_lcb);
+        // check if the lcb_vote of new_leader is bigger than second best ucb votes
+        let remaining_votes = f32::from(level.voter_chains) - total_votes_lcb;
+
+        // if max_vote_lcb is lesser than the remaining_votes, then a private block could
+        // get the remaining votes and become the leader block
+        let leader = match new_leader {
+            Some(leader) if max_vote_lcb > remaining_votes => leader,
+            _ => return None,
+        };
+        for ((p_block, _), lcb) in level.blocks.iter().zip(&votes_lcb) {
+            // if the below condition is true, then final votes on p_block could overtake new_leader
+            if max_vote_lcb < lcb + remaining_votes && *p_block != leader {
+                return None;
+            }
+            //In case of a tie, choose block with lower hash.
+            if (max_vote_lcb - (lcb + remaining_votes)).abs() < std::f32::EPSILON
+                && *p_block < leader
+            {
+                return None;
+            }
+        }
+        Some(leader)
+    }
+}
+
+impl ConfirmationRule for PrismRule {
+    fn leader(&self, level: &LevelVotes, confirmed: bool) -> Option<H256> {
+        // we confirm with a higher confidence so we don't have false deconfirmation
+        let quantile = if confirmed {
+            self.quantile_deconfirm
+        } else {
+            self.quantile_confirm
+        };
+        self.leader_at(level, quantile)
+    }
+
+    fn confidence(&self, level: &LevelVotes) -> Option<Confidence> {
+        let votes_lcb = match self.votes_lcb(level, self.quantile_confirm) {
+            Some(v) => v,
+            None => return Some(Confidence::none(level.voter_chains)),
+        };
+        let total_votes_lcb: f32 = votes_lcb.iter().sum();
+        let remaining_votes = f32::from(level.voter_chains) - total_votes_lcb;
+        let (candidate, candidate_lcb) = Self::max_lcb(level, &votes_lcb);
+        let candidate = match candidate {
+            Some(c) => c,
+            None => return Some(Confidence::none(level.voter_chains)),
+        };
+        // a block we have not seen could get all the remaining votes
+        let runner_up_ucb = level
+            .blocks
+            .iter()
+            .zip(&votes_lcb)
+            .filter(|((block, _), _)| *block != candidate)
+            .map(|(_, lcb)| lcb + remaining_votes)
+            .fold(remaining_votes, f32::max);
+
+        // find the highest quantile at which the candidate is still elected, and take the
+        // gaussian tail probability beyond it
+        let mut low = 0.0f32;
+        if self.leader_at(level, low) == Some(candidate) {
+            let mut high = MAX_QUANTILE;
+            while high - low > QUANTILE_PRECISION {
+                let mid = (low + high) / 2.0;
+                if self.leader_at(level, mid) == Some(candidate) {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+        }
+        Some(Confidence {
+            candidate: Some(candidate),
+            candidate_lcb,
+            runner_up_ucb,
+            epsilon: gaussian_tail(f64::from(low)),
+        })
+    }
+
+    fn candidates(&self, level: &LevelVotes) -> Option<Vec<H256>> {
+        let votes_lcb = self.votes_lcb(level, self.quantile_confirm)?;
+        let total_votes_lcb: f32 = votes_lcb.iter().sum();
+        let max_vote_lcb = votes_lcb.iter().cloned().fold(0.0, f32::max);
+        let remaining_votes = f32::from(level.voter_chains) - total_votes_lcb;
+        // a private block could still get the remaining votes and become the leader
+        if max_vote_lcb <= remaining_votes {
+            return None;
+        }
+        // the leader is one of the blocks whose upper confidence bound reaches the best lower
+        // confidence bound
+        Some(
+            level
+                .blocks
+                .iter()
+                .zip(&votes_lcb)
+                .filter(|(_, lcb)| *lcb + remaining_votes >= max_vote_lcb)
+                .map(|((block, _), _)| *block)
+                .collect(),
+        )
+    }
+}
+
+/// Majority of all voter chains, counting only votes buried under enough voter blocks.
+pub struct DepthMajorityRule {
+    depth: u64,
+}
+
+impl ConfirmationRule for DepthMajorityRule {
+    fn leader(&self, level: &LevelVotes, _confirmed: bool) -> Option<H256> {
+        level
+            .blocks
+            .iter()
+            .find(|(_, votes)| {
+                let deep_votes = votes.iter().filter(|v| v.depth >= self.depth).count();
+                deep_votes * 2 > level.voter_chains as usize
+            })
+            .map(|(block, _)| *block)
+    }
+
+    fn reevaluation_window(&self) -> Option<u64> {
+        // votes only get deeper, so a confirmed level stays confirmed until its votes change
+        Some(0)
+    }
+}
+
+/// Bitcoin-style rule that ignores the votes and follows the longest proposer chain.
+pub struct LongestChainRule {
+    depth: u64,
+}
+
+impl ConfirmationRule for LongestChainRule {
+    fn leader(&self, level: &LevelVotes, _confirmed: bool) -> Option<H256> {
+        if level.proposer_depth > self.depth {
+            level.main_chain
+        } else {
+            None
+        }
+    }
+
+    fn reevaluation_window(&self) -> Option<u64> {
+        // a reorg of the proposer tree may replace confirmed levels near the ledger tip
+        Some(self.depth)
+    }
+}
+
+/// Confirm the block with the most votes once the chains that have not voted on this level
+/// could not make any other block overtake it.
+pub struct OptimisticRule;
+
+impl ConfirmationRule for OptimisticRule {
+    fn leader(&self, level: &LevelVotes, _confirmed: bool) -> Option<H256> {
+        let mut counts: Vec<(u64, H256)> = level
+            .blocks
+            .iter()
+            .map(|(block, votes)| (votes.len() as u64, *block))
+            .collect();
+        // most votes first, and in case of a tie, lower hash first
+        counts.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        let (best_votes, best) = *counts.first()?;
+        let runner_up = counts.get(1).map(|c| c.0).unwrap_or(0);
+        let not_voted = u64::from(level.voter_chains) - level.total_votes();
+        // a block we have not seen yet could collect all the remaining votes
+        if best_votes > runner_up + not_voted {
+            Some(best)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::tests::generate_random_hash;
+
+    fn votes(chains: std::ops::Range<u16>, depth: u64) -> Vec<Vote> {
+        chains
+            .map(|chain| Vote {
+                chain,
+                depth,
+                blocks_after: depth,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare_rules() {
+        let config = BlockchainConfig::new(10, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        let (a, b) = (generate_random_hash(), generate_random_hash());
+        let shallow = LevelVotes {
+            level: 1,
+            blocks: vec![(a, votes(0..7, 1)), (b, votes(7..9, 1))],
+            voter_chains: 10,
+            main_chain: Some(b),
+            proposer_depth: 2,
+        };
+        let deep = LevelVotes {
+            blocks: vec![(a, votes(0..7, 50)), (b, votes(7..9, 50))],
+            proposer_depth: 7,
+            ..shallow.clone()
+        };
+        let rule = |s: &str| s.parse::<ConfirmationRuleKind>().unwrap().build(&config);
+
+        let prism = rule("prism");
+        assert_eq!(prism.leader(&shallow, false), None);
+        assert_eq!(prism.leader(&deep, false), Some(a));
+
+        // list decoding narrows the leader down to the blocks that can still win
+        assert_eq!(prism.candidates(&shallow), None);
+        assert_eq!(prism.candidates(&deep), Some(vec![a]));
+        let tied = LevelVotes {
+            blocks: vec![(a, votes(0..5, 50)), (b, votes(5..10, 50))],
+            ..deep.clone()
+        };
+        let mut candidates = prism.candidates(&tied).unwrap();
+        candidates.sort();
+        let mut both = vec![a, b];
+        both.sort();
+        assert_eq!(candidates, both);
+
+        // the candidate of a deep level is safer than the configured threshold
+        let confidence = prism.confidence(&deep).unwrap();
+        assert_eq!(confidence.candidate, Some(a));
+        assert!(confidence.candidate_lcb > confidence.runner_up_ucb);
+        assert!(confidence.epsilon < (-20.0f64).exp());
+        assert!(prism.confidence(&shallow).unwrap().epsilon > 1e-3);
+
+        let majority = rule("majority:6");
+        assert_eq!(majority.leader(&shallow, false), None);
+        assert_eq!(majority.leader(&deep, false), Some(a));
+
+        let longest_chain = rule("longest-chain:6");
+        assert_eq!(longest_chain.leader(&shallow, false), None);
+        assert_eq!(longest_chain.leader(&deep, false), Some(b));
+
+        // 7 votes against at most 2 + 1
+        let optimistic = rule("optimistic");
+        assert_eq!(optimistic.leader(&shallow, false), Some(a));
+        let split = LevelVotes {
+            blocks: vec![(a, votes(0..4, 1)), (b, votes(4..6, 1))],
+            ..shallow.clone()
+        };
+        assert_eq!(optimistic.leader(&split, false), None);
+        // too few votes to tell
+        assert_eq!(prism.confidence(&split).unwrap().epsilon, 1.0);
+
+        assert!("majority".parse::<ConfirmationRuleKind>().is_err());
+        assert!("prism:3".parse::<ConfirmationRuleKind>().is_err());
+        assert_eq!(
+            ConfirmationRuleKind::LongestChain(6).to_string(),
+            "longest-chain:6"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+/// Number of blocks at each level of a voter tree, indexed by a Fenwick tree so that the number
+/// of blocks in a range of levels is found in logarithmic time.
+#[derive(Default)]
+pub struct LevelCounts {
+    counts: Vec<u64>,
+    tree: Vec<u64>,
+}
+
+impl LevelCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a new block at the given level.
+    pub fn add(&mut self, level: u64) {
+        let level = level as usize;
+        if level >= self.counts.len() {
+            self.grow(level + 1);
+        }
+        self.counts[level] += 1;
+        let mut i = level + 1;
+        while i <= self.tree.len() {
+            self.tree[i - 1] += 1;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Number of blocks at the levels from 0 to `level`, inclusive.
+    fn prefix(&self, level: u64) -> u64 {
+        let mut i = std::cmp::min(level as usize + 1, self.tree.len());
+        let mut total = 0;
+        while i > 0 {
+            total += self.tree[i - 1];
+            i -= i & i.wrapping_neg();
+        }
+        total
+    }
+
+    /// Number of blocks at the levels from `start` to `end`, inclusive.
+    pub fn range(&self, start: u64, end: u64) -> u64 {
+        if start > end {
+            return 0;
+        }
+        match start {
+            0 => self.prefix(end),
+            _ => self.prefix(end) - self.prefix(start - 1),
+        }
+    }
+
+    /// Rebuild the tree with room for at least `len` levels, doubling the capacity so that
+    /// rebuilds are amortized over the blocks added.
+    fn grow(&mut self, len: usize) {
+        let capacity = std::cmp::max(len, self.counts.len() * 2);
+        self.counts.resize(capacity, 0);
+        self.tree = self.counts.clone();
+        for i in 1..=capacity {
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= capacity {
+                self.tree[parent - 1] += self.tree[i - 1];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_counts() {
+        let mut counts = LevelCounts::new();
+        let levels = [0, 1, 1, 2, 5, 3, 3, 3, 17, 4, 0];
+        for l in &levels {
+            counts.add(*l);
+        }
+        for start in 0..20 {
+            for end in start..20 {
+                let expected = levels.iter().filter(|l| **l >= start && **l <= end).count() as u64;
+                assert_eq!(counts.range(start, end), expected);
+            }
+        }
+        assert_eq!(counts.range(5, 4), 0);
+    }
+}
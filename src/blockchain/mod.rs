@@ -1,17 +1,22 @@
+pub mod confirmation;
+mod level_count;
+
+use self::confirmation::{Confidence, ConfirmationRule, LevelVotes, Vote};
+use self::level_count::LevelCounts;
 use crate::block::{Block, Content};
 use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
 
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use bincode::{deserialize, serialize};
+use crossbeam::channel;
 use log::{debug, info, warn};
 use rocksdb::{ColumnFamilyDescriptor, Options, WriteBatch, DB};
-use statrs::distribution::{Discrete, Poisson, Univariate};
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use std::ops::Range;
 use std::sync::Mutex;
+use std::{error, fmt};
 
 // Column family names for node/chain metadata
 const PROPOSER_NODE_LEVEL_CF: &str = "PROPOSER_NODE_LEVEL"; // hash to node level (u64)
@@ -34,7 +39,50 @@ const VOTER_PARENT_NEIGHBOR_CF: &str = "GRAPH_VOTER_PARENT_NEIGHBOR"; // the vot
 const TRANSACTION_REF_NEIGHBOR_CF: &str = "GRAPH_TRANSACTION_REF_NEIGHBOR";
 const PROPOSER_REF_NEIGHBOR_CF: &str = "GRAPH_PROPOSER_REF_NEIGHBOR";
 
-pub type Result<T> = std::result::Result<T, rocksdb::Error>;
+pub type Result<T> = std::result::Result<T, BlockChainError>;
+
+#[derive(Debug)]
+pub enum BlockChainError {
+    /// The metadata of the given block was pruned, so that a change of the ledger that needs it
+    /// cannot be followed.
+    Pruned(H256),
+    DBError(rocksdb::Error),
+}
+
+impl fmt::Display for BlockChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockChainError::Pruned(hash) => write!(f, "metadata of block {} is pruned", hash),
+            BlockChainError::DBError(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for BlockChainError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            BlockChainError::DBError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rocksdb::Error> for BlockChainError {
+    fn from(err: rocksdb::Error) -> BlockChainError {
+        BlockChainError::DBError(err)
+    }
+}
+
+/// A change in the fast confirmation of a transaction block, see `BlockChain::fast_confirm`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FastConfirmation {
+    /// The transaction block will enter the ledger whichever candidate becomes the leader of the
+    /// given proposer level.
+    Confirmed(H256, u64),
+    /// A fast confirmed transaction block was left out of the ledger once the level that
+    /// confirmed it was decided, or was removed from the ledger.
+    Revoked(H256),
+}
 
 // cf_handle is a lightweight operation, it takes 44000 micro seconds to get 100000 cf handles
 
@@ -42,11 +90,31 @@ pub struct BlockChain {
     db: DB,
     proposer_best_level: Mutex<u64>,
     voter_best: Vec<Mutex<(H256, u64)>>,
+    /// In-memory index of VOTER_TREE_LEVEL_COUNT_CF for each voter chain.
+    voter_level_counts: Vec<Mutex<LevelCounts>>,
     unreferred_transactions: Mutex<HashSet<H256>>,
     unreferred_proposers: Mutex<HashSet<H256>>,
     unconfirmed_proposers: Mutex<HashSet<H256>>,
+    /// Proposer levels above the final ones that have votes but no leader.
+    undecided_levels: Mutex<BTreeSet<u64>>,
+    /// Transaction blocks confirmed by list decoding that have not entered the ledger yet, and
+    /// the proposer level that confirmed each of them.
+    fast_confirmed_transactions: Mutex<HashMap<H256, u64>>,
+    /// Receivers of fast confirmation events, see `subscribe_fast_confirmations`.
+    fast_confirmation_subscribers: Mutex<Vec<channel::Sender<FastConfirmation>>>,
     proposer_ledger_tip: Mutex<u64>,
     voter_ledger_tips: Mutex<Vec<H256>>,
+    /// The highest proposer level whose vote bookkeeping is pruned.
+    pruned_level: Mutex<u64>,
+    /// For each voter chain, the highest level whose vote bookkeeping is pruned.
+    voter_pruned_levels: Mutex<Vec<u64>>,
+    /// The highest proposer level finalized from outside of consensus, e.g. by importing a UTXO
+    /// snapshot taken at that level.
+    finalized_level: Mutex<u64>,
+    /// Signals that a tip changed and the ledger may need an update. It holds at most one
+    /// pending signal, so that signals raised before the ledger is updated are coalesced.
+    ledger_signal: (channel::Sender<()>, channel::Receiver<()>),
+    confirmation_rule: Box<dyn ConfirmationRule>,
     config: BlockchainConfig,
 }
 
@@ -91,19 +159,30 @@ impl BlockChain {
         opts.create_missing_column_families(true);
         let db = DB::open_cf_descriptors(&opts, path, cfs)?;
         let mut voter_best: Vec<Mutex<(H256, u64)>> = vec![];
+        let mut voter_level_counts: Vec<Mutex<LevelCounts>> = vec![];
         for _ in 0..config.voter_chains {
             voter_best.push(Mutex::new((H256::default(), 0)));
+            voter_level_counts.push(Mutex::new(LevelCounts::new()));
         }
 
         let blockchain_db = Self {
             db,
             proposer_best_level: Mutex::new(0),
             voter_best,
+            voter_level_counts,
             unreferred_transactions: Mutex::new(HashSet::new()),
             unreferred_proposers: Mutex::new(HashSet::new()),
             unconfirmed_proposers: Mutex::new(HashSet::new()),
+            undecided_levels: Mutex::new(BTreeSet::new()),
+            fast_confirmed_transactions: Mutex::new(HashMap::new()),
+            fast_confirmation_subscribers: Mutex::new(vec![]),
             proposer_ledger_tip: Mutex::new(0),
             voter_ledger_tips: Mutex::new(vec![H256::default(); config.voter_chains as usize]),
+            pruned_level: Mutex::new(0),
+            voter_pruned_levels: Mutex::new(vec![0; config.voter_chains as usize]),
+            finalized_level: Mutex::new(0),
+            ledger_signal: channel::bounded(1),
+            confirmation_rule: config.confirmation_rule.build(&config),
             config,
         };
 
@@ -215,6 +294,10 @@ impl BlockChain {
             let mut voter_best = db.voter_best[chain_num as usize].lock().unwrap();
             voter_best.0 = db.config.voter_genesis[chain_num as usize];
             drop(voter_best);
+            db.voter_level_counts[chain_num as usize]
+                .lock()
+                .unwrap()
+                .add(0);
             voter_ledger_tips[chain_num as usize] = db.config.voter_genesis[chain_num as usize];
         }
         drop(voter_ledger_tips);
@@ -319,6 +402,8 @@ impl BlockChain {
                 if self_level > *proposer_best {
                     *proposer_best = self_level;
                     PERFORMANCE_COUNTER.record_update_proposer_main_chain(self_level as usize);
+                    // rules that follow the proposer tree may confirm a level
+                    self.signal_ledger();
                 }
                 drop(proposer_best);
 
@@ -376,6 +461,10 @@ impl BlockChain {
                 );
 
                 self.db.write(wb)?;
+                self.voter_level_counts[self_chain as usize]
+                    .lock()
+                    .unwrap()
+                    .add(self_level);
 
                 // This should happen after writing to db, because other modules will follow
                 // voter_best to query its metadata. We need to get the metadata into database
@@ -389,6 +478,7 @@ impl BlockChain {
                         .record_update_voter_main_chain(voter_best.1 as usize, self_level as usize);
                     voter_best.0 = block_hash;
                     voter_best.1 = self_level;
+                    self.signal_ledger();
                 }
                 drop(voter_best);
                 debug!(
@@ -411,81 +501,167 @@ impl BlockChain {
         Ok(())
     }
 
-    pub fn update_ledger(&self) -> Result<(Vec<H256>, Vec<H256>)> {
+    /// Prune the vote bookkeeping of the proposer levels more than the finality depth below the
+    /// given ledger level, and of the main chain voter blocks that only vote on those levels and
+    /// are as deep in their chain. Return the transaction blocks confirmed at the pruned levels
+    /// that no proposer block above them refers to, so that their bodies can be dropped once the
+    /// given level is reflected in the UTXO set. Proposer and voter blocks and
+    /// the ledger itself are kept, and the leaders of pruned levels become final. This does
+    /// nothing unless pruning is enabled in the config.
+    pub fn prune(&self, reflected_level: u64) -> Result<Vec<H256>> {
         let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
-        let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
-        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+        let proposer_vote_count_cf = self.db.cf_handle(PROPOSER_VOTE_COUNT_CF).unwrap();
+        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
         let proposer_ledger_order_cf = self.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
-        let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
         let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let voter_node_level_cf = self.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
+        let voter_node_voted_level_cf = self.db.cf_handle(VOTER_NODE_VOTED_LEVEL_CF).unwrap();
+        let voter_parent_neighbor_cf = self.db.cf_handle(VOTER_PARENT_NEIGHBOR_CF).unwrap();
+        let vote_neighbor_cf = self.db.cf_handle(VOTE_NEIGHBOR_CF).unwrap();
+        let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
 
         macro_rules! get_value {
             ($cf:expr, $key:expr) => {{
-                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
-                    Some(raw) => Some(deserialize(&raw).unwrap()),
-                    None => None,
-                }
+                deserialize(
+                    &self
+                        .db
+                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
+                        .unwrap(),
+                )
+                .unwrap()
             }};
         }
 
-        // apply the vote diff while tracking the votes of which proposer levels are affected
+        let depth = match self.config.pruning_depth {
+            Some(depth) => depth,
+            None => return Ok(vec![]),
+        };
+        let level = std::cmp::min(reflected_level, self.ledger_tip_level()).saturating_sub(depth);
+        let mut pruned_level = self.pruned_level.lock().unwrap();
+        if level <= *pruned_level {
+            return Ok(vec![]);
+        }
+
         let mut wb = WriteBatch::default();
-        macro_rules! merge_value {
-            ($cf:expr, $key:expr, $value:expr) => {{
-                wb.merge_cf($cf, serialize(&$key).unwrap(), serialize(&$value).unwrap())?;
-            }};
+        let mut transaction_blocks: Vec<H256> = vec![];
+        for l in *pruned_level + 1..=level {
+            let blocks: Vec<H256> = get_value!(proposer_tree_level_cf, l);
+            for block in &blocks {
+                wb.delete_cf(proposer_node_vote_cf, serialize(block).unwrap())?;
+                wb.delete_cf(proposer_vote_count_cf, serialize(block).unwrap())?;
+            }
+            let ledger: Vec<H256> = get_value!(proposer_ledger_order_cf, l);
+            for block in &ledger {
+                let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block);
+                transaction_blocks.extend(t);
+            }
         }
 
-        let mut voter_ledger_tips = self.voter_ledger_tips.lock().unwrap();
-        let mut affected_range: Range<u64> = Range {
-            start: std::u64::MAX,
-            end: std::u64::MIN,
-        };
+        // keep the transaction blocks that proposer blocks above the pruned levels refer to
+        // again, since those may still bring them into the ledger or take them out of it
+        let mut referred: HashSet<H256> = HashSet::new();
+        for l in level + 1..=self.best_proposer_level() {
+            let blocks: Vec<H256> = get_value!(proposer_tree_level_cf, l);
+            for block in &blocks {
+                let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block);
+                referred.extend(t);
+            }
+        }
+        transaction_blocks.retain(|hash| !referred.contains(hash));
+
+        // walk down each voter main chain to the first block that only votes on pruned levels and
+        // is at least the finality depth below the tip, so that the voter chain is not expected
+        // to reorganize below it, and prune from there down to what was pruned before
+        let voter_ledger_tips = self.voter_ledger_tips.lock().unwrap().clone();
+        let mut voter_pruned_levels = self.voter_pruned_levels.lock().unwrap();
+        for (chain_num, tip) in voter_ledger_tips.iter().enumerate() {
+            let mut hash = *tip;
+            let mut voter_level: u64 = get_value!(voter_node_level_cf, hash);
+            let tip_level = voter_level;
+            while voter_level > voter_pruned_levels[chain_num] {
+                let voted_level: u64 = get_value!(voter_node_voted_level_cf, hash);
+                if voted_level <= level && voter_level + depth <= tip_level {
+                    break;
+                }
+                hash = get_value!(voter_parent_neighbor_cf, hash);
+                voter_level -= 1;
+            }
+            let new_pruned_level = voter_level;
+            while voter_level > voter_pruned_levels[chain_num] {
+                wb.delete_cf(vote_neighbor_cf, serialize(&hash).unwrap())?;
+                wb.delete_cf(
+                    voter_tree_level_count_cf,
+                    serialize(&(chain_num as u16, voter_level)).unwrap(),
+                )?;
+                hash = get_value!(voter_parent_neighbor_cf, hash);
+                voter_level -= 1;
+            }
+            voter_pruned_levels[chain_num] = new_pruned_level;
+        }
+        self.db.write(wb)?;
+        debug!("Pruned proposer levels {} to {}", *pruned_level + 1, level);
+        *pruned_level = level;
+        Ok(transaction_blocks)
+    }
 
-        for chain_num in 0..self.config.voter_chains {
-            // get the diff of votes on this voter chain
-            let from = voter_ledger_tips[chain_num as usize];
-            let voter_best = self.voter_best[chain_num as usize].lock().unwrap();
-            let to = voter_best.0;
-            drop(voter_best);
-            voter_ledger_tips[chain_num as usize] = to;
+    /// Make the ledger up to the given proposer level final, so that it is never changed again.
+    pub fn finalize_ledger(&self, level: u64) {
+        let mut finalized_level = self.finalized_level.lock().unwrap();
+        if level > *finalized_level {
+            *finalized_level = level;
+        }
+    }
 
-            let (added, removed) = self.vote_diff(from, to)?;
+    /// Get a receiver that is signaled when the tip of a voter chain or the proposer chain
+    /// changes, i.e. when `update_ledger` may change the ledger.
+    pub fn ledger_signal(&self) -> channel::Receiver<()> {
+        self.ledger_signal.1.clone()
+    }
 
-            // apply the vote diff on the proposer main chain vote cf
-            for vote in &removed {
-                merge_value!(
-                    proposer_node_vote_cf,
-                    vote.0,
-                    (false, chain_num as u16, vote.1)
-                );
-                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0).unwrap();
-                if proposer_level < affected_range.start {
-                    affected_range.start = proposer_level;
-                }
-                if proposer_level >= affected_range.end {
-                    affected_range.end = proposer_level + 1;
-                }
-            }
+    fn signal_ledger(&self) {
+        // a full channel means an update is already pending
+        let _ = self.ledger_signal.0.try_send(());
+    }
 
-            for vote in &added {
-                merge_value!(
-                    proposer_node_vote_cf,
-                    vote.0,
-                    (true, chain_num as u16, vote.1)
-                );
-                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0).unwrap();
-                if proposer_level < affected_range.start {
-                    affected_range.start = proposer_level;
-                }
-                if proposer_level >= affected_range.end {
-                    affected_range.end = proposer_level + 1;
+    /// Get a receiver of the fast confirmation events raised from now on.
+    pub fn subscribe_fast_confirmations(&self) -> channel::Receiver<FastConfirmation> {
+        let (sender, receiver) = channel::unbounded();
+        self.fast_confirmation_subscribers
+            .lock()
+            .unwrap()
+            .push(sender);
+        receiver
+    }
+
+    fn notify_fast_confirmation(&self, event: FastConfirmation) {
+        // forget the subscribers that dropped their receivers
+        self.fast_confirmation_subscribers
+            .lock()
+            .unwrap()
+            .retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    /// Update the ledger with the latest votes. Return the transaction blocks added to the ledger,
+    /// grouped by proposer level, and those removed from it.
+    pub fn update_ledger(&self) -> Result<(Vec<(u64, Vec<H256>)>, Vec<H256>)> {
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+        let proposer_ledger_order_cf = self.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
+        let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
                 }
-            }
+            }};
         }
-        drop(voter_ledger_tips);
-        // commit the votes into the database
-        self.db.write(wb)?;
+
+        // take a snapshot of the voter tips so that the whole round sees the same votes
+        let voter_tips = self.voter_tips();
+        let levels = self.apply_vote_diff(&voter_tips)?;
+        let mut undecided_levels = self.undecided_levels.lock().unwrap();
 
         // recompute the leader of each level that was affected
         let mut wb = WriteBatch::default();
@@ -507,35 +683,40 @@ impl BlockChain {
             }};
         }
 
-        // we will recompute the leader starting from min. affected level or ledger tip + 1,
-        // whichever is smaller. so make min. affected level the smaller of the two
-        if affected_range.start < affected_range.end {
-            let proposer_ledger_tip_lock = self.proposer_ledger_tip.lock().unwrap();
-            let proposer_ledger_tip: u64 = *proposer_ledger_tip_lock;
-            drop(proposer_ledger_tip_lock);
-            if proposer_ledger_tip + 1 < affected_range.start {
-                affected_range.start = proposer_ledger_tip + 1;
-            }
-        }
-
         // start actually recomputing the leaders
         let mut change_begin: Option<u64> = None;
+        let main_chain = match levels.iter().next() {
+            Some(&first) => self.proposer_main_chain(first)?,
+            None => vec![],
+        };
+        let voter_best_levels: Vec<u64> = voter_tips.iter().map(|t| t.1).collect();
 
-        for level in affected_range {
+        for level in levels {
             let existing_leader: Option<H256> =
                 get_value!(proposer_leader_sequence_cf, level as u64);
-            let new_leader_confirm: Option<H256> = self.proposer_leader(level as u64, self.config.quantile_epsilon_confirm)?;
-            let new_leader_deconfirm: Option<H256> = self.proposer_leader(level as u64, self.config.quantile_epsilon_deconfirm)?;
-
-            // we confirm with a higher confidence so we don't have false deconfirmation
-            let new_leader = {
-                if existing_leader.is_some() {
-                    new_leader_deconfirm
+            let (new_leader, voted) = match self.config.checkpoints.get(&level) {
+                // a checkpoint is the leader of its level as soon as we have the block
+                Some(checkpoint) => {
+                    if self.contains_proposer(checkpoint)? {
+                        (Some(*checkpoint), true)
+                    } else {
+                        (None, true)
+                    }
                 }
-                else {
-                    new_leader_confirm
+                None => {
+                    let votes = self.level_votes(level, &main_chain, &voter_best_levels)?;
+                    let voted = votes.blocks.iter().any(|(_, v)| !v.is_empty());
+                    let leader = self
+                        .confirmation_rule
+                        .leader(&votes, existing_leader.is_some());
+                    (leader, voted)
                 }
             };
+            if new_leader.is_none() && voted {
+                undecided_levels.insert(level);
+            } else {
+                undecided_levels.remove(&level);
+            }
 
             if new_leader != existing_leader {
                 match new_leader {
@@ -555,6 +736,7 @@ impl BlockChain {
                 };
             }
         }
+        drop(undecided_levels);
         // commit the new leaders into the database
         self.db.write(wb)?;
 
@@ -563,7 +745,7 @@ impl BlockChain {
             let mut proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
             let mut unconfirmed_proposers = self.unconfirmed_proposers.lock().unwrap();
             let mut removed: Vec<H256> = vec![];
-            let mut added: Vec<H256> = vec![];
+            let mut added: Vec<(u64, Vec<H256>)> = vec![];
             let mut wb = WriteBatch::default();
             /*
             macro_rules! merge_value {
@@ -633,21 +815,51 @@ impl BlockChain {
                         .filter(|h| unconfirmed_proposers.remove(h))
                         .collect();
                     put_value!(proposer_ledger_order_cf, level as u64, order);
-                    added.extend(&order);
+                    added.push((level, order));
                 }
             }
             // commit the new ledger into the database
             self.db.write(wb)?;
 
             let mut removed_transaction_blocks: Vec<H256> = vec![];
-            let mut added_transaction_blocks: Vec<H256> = vec![];
+            let mut added_transaction_blocks: Vec<(u64, Vec<H256>)> = vec![];
             for block in &removed {
                 let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
                 removed_transaction_blocks.extend(&t);
             }
-            for block in &added {
-                let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
-                added_transaction_blocks.extend(&t);
+            for (level, blocks) in &added {
+                let mut level_transaction_blocks: Vec<H256> = vec![];
+                for block in blocks {
+                    let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block).unwrap();
+                    level_transaction_blocks.extend(&t);
+                }
+                added_transaction_blocks.push((*level, level_transaction_blocks));
+            }
+            // blocks in the ledger no longer need to be tracked by fast confirmation, while blocks
+            // removed from the ledger or missing from the ledger once the level that confirmed
+            // them is decided lose their fast confirmation
+            let in_ledger: HashSet<H256> = added_transaction_blocks
+                .iter()
+                .flat_map(|(_, blocks)| blocks.iter().cloned())
+                .collect();
+            let removed_blocks: HashSet<H256> =
+                removed_transaction_blocks.iter().cloned().collect();
+            let ledger_tip = *proposer_ledger_tip;
+            let mut revoked: Vec<H256> = vec![];
+            let mut fast_confirmed = self.fast_confirmed_transactions.lock().unwrap();
+            fast_confirmed.retain(|block, level| {
+                if in_ledger.contains(block) {
+                    false
+                } else if removed_blocks.contains(block) || *level <= ledger_tip {
+                    revoked.push(*block);
+                    false
+                } else {
+                    true
+                }
+            });
+            drop(fast_confirmed);
+            for block in revoked {
+                self.notify_fast_confirmation(FastConfirmation::Revoked(block));
             }
             Ok((added_transaction_blocks, removed_transaction_blocks))
         } else {
@@ -655,170 +867,352 @@ impl BlockChain {
         }
     }
 
-    fn proposer_leader(&self, level: u64, quantile: f32) -> Result<Option<H256>> {
+    /// Apply the votes cast or reverted since the last ledger update, up to the given voter tips.
+    /// Return the proposer levels whose leader has to be recomputed: the levels whose votes
+    /// changed, the undecided levels whose votes got deeper, and the window of levels that the
+    /// confirmation rule re-evaluates. Final levels are left out.
+    fn apply_vote_diff(&self, voter_tips: &[(H256, u64)]) -> Result<BTreeSet<u64>> {
         let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
-        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
+        let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
 
         macro_rules! get_value {
             ($cf:expr, $key:expr) => {{
-                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
-                    Some(raw) => Some(deserialize(&raw).unwrap()),
-                    None => None,
-                }
+                deserialize(
+                    &self
+                        .db
+                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
+                        .unwrap(),
+                )
+                .unwrap()
             }};
         }
-        let proposer_blocks: Vec<H256> = get_value!(proposer_tree_level_cf, level as u64).unwrap();
-        // compute the new leader of this level
-        // we use the confirmation policy from https://arxiv.org/abs/1810.08092
-        let mut new_leader: Option<H256> = None;
 
-        // collect the depth of each vote on each proposer block
-        let mut votes_depth: HashMap<&H256, Vec<u64>> = HashMap::new(); // chain number and vote depth casted on the proposer block
+        // apply the vote diff while tracking the votes of which proposer levels are affected
+        let mut wb = WriteBatch::default();
+        macro_rules! merge_value {
+            ($cf:expr, $key:expr, $value:expr) => {{
+                wb.merge_cf($cf, serialize(&$key).unwrap(), serialize(&$value).unwrap())?;
+            }};
+        }
 
-        // collect the total votes on all proposer blocks, and the number of
-        // voter blocks mined after those votes are casted
-        let mut total_vote_count: u16 = 0;
-        let mut total_vote_blocks: u64 = 0;
+        let mut voter_ledger_tips = self.voter_ledger_tips.lock().unwrap();
+        // only move the tips once all the diffs are found and written, so that a voter chain
+        // reorganizing into pruned blocks leaves the votes untouched
+        let mut new_voter_ledger_tips = voter_ledger_tips.clone();
+        let mut affected_levels: BTreeSet<u64> = BTreeSet::new();
+        let mut tips_moved = false;
 
-        for block in &proposer_blocks {
-            let votes: Vec<(u16, u64)> = match get_value!(proposer_node_vote_cf, block) {
-                None => vec![],
-                Some(d) => d,
-            };
-            let mut vote_depth: Vec<u64> = vec![];
-            for (chain_num, vote_level) in &votes {
-                // TODO: cache the voter chain best levels
-                let voter_best = self.voter_best[*chain_num as usize].lock().unwrap();
-                let voter_best_level = voter_best.1;
-                drop(voter_best);
-                total_vote_blocks += self
-                    .num_voter_blocks(*chain_num, *vote_level, voter_best_level)
-                    .unwrap();
-                total_vote_count += 1;
-                let this_depth = voter_best_level - vote_level + 1;
-                vote_depth.push(this_depth);
+        for chain_num in 0..self.config.voter_chains {
+            // get the diff of votes on this voter chain
+            let from = voter_ledger_tips[chain_num as usize];
+            let to = voter_tips[chain_num as usize].0;
+            if from == to {
+                continue;
+            }
+            tips_moved = true;
+            new_voter_ledger_tips[chain_num as usize] = to;
+
+            let (added, removed) = self.vote_diff(from, to)?;
+
+            // apply the vote diff on the proposer main chain vote cf
+            for vote in &removed {
+                merge_value!(
+                    proposer_node_vote_cf,
+                    vote.0,
+                    (false, chain_num as u16, vote.1)
+                );
+                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0);
+                affected_levels.insert(proposer_level);
+            }
+
+            for vote in &added {
+                merge_value!(
+                    proposer_node_vote_cf,
+                    vote.0,
+                    (true, chain_num as u16, vote.1)
+                );
+                let proposer_level: u64 = get_value!(proposer_node_level_cf, vote.0);
+                affected_levels.insert(proposer_level);
             }
-            votes_depth.insert(block, vote_depth);
         }
+        // commit the votes into the database
+        self.db.write(wb)?;
+        *voter_ledger_tips = new_voter_ledger_tips;
+        drop(voter_ledger_tips);
 
-        // For debugging purpose only. This is very important for security.
-        // TODO: remove this check in the future
-        if self.config.voter_chains < total_vote_count {
-            panic!(
-                "self.config.voter_chains: {} total_votes:{}",
-                self.config.voter_chains, total_vote_count
-            )
+        // the levels whose votes changed are dirty. once a voter chain grows, the votes it cast
+        // on the undecided levels are buried deeper, which may decide those levels too. decided
+        // levels whose votes did not change keep their leader
+        let mut levels = affected_levels;
+        if tips_moved {
+            levels.extend(self.undecided_levels.lock().unwrap().iter());
+        }
+        let proposer_ledger_tip_lock = self.proposer_ledger_tip.lock().unwrap();
+        let proposer_ledger_tip: u64 = *proposer_ledger_tip_lock;
+        drop(proposer_ledger_tip_lock);
+        // some rules may change their decision without any change of votes, so they look at
+        // every unconfirmed level and a window below the ledger tip
+        if let Some(window) = self.confirmation_rule.reevaluation_window() {
+            let start = (proposer_ledger_tip + 1).saturating_sub(window).max(1);
+            levels.extend(start..=self.best_proposer_level());
+        }
+        // the leaders of pruned or finalized levels, and of the levels up to the newest checkpoint
+        // that the ledger has reached, are final
+        let finalized_level = std::cmp::max(
+            *self.pruned_level.lock().unwrap(),
+            *self.finalized_level.lock().unwrap(),
+        );
+        let final_level = match self
+            .config
+            .checkpoints
+            .range(..=proposer_ledger_tip)
+            .next_back()
+        {
+            Some((&checkpoint_level, _)) => std::cmp::max(finalized_level, checkpoint_level),
+            None => finalized_level,
+        };
+        let mut undecided_levels = self.undecided_levels.lock().unwrap();
+        *undecided_levels = undecided_levels.split_off(&(final_level + 1));
+        Ok(levels.split_off(&(final_level + 1)))
+    }
+
+    /// Find the transaction blocks that will enter the ledger whichever candidate becomes the
+    /// leader of a still undecided proposer level, and return those not returned before. Each of
+    /// them is also sent to the subscribers of fast confirmation events. This is the list decoding
+    /// fast confirmation from https://arxiv.org/abs/1810.08092.
+    pub fn fast_confirm(&self) -> Result<Vec<H256>> {
+        let proposer_ref_neighbor_cf = self.db.cf_handle(PROPOSER_REF_NEIGHBOR_CF).unwrap();
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                deserialize(
+                    &self
+                        .db
+                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
+                        .unwrap(),
+                )
+                .unwrap()
+            }};
         }
 
-        // no point in going further if less than 3/5 votes are cast
-        if total_vote_count > self.config.voter_chains * 3 / 5 {
-            // calculate the average number of voter blocks mined after
-            // a vote is casted. we use this as an estimator of honest mining
-            // rate, and then derive the believed malicious mining rate
-            let avg_vote_blocks = total_vote_blocks as f32 / f32::from(total_vote_count);
-            // expected voter depth of an adversary
-            let adversary_expected_vote_depth =
-                avg_vote_blocks / (1.0 - self.config.adversary_ratio) * self.config.adversary_ratio;
-            let poisson = Poisson::new(f64::from(adversary_expected_vote_depth)).unwrap();
-
-            // for each block calculate the lower bound on the number of votes
-            let mut votes_lcb: HashMap<&H256, f32> = HashMap::new();
-            let mut total_votes_lcb: f32 = 0.0;
-            let mut max_vote_lcb: f32 = 0.0;
-
-            for block in &proposer_blocks {
-                let votes = votes_depth.get(block).unwrap();
-
-                let mut block_votes_mean: f32 = 0.0; // mean E[X]
-                let mut block_votes_variance: f32 = 0.0; // Var[X]
-                let mut block_votes_lcb: f32 = 0.0;
-                for depth in votes.iter() {
-                    // probability that the adversary will remove this vote
-                    let mut p: f32 = 1.0 - poisson.cdf((*depth as f32 + 1.0).into()) as f32;
-                    for k in 0..(*depth as u64) {
-                        // probability that the adversary has mined k blocks
-                        let p1 = poisson.pmf(k) as f32;
-                        // probability that the adversary will overtake 'depth-k' blocks
-                        let p2 = (self.config.adversary_ratio
-                            / (1.0 - self.config.adversary_ratio))
-                            .powi((depth - k + 1) as i32);
-                        p += p1 * p2;
+        let ledger_tip = self.ledger_tip_level();
+        let best_level = self.best_proposer_level();
+        if ledger_tip >= best_level {
+            return Ok(vec![]);
+        }
+        let main_chain = self.proposer_main_chain(ledger_tip + 1)?;
+        let unconfirmed_proposers = self.unconfirmed_proposers.lock().unwrap().clone();
+        let mut confirmed: Vec<H256> = vec![];
+
+        let voter_best_levels: Vec<u64> = self.voter_tips().iter().map(|t| t.1).collect();
+
+        for level in ledger_tip + 1..=best_level {
+            let votes = self.level_votes(level, &main_chain, &voter_best_levels)?;
+            let candidates = match self.confirmation_rule.candidates(&votes) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+            // intersect the transaction blocks that each candidate would bring into the ledger,
+            // by referring to them directly or through other unconfirmed proposer blocks
+            let mut common: Option<HashSet<H256>> = None;
+            for candidate in candidates {
+                let mut refs: HashSet<H256> = HashSet::new();
+                let mut visited: HashSet<H256> = HashSet::new();
+                let mut stack: Vec<H256> = vec![candidate];
+                while let Some(top) = stack.pop() {
+                    if !unconfirmed_proposers.contains(&top) || !visited.insert(top) {
+                        continue;
                     }
-                    block_votes_mean += 1.0 - p;
-                    block_votes_variance += p * (1.0 - p);
+                    let t: Vec<H256> = get_value!(transaction_ref_neighbor_cf, top);
+                    refs.extend(t);
+                    let p: Vec<H256> = get_value!(proposer_ref_neighbor_cf, top);
+                    stack.extend(p);
                 }
-                // using gaussian approximation
-                let tmp = block_votes_mean
-                    - (block_votes_variance).sqrt() * quantile;
-                if tmp > 0.0 {
-                    block_votes_lcb += tmp;
+                common = match common {
+                    None => Some(refs),
+                    Some(c) => Some(c.intersection(&refs).cloned().collect()),
+                };
+            }
+            if let Some(common) = common {
+                let mut fast_confirmed = self.fast_confirmed_transactions.lock().unwrap();
+                for block in common {
+                    if !fast_confirmed.contains_key(&block) {
+                        fast_confirmed.insert(block, level);
+                        confirmed.push(block);
+                        self.notify_fast_confirmation(FastConfirmation::Confirmed(block, level));
+                    }
                 }
-                votes_lcb.insert(block, block_votes_lcb);
-                total_votes_lcb += block_votes_lcb;
+            }
+        }
+        Ok(confirmed)
+    }
 
-                if max_vote_lcb < block_votes_lcb {
-                    max_vote_lcb = block_votes_lcb;
-                    new_leader = Some(*block);
-                }
-                // In case of a tie, choose block with lower hash.
-                if (max_vote_lcb - block_votes_lcb).abs() < std::f32::EPSILON
-                    && new_leader.is_some()
-                {
-                    // TODO: is_some required?
-                    if *block < new_leader.unwrap() {
-                        new_leader = Some(*block);
-                    }
+    /// Get the confidence in the leader candidate of the given proposer level, or `None` if the
+    /// confirmation rule has no probabilistic model.
+    pub fn level_confidence(&self, level: u64) -> Result<Option<Confidence>> {
+        let voter_best_levels: Vec<u64> = self.voter_tips().iter().map(|t| t.1).collect();
+        // look the level up directly instead of walking the proposer main chain down to it. the
+        // confidence only depends on the votes, so the main chain block is only filled in when
+        // the level has a single block, which must then be on the main chain
+        let mut votes = self.level_votes(level, &[], &voter_best_levels)?;
+        let best_level = self.best_proposer_level();
+        if level <= best_level {
+            votes.proposer_depth = best_level - level + 1;
+            if votes.blocks.len() == 1 {
+                votes.main_chain = Some(votes.blocks[0].0);
+            }
+        }
+        Ok(self.confirmation_rule.confidence(&votes))
+    }
+
+    /// Find the lowest proposer level, among the given number of levels below the best proposer
+    /// block, where a block refers to the given transaction block. Return that level, whether the
+    /// leader candidate of the level is one of the referring blocks, and the confidence in the
+    /// candidate.
+    pub fn transaction_block_confidence(
+        &self,
+        hash: &H256,
+        depth: u64,
+    ) -> Result<Option<(u64, bool, Option<Confidence>)>> {
+        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                deserialize(
+                    &self
+                        .db
+                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
+                        .unwrap(),
+                )
+                .unwrap()
+            }};
+        }
+
+        let best_level = self.best_proposer_level();
+        let mut found: Option<(u64, Vec<H256>)> = None;
+        for level in (best_level.saturating_sub(depth)..=best_level).rev() {
+            let blocks: Vec<H256> = get_value!(proposer_tree_level_cf, level);
+            let mut referring: Vec<H256> = vec![];
+            for block in blocks {
+                let refs: Vec<H256> = get_value!(transaction_ref_neighbor_cf, block);
+                if refs.contains(hash) {
+                    referring.push(block);
                 }
             }
-            // check if the lcb_vote of new_leader is bigger than second best ucb votes
-            let remaining_votes = f32::from(self.config.voter_chains) - total_votes_lcb;
+            if !referring.is_empty() {
+                found = Some((level, referring));
+            }
+        }
+        let (level, referring) = match found {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let confidence = self.level_confidence(level)?;
+        let referred_by_candidate = match &confidence {
+            Some(Confidence {
+                candidate: Some(c), ..
+            }) => referring.contains(c),
+            _ => false,
+        };
+        Ok(Some((level, referred_by_candidate, confidence)))
+    }
 
-            // if max_vote_lcb is lesser than the remaining_votes, then a private block could
-            // get the remaining votes and become the leader block
-            if max_vote_lcb <= remaining_votes || new_leader.is_none() {
-                new_leader = None;
-            } else {
-                for p_block in &proposer_blocks {
-                    // if the below condition is true, then final votes on p_block could overtake new_leader
-                    if max_vote_lcb < votes_lcb.get(p_block).unwrap() + remaining_votes
-                        && *p_block != new_leader.unwrap()
-                    {
-                        new_leader = None;
-                        break;
-                    }
-                    //In case of a tie, choose block with lower hash.
-                    if (max_vote_lcb - (votes_lcb.get(p_block).unwrap() + remaining_votes)).abs()
-                        < std::f32::EPSILON
-                        && *p_block < new_leader.unwrap()
-                    {
-                        new_leader = None;
-                        break;
-                    }
+    /// Collect the votes on the proposer blocks of the given level for the confirmation rule.
+    /// `main_chain` holds the blocks on the longest proposer chain, starting from its genesis
+    /// block or from some level up to its tip.
+    fn level_votes(
+        &self,
+        level: u64,
+        main_chain: &[(u64, H256)],
+        voter_best_levels: &[u64],
+    ) -> Result<LevelVotes> {
+        let proposer_node_vote_cf = self.db.cf_handle(PROPOSER_NODE_VOTE_CF).unwrap();
+        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
+
+        macro_rules! get_value {
+            ($cf:expr, $key:expr) => {{
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => Some(deserialize(&raw).unwrap()),
+                    None => None,
                 }
+            }};
+        }
+        let proposer_blocks: Vec<H256> = match get_value!(proposer_tree_level_cf, level as u64) {
+            None => vec![],
+            Some(d) => d,
+        };
+
+        let mut blocks: Vec<(H256, Vec<Vote>)> = vec![];
+        for block in proposer_blocks {
+            let votes: Vec<(u16, u64)> = match get_value!(proposer_node_vote_cf, block) {
+                None => vec![],
+                Some(d) => d,
+            };
+            let mut block_votes: Vec<Vote> = vec![];
+            for (chain_num, vote_level) in votes {
+                let voter_best_level = voter_best_levels[chain_num as usize];
+                block_votes.push(Vote {
+                    chain: chain_num,
+                    depth: voter_best_level - vote_level + 1,
+                    blocks_after: self.num_voter_blocks(chain_num, vote_level, voter_best_level),
+                });
             }
+            blocks.push((block, block_votes));
         }
 
-        Ok(new_leader)
+        let (main_chain, proposer_depth) = match main_chain.first() {
+            Some((first_level, _)) if *first_level <= level => {
+                let tip_level = first_level + main_chain.len() as u64 - 1;
+                main_chain
+                    .get((level - first_level) as usize)
+                    .map(|(_, hash)| (Some(*hash), tip_level - level + 1))
+                    .unwrap_or((None, 0))
+            }
+            _ => (None, 0),
+        };
+        Ok(LevelVotes {
+            level,
+            blocks,
+            voter_chains: self.config.voter_chains,
+            main_chain,
+            proposer_depth,
+        })
     }
 
-    fn num_voter_blocks(&self, chain: u16, start_level: u64, end_level: u64) -> Result<u64> {
-        let voter_tree_level_count_cf = self.db.cf_handle(VOTER_TREE_LEVEL_COUNT_CF).unwrap();
-        let mut total: u64 = 0;
-        for l in start_level..=end_level {
-            let t: u64 = deserialize(
+    /// Get the blocks on the longest proposer chain from the given level up to its tip, with
+    /// their levels, in increasing order of level.
+    fn proposer_main_chain(&self, from_level: u64) -> Result<Vec<(u64, H256)>> {
+        let parent_neighbor_cf = self.db.cf_handle(PARENT_NEIGHBOR_CF).unwrap();
+        let mut level = self.best_proposer_level();
+        let mut hash = self.best_proposer()?;
+        let mut chain: Vec<(u64, H256)> = vec![];
+        while level >= from_level {
+            chain.push((level, hash));
+            if level == 0 {
+                break;
+            }
+            hash = deserialize(
                 &self
                     .db
-                    .get_pinned_cf(voter_tree_level_count_cf, serialize(&(chain, l)).unwrap())?
+                    .get_pinned_cf(parent_neighbor_cf, serialize(&hash).unwrap())?
                     .unwrap(),
             )
             .unwrap();
-            total += t;
+            level -= 1;
         }
-        Ok(total)
+        chain.reverse();
+        Ok(chain)
+    }
+
+    fn num_voter_blocks(&self, chain: u16, start_level: u64, end_level: u64) -> u64 {
+        let counts = self.voter_level_counts[chain as usize].lock().unwrap();
+        counts.range(start_level, end_level)
     }
 
     /// Given two voter blocks on the same chain, calculate the added and removed votes when
-    /// switching the main chain.
+    /// switching the main chain. Fail if the switch goes through pruned voter blocks.
     fn vote_diff(&self, from: H256, to: H256) -> Result<(Vec<(H256, u64)>, Vec<(H256, u64)>)> {
         // get cf handles
         let voter_node_level_cf = self.db.cf_handle(VOTER_NODE_LEVEL_CF).unwrap();
@@ -827,13 +1221,11 @@ impl BlockChain {
 
         macro_rules! get_value {
             ($cf:expr, $key:expr) => {{
-                deserialize(
-                    &self
-                        .db
-                        .get_pinned_cf($cf, serialize(&$key).unwrap())?
-                        .unwrap(),
-                )
-                .unwrap()
+                match self.db.get_pinned_cf($cf, serialize(&$key).unwrap())? {
+                    Some(raw) => deserialize(&raw).unwrap(),
+                    // the votes of pruned voter blocks are gone
+                    None => return Err(BlockChainError::Pruned($key)),
+                }
             }};
         }
 
@@ -906,26 +1298,74 @@ impl BlockChain {
         hash
     }
 
+    pub fn best_proposer_level(&self) -> u64 {
+        let proposer_best = self.proposer_best_level.lock().unwrap();
+        *proposer_best
+    }
+
+    /// Get the best block and its level of each voter chain.
+    pub fn voter_tips(&self) -> Vec<(H256, u64)> {
+        self.voter_best
+            .iter()
+            .map(|voter_best| *voter_best.lock().unwrap())
+            .collect()
+    }
+
+    /// Get the checkpoint pinned at the given proposer level, if any.
+    pub fn checkpoint(&self, level: u64) -> Option<H256> {
+        self.config.checkpoints.get(&level).cloned()
+    }
+
+    /// Get the newest checkpoint pinned at or below the given proposer level, with its level.
+    pub fn last_checkpoint(&self, level: u64) -> Option<(u64, H256)> {
+        self.config
+            .checkpoints
+            .range(..=level)
+            .next_back()
+            .map(|(level, hash)| (*level, *hash))
+    }
+
+    /// Get the level of the last proposer block confirmed into the ledger.
+    pub fn ledger_tip_level(&self) -> u64 {
+        let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
+        *proposer_ledger_tip
+    }
+
     pub fn unreferred_proposers(&self) -> Vec<H256> {
         // TODO: does ordering matter?
         // TODO: should remove the parent block when mining
         let unreferred_proposers = self.unreferred_proposers.lock().unwrap();
-        let list: Vec<H256> = unreferred_proposers.iter().cloned().collect();
+        let mut list: Vec<H256> = unreferred_proposers.iter().cloned().collect();
         drop(unreferred_proposers);
+        // sort the list, so that the blocks mined from it do not depend on the hash set order
+        list.sort_unstable();
         list
     }
 
     pub fn unreferred_transactions(&self) -> Vec<H256> {
         // TODO: does ordering matter?
         let unreferred_transactions = self.unreferred_transactions.lock().unwrap();
-        let list: Vec<H256> = unreferred_transactions.iter().cloned().collect();
+        let mut list: Vec<H256> = unreferred_transactions.iter().cloned().collect();
         drop(unreferred_transactions);
+        // sort the list, so that the blocks mined from it do not depend on the hash set order
+        list.sort_unstable();
         list
     }
 
     /// Get the list of unvoted proposer blocks that a voter chain should vote for, given the tip
     /// of the particular voter chain.
     pub fn unvoted_proposer(&self, tip: &H256, proposer_parent: &H256) -> Result<Vec<H256>> {
+        self.choose_votes(tip, proposer_parent, false)
+    }
+
+    /// Get the list of unvoted proposer blocks that a voter chain should vote for to keep competing
+    /// proposer blocks balanced, given the tip of the voter chain. On each level, it votes for the
+    /// block with the fewest votes.
+    pub fn balancing_votes(&self, tip: &H256, proposer_parent: &H256) -> Result<Vec<H256>> {
+        self.choose_votes(tip, proposer_parent, true)
+    }
+
+    fn choose_votes(&self, tip: &H256, proposer_parent: &H256, fewest: bool) -> Result<Vec<H256>> {
         let voter_node_voted_level_cf = self.db.cf_handle(VOTER_NODE_VOTED_LEVEL_CF).unwrap();
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
         let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
@@ -947,7 +1387,7 @@ impl BlockChain {
         )
         .unwrap();
 
-        // get the block with the most votes on each proposer level
+        // get the block with the most (or the fewest) votes on each proposer level
         // and break ties with hash value
         let mut list: Vec<H256> = vec![];
         for level in first_vote_level + 1..=last_vote_level {
@@ -971,7 +1411,8 @@ impl BlockChain {
                 };
                 match best_vote {
                     Some((_, num_votes)) => {
-                        if vote_count > num_votes {
+                        if (!fewest && vote_count > num_votes) || (fewest && vote_count < num_votes)
+                        {
                             best_vote = Some((*block_hash, vote_count));
                         }
                     }
@@ -985,6 +1426,18 @@ impl BlockChain {
         Ok(list)
     }
 
+    /// Get the proposer blocks at the given level.
+    pub fn proposers_at_level(&self, level: u64) -> Result<Vec<H256>> {
+        let proposer_tree_level_cf = self.db.cf_handle(PROPOSER_TREE_LEVEL_CF).unwrap();
+        match self
+            .db
+            .get_pinned_cf(proposer_tree_level_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => Ok(deserialize(&d).unwrap()),
+            None => Ok(vec![]),
+        }
+    }
+
     /// Get the level of the proposer block
     pub fn proposer_level(&self, hash: &H256) -> Result<u64> {
         let proposer_node_level_cf = self.db.cf_handle(PROPOSER_NODE_LEVEL_CF).unwrap();
@@ -998,6 +1451,19 @@ impl BlockChain {
         Ok(level)
     }
 
+    /// Get the proposer parent of the proposer block
+    pub fn proposer_parent(&self, hash: &H256) -> Result<H256> {
+        let parent_neighbor_cf = self.db.cf_handle(PARENT_NEIGHBOR_CF).unwrap();
+        let parent: H256 = deserialize(
+            &self
+                .db
+                .get_pinned_cf(parent_neighbor_cf, serialize(&hash).unwrap())?
+                .unwrap(),
+        )
+        .unwrap();
+        Ok(parent)
+    }
+
     /// Get the deepest voted level of a voter
     pub fn deepest_voted_level(&self, voter: &H256) -> Result<u64> {
         let voter_node_voted_level_cf = self.db.cf_handle(VOTER_NODE_VOTED_LEVEL_CF).unwrap();
@@ -1062,6 +1528,48 @@ impl BlockChain {
         }
     }
 
+    /// Get the leader of the given proposer level, if there is one.
+    pub fn proposer_leader(&self, level: u64) -> Result<Option<H256>> {
+        let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
+        match self
+            .db
+            .get_pinned_cf(proposer_leader_sequence_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => Ok(Some(deserialize(&d).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the proposer blocks confirmed by the leader of the given proposer level, in ledger
+    /// order.
+    pub fn ledger_proposers(&self, level: u64) -> Result<Vec<H256>> {
+        let proposer_ledger_order_cf = self.db.cf_handle(PROPOSER_LEDGER_ORDER_CF).unwrap();
+        match self
+            .db
+            .get_pinned_cf(proposer_ledger_order_cf, serialize(&level).unwrap())?
+        {
+            Some(d) => Ok(deserialize(&d).unwrap()),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Get the transaction blocks that entered the ledger at the given proposer level, in ledger
+    /// order.
+    pub fn ledger_transaction_blocks(&self, level: u64) -> Result<Vec<H256>> {
+        let transaction_ref_neighbor_cf = self.db.cf_handle(TRANSACTION_REF_NEIGHBOR_CF).unwrap();
+        let proposers = self.ledger_proposers(level)?;
+        let mut transaction_blocks: Vec<H256> = vec![];
+        for proposer in &proposers {
+            let d = self
+                .db
+                .get_pinned_cf(transaction_ref_neighbor_cf, serialize(proposer).unwrap())?
+                .unwrap();
+            let t: Vec<H256> = deserialize(&d).unwrap();
+            transaction_blocks.extend(t);
+        }
+        Ok(transaction_blocks)
+    }
+
     pub fn proposer_leaders(&self) -> Result<Vec<H256>> {
         let proposer_leader_sequence_cf = self.db.cf_handle(PROPOSER_LEADER_SEQUENCE_CF).unwrap();
         let proposer_ledger_tip = self.proposer_ledger_tip.lock().unwrap();
@@ -1655,6 +2163,199 @@ fn u64_plus_merge(
     Some(result)
 }
 
+#[cfg(test)]
+pub mod tests {
+    use super::confirmation::ConfirmationRuleKind;
+    use super::*;
+    use crate::block::tests::{proposer_block, voter_block};
+    use crate::crypto::hash::tests::generate_random_hash;
+
+    /// Create an empty blockchain with the given number of voter chains, where a proposer block
+    /// becomes the leader of its level as soon as no other block can outvote it.
+    pub fn new_blockchain(name: &str, voter_chains: u16) -> BlockChain {
+        let mut config = BlockchainConfig::new(voter_chains, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        BlockChain::new(
+            format!("/tmp/prism_test_blockchain_{}.rocksdb", name),
+            config,
+        )
+        .unwrap()
+    }
+
+    /// Insert a proposer block and return its hash.
+    pub fn propose(
+        chain: &BlockChain,
+        parent: H256,
+        proposer_refs: Vec<H256>,
+        transaction_refs: Vec<H256>,
+    ) -> H256 {
+        let block = proposer_block(parent, 0, proposer_refs, transaction_refs);
+        chain.insert_block(&block).unwrap();
+        block.hash()
+    }
+
+    /// Insert a voter block mined on the last proposer block it votes for, or on the best one if
+    /// it votes for none, and return its hash.
+    pub fn vote(chain: &BlockChain, chain_num: u16, voter_parent: H256, votes: Vec<H256>) -> H256 {
+        let parent = match votes.last() {
+            Some(last) => *last,
+            None => chain.best_proposer().unwrap(),
+        };
+        let block = voter_block(parent, 0, chain_num, voter_parent, votes);
+        chain.insert_block(&block).unwrap();
+        block.hash()
+    }
+
+    #[test]
+    fn prune_then_reorganize() {
+        let mut config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        config.pruning_depth = Some(1);
+        let chain = BlockChain::new(
+            "/tmp/prism_test_blockchain_prune_then_reorganize.rocksdb",
+            config,
+        )
+        .unwrap();
+        let genesis = chain.config.proposer_genesis;
+        let voter_genesis = chain.config.voter_genesis[0];
+        let (t1, t2) = (generate_random_hash(), generate_random_hash());
+
+        // the transaction block of level 1 is referred to again on level 4
+        let p1 = propose(&chain, genesis, vec![], vec![t1]);
+        let p2 = propose(&chain, p1, vec![], vec![t2]);
+        let p3 = propose(&chain, p2, vec![], vec![]);
+        let p4 = propose(&chain, p3, vec![], vec![t1]);
+        let mut voter_tip = voter_genesis;
+        for p in &[p1, p2, p3, p4] {
+            voter_tip = vote(&chain, 0, voter_tip, vec![*p]);
+        }
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.ledger_tip_level(), 4);
+        assert_eq!(chain.prune(4).unwrap(), vec![t2]);
+        assert_eq!(*chain.pruned_level.lock().unwrap(), 3);
+
+        // a longer fork of the voter chain goes through the pruned voter blocks
+        let mut fork = voter_genesis;
+        for _ in 0..5 {
+            fork = vote(&chain, 0, fork, vec![]);
+        }
+        match chain.update_ledger() {
+            Err(BlockChainError::Pruned(_)) => {}
+            _ => panic!("reorganized through pruned voter blocks"),
+        }
+        assert_eq!(chain.voter_ledger_tips.lock().unwrap()[0], voter_tip);
+        assert_eq!(chain.ledger_tip_level(), 4);
+        assert_eq!(chain.proposer_leader(4).unwrap(), Some(p4));
+    }
+
+    #[test]
+    fn untouched_levels_not_recomputed() {
+        let chain = new_blockchain("untouched_levels_not_recomputed", 3);
+        let genesis = chain.config.proposer_genesis;
+        let voter_genesis = chain.config.voter_genesis.clone();
+
+        let p1 = propose(&chain, genesis, vec![], vec![]);
+        let p2 = propose(&chain, p1, vec![], vec![]);
+        let p3a = propose(&chain, p2, vec![], vec![]);
+        let p3b = propose(&chain, p2, vec![], vec![]);
+        let p4 = propose(&chain, p3a, vec![], vec![]);
+        let p5 = propose(&chain, p4, vec![], vec![]);
+        let v0 = vote(&chain, 0, voter_genesis[0], vec![p1, p2, p3a, p4]);
+        vote(&chain, 1, voter_genesis[1], vec![p1, p2, p3b, p4]);
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.ledger_tip_level(), 2);
+        assert_eq!(chain.proposer_leader(4).unwrap(), Some(p4));
+        let undecided: Vec<u64> = chain
+            .undecided_levels
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(undecided, vec![3]);
+
+        // a vote on level 5 deepens the votes on the undecided level 3, but levels 1, 2 and 4
+        // keep their leaders
+        vote(&chain, 0, v0, vec![p5]);
+        let levels: Vec<u64> = chain
+            .apply_vote_diff(&chain.voter_tips())
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(levels, vec![3, 5]);
+        assert!(chain
+            .apply_vote_diff(&chain.voter_tips())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn fast_confirmation_cleared_by_ledger() {
+        let chain = new_blockchain("fast_confirmation_cleared_by_ledger", 3);
+        let events = chain.subscribe_fast_confirmations();
+        let genesis = chain.config.proposer_genesis;
+        let voter_genesis = chain.config.voter_genesis.clone();
+        let tx = generate_random_hash();
+
+        // level 1 is split, while the leader of level 2 refers to the transaction block
+        let p1a = propose(&chain, genesis, vec![], vec![]);
+        let p1b = propose(&chain, genesis, vec![], vec![tx]);
+        let p2 = propose(&chain, p1a, vec![], vec![tx]);
+        let v0 = vote(&chain, 0, voter_genesis[0], vec![p1a]);
+        vote(&chain, 0, v0, vec![p2]);
+        let v1 = vote(&chain, 1, voter_genesis[1], vec![p1b]);
+        vote(&chain, 1, v1, vec![p2]);
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.ledger_tip_level(), 0);
+        assert_eq!(chain.fast_confirm().unwrap(), vec![tx]);
+        assert_eq!(events.try_recv(), Ok(FastConfirmation::Confirmed(tx, 2)));
+
+        // the transaction block enters the ledger through the other block of level 1
+        vote(&chain, 2, voter_genesis[2], vec![p1b]);
+        let (added, _) = chain.update_ledger().unwrap();
+        assert_eq!(added[0], (1, vec![tx]));
+        assert!(chain.fast_confirmed_transactions.lock().unwrap().is_empty());
+        assert!(events.try_recv().is_err());
+        assert_eq!(chain.fast_confirm().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn fast_confirmation_revoked() {
+        let chain = new_blockchain("fast_confirmation_revoked", 3);
+        let events = chain.subscribe_fast_confirmations();
+        let genesis = chain.config.proposer_genesis;
+        let voter_genesis = chain.config.voter_genesis.clone();
+        let tx = generate_random_hash();
+
+        let p1a = propose(&chain, genesis, vec![], vec![]);
+        let p1b = propose(&chain, genesis, vec![], vec![]);
+        let p2a = propose(&chain, p1a, vec![], vec![tx]);
+        let p2b = propose(&chain, p1b, vec![], vec![]);
+        let v0 = vote(&chain, 0, voter_genesis[0], vec![p1a]);
+        vote(&chain, 0, v0, vec![p2a]);
+        let v1 = vote(&chain, 1, voter_genesis[1], vec![p1b]);
+        vote(&chain, 1, v1, vec![p2a]);
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.fast_confirm().unwrap(), vec![tx]);
+        assert_eq!(events.try_recv(), Ok(FastConfirmation::Confirmed(tx, 2)));
+
+        // a voter chain switches to a fork that votes for the other block of level 2
+        let v1 = vote(&chain, 1, v1, vec![p2b]);
+        vote(&chain, 1, v1, vec![]);
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.proposer_leader(2).unwrap(), None);
+        assert!(events.try_recv().is_err());
+
+        // level 2 is decided without the transaction block
+        let v2 = vote(&chain, 2, voter_genesis[2], vec![p1b]);
+        vote(&chain, 2, v2, vec![p2b]);
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.ledger_tip_level(), 2);
+        assert_eq!(events.try_recv(), Ok(FastConfirmation::Revoked(tx)));
+        assert!(chain.fast_confirmed_transactions.lock().unwrap().is_empty());
+    }
+}
+
 /*
 #[cfg(test)]
 mod tests {
@@ -159,15 +159,34 @@ impl BlockDatabase {
         Ok(serialized)
     }
 
+    /// Check whether a block has ever been inserted, even if its body was pruned since.
     pub fn contains(&self, hash: &H256) -> Result<bool, rocksdb::Error> {
-        let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
-        let serialized = self.db.get_pinned_cf(block_cf, hash)?;
+        let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
+        let serialized = self.db.get_pinned_cf(block_sequence_number_cf, hash)?;
         match serialized {
             None => Ok(false),
             Some(_) => Ok(true),
         }
     }
 
+    /// Drop the body of a block, while remembering that the block was inserted.
+    pub fn prune(&self, hash: &H256) -> Result<(), rocksdb::Error> {
+        let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
+        self.db.delete_cf(block_cf, hash)
+    }
+
+    /// Forget a block that was never processed, so that it can be received again.
+    pub fn remove(&self, hash: &H256) -> Result<(), rocksdb::Error> {
+        let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
+        let block_arrival_order_cf = self.db.cf_handle(BLOCK_ARRIVAL_ORDER_CF).unwrap();
+        let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
+        if let Some(counter) = self.db.get_pinned_cf(block_sequence_number_cf, hash)? {
+            self.db.delete_cf(block_arrival_order_cf, &counter)?;
+        }
+        self.db.delete_cf(block_sequence_number_cf, hash)?;
+        self.db.delete_cf(block_cf, hash)
+    }
+
     pub fn blocks_after(&self, after: &H256, batch_size: u64) -> BlocksInArrivalOrder {
         let block_sequence_number_cf = self.db.cf_handle(BLOCK_SEQUENCE_NUMBER_CF).unwrap();
         let start_seq = u64::from_ne_bytes(
@@ -236,9 +255,11 @@ impl<'a> std::iter::Iterator for BlocksInArrivalOrder<'a> {
                 .get_cf(block_arrival_order_cf, &self.seq.to_ne_bytes())
                 .unwrap()
                 .unwrap();
-            let block: Block =
-                deserialize(&self.db.db.get_cf(block_cf, &hash_bytes).unwrap().unwrap()).unwrap();
-            result.push(block);
+            // skip the blocks whose bodies were pruned
+            if let Some(raw) = self.db.db.get_cf(block_cf, &hash_bytes).unwrap() {
+                let block: Block = deserialize(&raw).unwrap();
+                result.push(block);
+            }
             self.seq += 1;
             this_batch += 1;
         }
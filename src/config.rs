@@ -1,5 +1,7 @@
+use crate::blockchain::confirmation::ConfirmationRuleKind;
 use crate::crypto::hash::H256;
 use bigint::uint::U256;
+use std::collections::BTreeMap;
 
 const AVG_TX_SIZE: u32 = 168; // average size of a transaction (in Bytes)
 const PROPOSER_TX_REF_HEADROOM: f32 = 10.0;
@@ -11,6 +13,10 @@ pub const PROPOSER_INDEX: u16 = 0;
 pub const TRANSACTION_INDEX: u16 = 1;
 pub const FIRST_VOTER_INDEX: u16 = 2;
 
+/// Number of proposer levels between a proposer block and the ledger level whose UTXO set it
+/// commits to. It leaves the ledger time to confirm that level before blocks commit to it.
+pub const UTXO_COMMITMENT_DELAY: u64 = 100;
+
 #[derive(Clone)]
 pub struct BlockchainConfig {
     /// Number of voter chains.
@@ -38,6 +44,32 @@ pub struct BlockchainConfig {
     log_epsilon: f32,
     pub quantile_epsilon_confirm: f32,
     pub quantile_epsilon_deconfirm: f32,
+    /// Rule used to confirm the leader of each proposer level.
+    pub confirmation_rule: ConfirmationRuleKind,
+    /// Number of proposer levels below the ledger tip whose consensus metadata is kept, or
+    /// `None` to keep everything.
+    pub pruning_depth: Option<u64>,
+    /// Proposer blocks pinned as the leaders of their levels. Blocks conflicting with them are
+    /// rejected, and the ledger is never deconfirmed below the newest one it has reached.
+    pub checkpoints: BTreeMap<u64, H256>,
+    /// Whether the node is a light client, which only tracks block headers and voter content and
+    /// does not execute the ledger.
+    pub light_client: bool,
+    /// Number of threads doing real proof of work in the miner, or 0 to emulate the mining rate
+    /// with one thread.
+    pub mining_threads: usize,
+    /// Difficulty of the blocks mined with real proof of work.
+    pub mining_difficulty: H256,
+    /// Whether to hand out header templates to external miners.
+    pub external_mining: bool,
+    /// Whether the miner draws the blocks of each chain from the mining rates on a simulated clock,
+    /// instead of mining in real time.
+    pub simulated_mining: bool,
+    /// Address of the miner written into the headers of our blocks, or zero to leave them
+    /// unattributed.
+    pub miner_address: H256,
+    /// Extra content of the headers of our blocks, holding the operator tag.
+    pub miner_tag: [u8; 32],
 }
 
 impl BlockchainConfig {
@@ -112,6 +144,16 @@ impl BlockchainConfig {
             log_epsilon,
             quantile_epsilon_confirm: quantile_confirm,
             quantile_epsilon_deconfirm: quantile_deconfirm,
+            confirmation_rule: ConfirmationRuleKind::Prism,
+            pruning_depth: None,
+            checkpoints: BTreeMap::new(),
+            light_client: false,
+            mining_threads: 0,
+            mining_difficulty: *DEFAULT_DIFFICULTY,
+            external_mining: false,
+            simulated_mining: false,
+            miner_address: H256::default(),
+            miner_tag: [0; 32],
         }
     }
 
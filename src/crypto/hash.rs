@@ -86,6 +86,17 @@ impl std::convert::From<ring::digest::Digest> for H256 {
     }
 }
 
+impl std::str::FromStr for H256 {
+    type Err = hex::FromHexError;
+
+    /// Parse a hash from its hex representation, as produced by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buffer: [u8; 32] = [0; 32];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H256(buffer))
+    }
+}
+
 impl Ord for H256 {
     fn cmp(&self, other: &H256) -> std::cmp::Ordering {
         let self_higher = u128::from_be_bytes(self.0[0..16].try_into().unwrap());
@@ -172,4 +183,11 @@ pub mod tests {
         let should_be: H256 = (&should_be).into();
         assert_eq!(hashed_hash, should_be);
     }
+
+    #[test]
+    fn parse() {
+        let hash = generate_random_hash();
+        assert_eq!(hash.to_string().parse::<H256>().unwrap(), hash);
+        assert!("0001".parse::<H256>().is_err());
+    }
 }
@@ -0,0 +1,83 @@
+use crate::block::header::Header;
+use crate::block::Content;
+use crate::blockchain::{BlockChain, Result};
+use crate::blockdb::BlockDatabase;
+use crate::crypto::hash::H256;
+use crate::transaction::address::Bech32Address;
+use crate::transaction::Address;
+
+use std::collections::{BTreeMap, HashSet};
+
+/// Number of blocks of each type mined by one miner with one tag.
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct MinerShare {
+    /// Address of the miner, or `None` for unattributed blocks.
+    pub miner: Option<Bech32Address>,
+    pub tag: Option<String>,
+    pub proposer_blocks: u64,
+    pub voter_blocks: u64,
+    pub transaction_blocks: u64,
+}
+
+/// Blocks of the last levels of each chain, attributed to the miners who mined them.
+#[derive(Default)]
+pub struct Attribution {
+    shares: BTreeMap<(Option<Address>, Option<String>), MinerShare>,
+}
+
+impl Attribution {
+    /// Attribute the proposer blocks in the given number of proposer levels below the best one,
+    /// the transaction blocks they refer to, and the blocks in the same number of levels below
+    /// the tip of each voter chain. Blocks missing from the database are skipped.
+    pub fn collect(blockchain: &BlockChain, blockdb: &BlockDatabase, levels: u64) -> Result<Self> {
+        let mut attribution = Self::default();
+        let best_level = blockchain.best_proposer_level();
+        let mut transaction_blocks: HashSet<H256> = HashSet::new();
+        for level in best_level.saturating_sub(levels) + 1..=best_level {
+            for hash in blockchain.proposers_at_level(level)? {
+                if let Some(block) = blockdb.get(&hash)? {
+                    if let Content::Proposer(content) = &block.content {
+                        transaction_blocks.extend(content.transaction_refs.iter().cloned());
+                    }
+                    attribution.add(&block.header).proposer_blocks += 1;
+                }
+            }
+        }
+        for hash in &transaction_blocks {
+            if let Some(block) = blockdb.get(hash)? {
+                attribution.add(&block.header).transaction_blocks += 1;
+            }
+        }
+        for (tip, level) in blockchain.voter_tips() {
+            let mut hash = tip;
+            for _ in 0..levels.min(level) {
+                let block = match blockdb.get(&hash)? {
+                    Some(block) => block,
+                    None => break,
+                };
+                attribution.add(&block.header).voter_blocks += 1;
+                match &block.content {
+                    Content::Voter(content) => hash = content.voter_parent,
+                    _ => break,
+                }
+            }
+        }
+        Ok(attribution)
+    }
+
+    fn add(&mut self, header: &Header) -> &mut MinerShare {
+        let key = (header.miner(), header.tag());
+        self.shares
+            .entry(key.clone())
+            .or_insert_with(|| MinerShare {
+                miner: key.0.map(Bech32Address),
+                tag: key.1,
+                ..Default::default()
+            })
+    }
+
+    /// Get the shares of the miners, ordered by address and tag.
+    pub fn shares(&self) -> Vec<MinerShare> {
+        self.shares.values().cloned().collect()
+    }
+}
@@ -1,12 +1,12 @@
+pub mod attribution;
 pub mod performance_counter;
+pub mod simulation;
 pub mod transaction_generator;
 
 use crate::crypto::hash::H256;
 use crate::transaction::{CoinId, Output};
 use crate::utxodb::UtxoDatabase;
 use crate::wallet::Wallet;
-use bincode::serialize;
-use rocksdb::*;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
@@ -29,14 +29,10 @@ pub fn ico(
         let handle = thread::spawn(move || {
             while let Some(recipient) = recipients.lock().unwrap().pop() {
                 let transaction_id_start = (recipient.0 * num_coins) as u128;
-
-                let mut write_opt = WriteOptions::default();
-                write_opt.disable_wal(true);
                 let output = Output {
                     value,
                     recipient: recipient.1,
                 };
-                let output_raw = serialize(&output).unwrap();
                 for i in 0..num_coins {
                     let tx_uid = transaction_id_start + i as u128;
                     let tx_uid = tx_uid.to_ne_bytes();
@@ -46,10 +42,7 @@ pub fn ico(
                         hash: tx_hash_raw.into(),
                         index: 0,
                     };
-                    utxodb
-                        .db
-                        .put_opt(serialize(&coinid).unwrap(), output_raw.clone(), &write_opt)
-                        .unwrap();
+                    utxodb.add_coin(&coinid, &output).unwrap();
                     wallet.apply_diff(&[(coinid, output)], &[]).unwrap();
                 }
             }
@@ -1,10 +1,13 @@
+use super::simulation::Clock;
 use crate::block::Block;
 use crate::block::Content as BlockContent;
 
 use crate::transaction::Transaction;
 use crate::wallet::WalletError;
 use log::debug;
+use std::fmt::Write;
 use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 
 lazy_static! {
@@ -15,6 +18,68 @@ pub trait PayloadSize {
     fn size(&self) -> usize;
 }
 
+/// Upper bounds (in ms) of the latency histogram buckets. The last bucket is +Inf.
+const LATENCY_BUCKETS: [usize; 14] = [
+    10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000, 60000, 120000, 300000,
+];
+
+#[derive(Default)]
+pub struct Histogram {
+    // per-bucket (non-cumulative) counts, with one extra bucket for +Inf
+    buckets: [AtomicUsize; 15],
+    sum: AtomicUsize,
+    count: AtomicUsize,
+}
+
+impl Histogram {
+    pub fn observe(&self, value: usize) {
+        let idx = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sum(&self) -> usize {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn write_prometheus(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        let mut cumulative = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            match LATENCY_BUCKETS.get(idx) {
+                Some(bound) => {
+                    writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).unwrap()
+                }
+                None => writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative).unwrap(),
+            }
+        }
+        writeln!(out, "{}_sum {}", name, self.sum()).unwrap();
+        writeln!(out, "{}_count {}", name, self.count()).unwrap();
+    }
+}
+
+fn write_metric<T: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    value: T,
+) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, metric_type).unwrap();
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
 #[derive(Default)]
 pub struct Counter {
     generated_transactions: AtomicUsize,
@@ -38,20 +103,33 @@ pub struct Counter {
     mined_voter_block_bytes: AtomicUsize,
     mined_transaction_blocks: AtomicUsize,
     mined_transaction_block_bytes: AtomicUsize,
-    total_proposer_block_delay: AtomicUsize,
-    total_voter_block_delay: AtomicUsize,
-    total_transaction_block_delay: AtomicUsize,
-    total_proposer_block_squared_delay: AtomicUsize,
-    total_voter_block_squared_delay: AtomicUsize,
-    total_transaction_block_squared_delay: AtomicUsize,
+    computed_hashes: AtomicUsize,
+    in_flight_transactions: AtomicUsize,
+    in_flight_duplicate_transactions: AtomicUsize,
+    in_flight_conflicting_transactions: AtomicUsize,
+    skipped_duplicate_transactions: AtomicUsize,
+    skipped_conflicting_transactions: AtomicUsize,
+    hash_rate: AtomicUsize,
+    proposer_block_delay: Histogram,
+    voter_block_delay: Histogram,
+    transaction_block_delay: Histogram,
     received_proposer_blocks: AtomicUsize,
     received_voter_blocks: AtomicUsize,
     received_transaction_blocks: AtomicUsize,
     incoming_message_queue: AtomicIsize,
-    total_transaction_block_confirmation_latency: AtomicUsize,
-    total_transaction_block_squared_confirmation_latency: AtomicUsize,
+    buffered_blocks: AtomicUsize,
+    evicted_buffered_blocks: AtomicUsize,
+    expired_buffered_blocks: AtomicUsize,
+    utxo_commitment_mismatches: AtomicUsize,
+    signature_verification_queue: AtomicUsize,
+    block_insertion_queue: AtomicUsize,
+    transaction_block_confirmation_latency: Histogram,
+    fast_confirmed_transaction_blocks: AtomicUsize,
+    transaction_block_fast_confirmation_latency: Histogram,
     proposer_main_chain_length: AtomicUsize,
     voter_main_chain_length_sum: AtomicIsize,
+    // clock the block timestamps come from, the wall clock if unset
+    clock: RwLock<Option<Arc<dyn Clock>>>,
 }
 
 #[derive(Serialize)]
@@ -77,23 +155,51 @@ pub struct Snapshot {
     pub mined_voter_block_bytes: usize,
     pub mined_transaction_blocks: usize,
     pub mined_transaction_block_bytes: usize,
+    pub computed_hashes: usize,
+    pub in_flight_transactions: usize,
+    pub in_flight_duplicate_transactions: usize,
+    pub in_flight_conflicting_transactions: usize,
+    pub skipped_duplicate_transactions: usize,
+    pub skipped_conflicting_transactions: usize,
+    pub hash_rate: usize,
     pub total_proposer_block_delay: usize,
     pub total_voter_block_delay: usize,
     pub total_transaction_block_delay: usize,
-    pub total_proposer_block_squared_delay: usize,
-    pub total_voter_block_squared_delay: usize,
-    pub total_transaction_block_squared_delay: usize,
     pub received_proposer_blocks: usize,
     pub received_voter_blocks: usize,
     pub received_transaction_blocks: usize,
     pub incoming_message_queue: isize,
+    pub buffered_blocks: usize,
+    pub evicted_buffered_blocks: usize,
+    pub expired_buffered_blocks: usize,
+    pub utxo_commitment_mismatches: usize,
+    pub signature_verification_queue: usize,
+    pub block_insertion_queue: usize,
     pub total_transaction_block_confirmation_latency: usize,
-    pub total_transaction_block_squared_confirmation_latency: usize,
+    pub fast_confirmed_transaction_blocks: usize,
+    pub total_transaction_block_fast_confirmation_latency: usize,
     pub proposer_main_chain_length: usize,
     pub voter_main_chain_length_sum: isize,
 }
 
 impl Counter {
+    /// Measure latencies on the given clock, which must be the one that timestamps the blocks.
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.write().unwrap() = Some(clock);
+    }
+
+    /// Milliseconds elapsed since the given timestamp.
+    fn delay_since(&self, timestamp: u128) -> u128 {
+        let current_time = match &*self.clock.read().unwrap() {
+            Some(clock) => clock.now(),
+            None => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+        };
+        current_time.saturating_sub(timestamp)
+    }
+
     pub fn record_process_message(&self) {
         self.incoming_message_queue.fetch_sub(1, Ordering::Relaxed);
     }
@@ -103,41 +209,23 @@ impl Counter {
     }
 
     pub fn record_receive_block(&self, b: &Block) {
-        let mined_time = b.header.timestamp;
-        let current_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        let delay = if current_time <= mined_time {
-            0
-        } else {
-            current_time - mined_time
-        };
+        let delay = self.delay_since(b.header.timestamp);
         match b.content {
             BlockContent::Transaction(_) => {
                 debug!("Received Transaction block, delay={} ms", delay);
-                self.total_transaction_block_delay
-                    .fetch_add(delay as usize, Ordering::Relaxed);
-                self.total_transaction_block_squared_delay
-                    .fetch_add((delay * delay) as usize, Ordering::Relaxed);
+                self.transaction_block_delay.observe(delay as usize);
                 self.received_transaction_blocks
                     .fetch_add(1, Ordering::Relaxed);
             }
             BlockContent::Proposer(_) => {
                 debug!("Received Proposer block, delay={} ms", delay);
-                self.total_proposer_block_delay
-                    .fetch_add(delay as usize, Ordering::Relaxed);
-                self.total_proposer_block_squared_delay
-                    .fetch_add((delay * delay) as usize, Ordering::Relaxed);
+                self.proposer_block_delay.observe(delay as usize);
                 self.received_proposer_blocks
                     .fetch_add(1, Ordering::Relaxed);
             }
             BlockContent::Voter(_) => {
                 debug!("Received Voter block, delay={} ms", delay);
-                self.total_voter_block_delay
-                    .fetch_add(delay as usize, Ordering::Relaxed);
-                self.total_voter_block_squared_delay
-                    .fetch_add((delay * delay) as usize, Ordering::Relaxed);
+                self.voter_block_delay.observe(delay as usize);
                 self.received_voter_blocks.fetch_add(1, Ordering::Relaxed);
             }
         }
@@ -186,6 +274,56 @@ impl Counter {
         }
     }
 
+    pub fn record_hashes(&self, num_hashes: usize) {
+        self.computed_hashes
+            .fetch_add(num_hashes, Ordering::Relaxed);
+    }
+
+    pub fn record_hash_rate(&self, hashes_per_second: usize) {
+        self.hash_rate.store(hashes_per_second, Ordering::Relaxed);
+    }
+
+    pub fn record_in_flight_transactions(&self, total: usize, duplicates: usize, conflicts: usize) {
+        self.in_flight_transactions
+            .fetch_add(total, Ordering::Relaxed);
+        self.in_flight_duplicate_transactions
+            .fetch_add(duplicates, Ordering::Relaxed);
+        self.in_flight_conflicting_transactions
+            .fetch_add(conflicts, Ordering::Relaxed);
+    }
+
+    pub fn record_skip_transactions(&self, duplicates: usize, conflicts: usize) {
+        self.skipped_duplicate_transactions
+            .fetch_add(duplicates, Ordering::Relaxed);
+        self.skipped_conflicting_transactions
+            .fetch_add(conflicts, Ordering::Relaxed);
+    }
+
+    /// Record the number of blocks waiting for their references, for signature verification
+    /// and for insertion into the blockchain.
+    pub fn record_block_queues(&self, buffered: usize, verification: usize, insertion: usize) {
+        self.buffered_blocks.store(buffered, Ordering::Relaxed);
+        self.signature_verification_queue
+            .store(verification, Ordering::Relaxed);
+        self.block_insertion_queue
+            .store(insertion, Ordering::Relaxed);
+    }
+
+    /// Record blocks dropped from the buffer of blocks waiting for their references, either to
+    /// stay within its limits or because they waited for too long.
+    pub fn record_drop_buffered_blocks(&self, evicted: usize, expired: usize) {
+        self.evicted_buffered_blocks
+            .fetch_add(evicted, Ordering::Relaxed);
+        self.expired_buffered_blocks
+            .fetch_add(expired, Ordering::Relaxed);
+    }
+
+    /// Record a confirmed proposer block whose UTXO commitment differs from our UTXO set.
+    pub fn record_utxo_commitment_mismatch(&self) {
+        self.utxo_commitment_mismatches
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn record_update_proposer_main_chain(&self, new_height: usize) {
         self.proposer_main_chain_length
             .store(new_height, Ordering::Relaxed);
@@ -204,24 +342,21 @@ impl Counter {
     }
 
     pub fn record_confirm_transaction_block(&self, b: &Block) {
-        let mined_time = b.header.timestamp;
-        let current_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        let delay = if current_time <= mined_time {
-            0
-        } else {
-            current_time - mined_time
-        };
-        self.total_transaction_block_confirmation_latency
-            .fetch_add(delay as usize, Ordering::Relaxed);
-        self.total_transaction_block_squared_confirmation_latency
-            .fetch_add((delay * delay) as usize, Ordering::Relaxed);
+        let delay = self.delay_since(b.header.timestamp);
+        self.transaction_block_confirmation_latency
+            .observe(delay as usize);
         self.confirmed_transaction_blocks
             .fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn record_fast_confirm_transaction_block(&self, b: &Block) {
+        let delay = self.delay_since(b.header.timestamp);
+        self.transaction_block_fast_confirmation_latency
+            .observe(delay as usize);
+        self.fast_confirmed_transaction_blocks
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn record_deconfirm_transaction_blocks(&self, num_blocks: usize) {
         self.deconfirmed_transaction_blocks
             .fetch_add(num_blocks, Ordering::Relaxed);
@@ -302,32 +437,338 @@ impl Counter {
             mined_transaction_block_bytes: self
                 .mined_transaction_block_bytes
                 .load(Ordering::Relaxed),
-            total_proposer_block_delay: self.total_proposer_block_delay.load(Ordering::Relaxed),
-            total_voter_block_delay: self.total_voter_block_delay.load(Ordering::Relaxed),
-            total_transaction_block_delay: self
-                .total_transaction_block_delay
+            computed_hashes: self.computed_hashes.load(Ordering::Relaxed),
+            in_flight_transactions: self.in_flight_transactions.load(Ordering::Relaxed),
+            in_flight_duplicate_transactions: self
+                .in_flight_duplicate_transactions
                 .load(Ordering::Relaxed),
-            total_proposer_block_squared_delay: self
-                .total_proposer_block_squared_delay
+            in_flight_conflicting_transactions: self
+                .in_flight_conflicting_transactions
                 .load(Ordering::Relaxed),
-            total_voter_block_squared_delay: self
-                .total_voter_block_squared_delay
+            skipped_duplicate_transactions: self
+                .skipped_duplicate_transactions
                 .load(Ordering::Relaxed),
-            total_transaction_block_squared_delay: self
-                .total_transaction_block_squared_delay
+            skipped_conflicting_transactions: self
+                .skipped_conflicting_transactions
                 .load(Ordering::Relaxed),
+            hash_rate: self.hash_rate.load(Ordering::Relaxed),
+            total_proposer_block_delay: self.proposer_block_delay.sum(),
+            total_voter_block_delay: self.voter_block_delay.sum(),
+            total_transaction_block_delay: self.transaction_block_delay.sum(),
             received_proposer_blocks: self.received_proposer_blocks.load(Ordering::Relaxed),
             received_voter_blocks: self.received_voter_blocks.load(Ordering::Relaxed),
             received_transaction_blocks: self.received_transaction_blocks.load(Ordering::Relaxed),
             incoming_message_queue,
+            buffered_blocks: self.buffered_blocks.load(Ordering::Relaxed),
+            evicted_buffered_blocks: self.evicted_buffered_blocks.load(Ordering::Relaxed),
+            expired_buffered_blocks: self.expired_buffered_blocks.load(Ordering::Relaxed),
+            utxo_commitment_mismatches: self.utxo_commitment_mismatches.load(Ordering::Relaxed),
+            signature_verification_queue: self.signature_verification_queue.load(Ordering::Relaxed),
+            block_insertion_queue: self.block_insertion_queue.load(Ordering::Relaxed),
             total_transaction_block_confirmation_latency: self
-                .total_transaction_block_confirmation_latency
-                .load(Ordering::Relaxed),
-            total_transaction_block_squared_confirmation_latency: self
-                .total_transaction_block_squared_confirmation_latency
+                .transaction_block_confirmation_latency
+                .sum(),
+            fast_confirmed_transaction_blocks: self
+                .fast_confirmed_transaction_blocks
                 .load(Ordering::Relaxed),
+            total_transaction_block_fast_confirmation_latency: self
+                .transaction_block_fast_confirmation_latency
+                .sum(),
             proposer_main_chain_length: self.proposer_main_chain_length.load(Ordering::Relaxed),
             voter_main_chain_length_sum,
         }
     }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn prometheus(&self) -> String {
+        let s = self.snapshot();
+        let counters = [
+            (
+                "generated_transactions",
+                "Number of transactions generated by the transaction generator",
+                s.generated_transactions,
+            ),
+            (
+                "generated_transaction_bytes",
+                "Bytes of transactions generated by the transaction generator",
+                s.generated_transaction_bytes,
+            ),
+            (
+                "generate_transaction_failures",
+                "Number of failed attempts to generate a transaction",
+                s.generate_transaction_failures,
+            ),
+            (
+                "confirmed_transactions",
+                "Number of confirmed transactions",
+                s.confirmed_transactions,
+            ),
+            (
+                "confirmed_transaction_bytes",
+                "Bytes of confirmed transactions",
+                s.confirmed_transaction_bytes,
+            ),
+            (
+                "deconfirmed_transactions",
+                "Number of deconfirmed transactions",
+                s.deconfirmed_transactions,
+            ),
+            (
+                "deconfirmed_transaction_bytes",
+                "Bytes of deconfirmed transactions",
+                s.deconfirmed_transaction_bytes,
+            ),
+            (
+                "confirmed_transaction_blocks",
+                "Number of confirmed transaction blocks",
+                s.confirmed_transaction_blocks,
+            ),
+            (
+                "deconfirmed_transaction_blocks",
+                "Number of deconfirmed transaction blocks",
+                s.deconfirmed_transaction_blocks,
+            ),
+            (
+                "fast_confirmed_transaction_blocks",
+                "Number of transaction blocks confirmed before the leader of their level is known",
+                s.fast_confirmed_transaction_blocks,
+            ),
+            (
+                "processed_proposer_blocks",
+                "Number of proposer blocks inserted into the blockchain",
+                s.processed_proposer_blocks,
+            ),
+            (
+                "processed_proposer_block_bytes",
+                "Bytes of proposer blocks inserted into the blockchain",
+                s.processed_proposer_block_bytes,
+            ),
+            (
+                "processed_voter_blocks",
+                "Number of voter blocks inserted into the blockchain",
+                s.processed_voter_blocks,
+            ),
+            (
+                "processed_voter_block_bytes",
+                "Bytes of voter blocks inserted into the blockchain",
+                s.processed_voter_block_bytes,
+            ),
+            (
+                "processed_transaction_blocks",
+                "Number of transaction blocks inserted into the blockchain",
+                s.processed_transaction_blocks,
+            ),
+            (
+                "processed_transaction_block_bytes",
+                "Bytes of transaction blocks inserted into the blockchain",
+                s.processed_transaction_block_bytes,
+            ),
+            (
+                "mined_proposer_blocks",
+                "Number of proposer blocks mined by this node",
+                s.mined_proposer_blocks,
+            ),
+            (
+                "mined_proposer_block_bytes",
+                "Bytes of proposer blocks mined by this node",
+                s.mined_proposer_block_bytes,
+            ),
+            (
+                "mined_voter_blocks",
+                "Number of voter blocks mined by this node",
+                s.mined_voter_blocks,
+            ),
+            (
+                "mined_voter_block_bytes",
+                "Bytes of voter blocks mined by this node",
+                s.mined_voter_block_bytes,
+            ),
+            (
+                "mined_transaction_blocks",
+                "Number of transaction blocks mined by this node",
+                s.mined_transaction_blocks,
+            ),
+            (
+                "mined_transaction_block_bytes",
+                "Bytes of transaction blocks mined by this node",
+                s.mined_transaction_block_bytes,
+            ),
+            (
+                "computed_hashes",
+                "Number of hashes computed by the proof of work threads",
+                s.computed_hashes,
+            ),
+            (
+                "in_flight_transactions",
+                "Number of transactions in transaction blocks that were not referred when inserted",
+                s.in_flight_transactions,
+            ),
+            (
+                "in_flight_duplicate_transactions",
+                "Number of transactions already in another unreferred transaction block",
+                s.in_flight_duplicate_transactions,
+            ),
+            (
+                "in_flight_conflicting_transactions",
+                "Number of transactions spending a coin already spent in another unreferred transaction block",
+                s.in_flight_conflicting_transactions,
+            ),
+            (
+                "skipped_duplicate_transactions",
+                "Number of memory pool transactions left out of transaction block templates because an unreferred transaction block has them",
+                s.skipped_duplicate_transactions,
+            ),
+            (
+                "skipped_conflicting_transactions",
+                "Number of memory pool transactions left out of transaction block templates because they conflict with an unreferred transaction block",
+                s.skipped_conflicting_transactions,
+            ),
+            (
+                "received_proposer_blocks",
+                "Number of proposer blocks received from peers",
+                s.received_proposer_blocks,
+            ),
+            (
+                "received_voter_blocks",
+                "Number of voter blocks received from peers",
+                s.received_voter_blocks,
+            ),
+            (
+                "received_transaction_blocks",
+                "Number of transaction blocks received from peers",
+                s.received_transaction_blocks,
+            ),
+            (
+                "evicted_buffered_blocks",
+                "Number of received blocks dropped to keep the buffer of blocks waiting for the blocks they refer to within its limits",
+                s.evicted_buffered_blocks,
+            ),
+            (
+                "expired_buffered_blocks",
+                "Number of received blocks dropped after waiting for too long for the blocks they refer to",
+                s.expired_buffered_blocks,
+            ),
+            (
+                "utxo_commitment_mismatches",
+                "Number of confirmed proposer blocks whose UTXO commitment differs from the UTXO set we computed",
+                s.utxo_commitment_mismatches,
+            ),
+        ];
+        let gauges = [
+            (
+                "incoming_message_queue",
+                "Number of network messages waiting to be processed",
+                s.incoming_message_queue as isize,
+            ),
+            (
+                "buffered_blocks",
+                "Number of received blocks waiting for the blocks they refer to",
+                s.buffered_blocks as isize,
+            ),
+            (
+                "signature_verification_queue",
+                "Number of transaction block chunks waiting for signature verification",
+                s.signature_verification_queue as isize,
+            ),
+            (
+                "block_insertion_queue",
+                "Number of validated blocks waiting to be inserted into the blockchain",
+                s.block_insertion_queue as isize,
+            ),
+            (
+                "proposer_main_chain_length",
+                "Level of the best proposer block",
+                s.proposer_main_chain_length as isize,
+            ),
+            (
+                "voter_main_chain_length_sum",
+                "Sum of the levels of the best voter blocks of all voter chains",
+                s.voter_main_chain_length_sum as isize,
+            ),
+            (
+                "hash_rate",
+                "Hashes per second computed by the proof of work threads",
+                s.hash_rate as isize,
+            ),
+        ];
+        let histograms = [
+            (
+                &self.proposer_block_delay,
+                "proposer_block_delay",
+                "Propagation delay of received proposer blocks",
+            ),
+            (
+                &self.voter_block_delay,
+                "voter_block_delay",
+                "Propagation delay of received voter blocks",
+            ),
+            (
+                &self.transaction_block_delay,
+                "transaction_block_delay",
+                "Propagation delay of received transaction blocks",
+            ),
+            (
+                &self.transaction_block_confirmation_latency,
+                "transaction_block_confirmation_latency",
+                "Time from mining a transaction block to confirming it in the ledger",
+            ),
+            (
+                &self.transaction_block_fast_confirmation_latency,
+                "transaction_block_fast_confirmation_latency",
+                "Time from mining a transaction block to confirming it by list decoding",
+            ),
+        ];
+        let mut out = String::new();
+        for (name, help, value) in counters.iter() {
+            write_metric(
+                &mut out,
+                &format!("prism_{}_total", name),
+                "counter",
+                help,
+                value,
+            );
+        }
+        for (name, help, value) in gauges.iter() {
+            write_metric(&mut out, &format!("prism_{}", name), "gauge", help, value);
+        }
+        for (histogram, name, help) in histograms.iter() {
+            histogram.write_prometheus(&mut out, &format!("prism_{}_milliseconds", name), help);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let h = Histogram::default();
+        h.observe(5);
+        h.observe(10);
+        h.observe(11);
+        h.observe(1_000_000);
+        assert_eq!(h.count(), 4);
+        assert_eq!(h.sum(), 1_000_026);
+        let mut out = String::new();
+        h.write_prometheus(&mut out, "delay", "test");
+        assert!(out.contains("delay_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("delay_bucket{le=\"25\"} 3\n"));
+        assert!(out.contains("delay_bucket{le=\"300000\"} 3\n"));
+        assert!(out.contains("delay_bucket{le=\"+Inf\"} 4\n"));
+        assert!(out.contains("delay_count 4\n"));
+    }
+    #[test]
+    fn latency_on_simulated_clock() {
+        use crate::experiment::simulation::SimulatedClock;
+        use std::time::Duration;
+
+        let counter = Counter::default();
+        let clock: Arc<dyn Clock> = Arc::new(SimulatedClock::new());
+        counter.set_clock(Arc::clone(&clock));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(counter.delay_since(100), 150);
+        assert_eq!(counter.delay_since(300), 0);
+    }
 }
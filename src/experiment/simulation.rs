@@ -0,0 +1,196 @@
+use rand::rngs::StdRng;
+use rand::{FromEntropy, SeedableRng};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// RNG stream of the miner.
+pub const MINER_STREAM: u64 = 0;
+/// RNG stream of the transaction generator.
+pub const TRANSACTION_GENERATOR_STREAM: u64 = 1;
+
+/// Create the RNG of a component. Components draw from separate streams of the same seed, so that
+/// their draws do not depend on how their threads are scheduled.
+pub fn rng(seed: Option<u64>, stream: u64) -> StdRng {
+    match seed {
+        Some(seed) => {
+            StdRng::seed_from_u64(seed.wrapping_add(stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+        }
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Source of time for the miner, the transaction generator and block timestamps.
+pub trait Clock: Send + Sync {
+    /// Get the current UNIX timestamp in microseconds.
+    fn now_micros(&self) -> u128;
+    /// Get the current UNIX timestamp in milliseconds.
+    fn now(&self) -> u128 {
+        self.now_micros() / 1000
+    }
+    /// Sleep for the given duration, as a thread following the time.
+    fn sleep(&self, duration: Duration);
+    /// Move the time forward by the given duration, as the thread driving the time.
+    fn advance(&self, duration: Duration);
+    /// Start following the time. A simulated clock does not move past a follower's wake-up time
+    /// until the follower is asleep again.
+    fn join(&self) {}
+    /// Stop following the time.
+    fn leave(&self) {}
+}
+
+/// The wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u128 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_micros()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn advance(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that starts at the UNIX epoch and only moves when the driving thread advances it. The
+/// followers wake up in the order of their wake-up times, and the clock waits for each of them to
+/// fall asleep again before moving on, so that the schedule of the mined blocks and transactions
+/// only depends on the seed. Their content does not: the UTXO commitments of proposer blocks and
+/// the coins the transaction generator spends come from the ledger, which is updated in real time.
+pub struct SimulatedClock {
+    state: Mutex<SimulatedState>,
+    cvar: Condvar,
+}
+
+struct SimulatedState {
+    /// Current time in microseconds.
+    now: u128,
+    /// Number of threads following the time.
+    followers: usize,
+    /// Wake-up times of the sleeping followers.
+    sleeping: Vec<u128>,
+}
+
+impl SimulatedState {
+    /// Whether every follower is asleep and waiting for a time in the future.
+    fn settled(&self) -> bool {
+        self.sleeping.len() >= self.followers && self.sleeping.iter().all(|t| *t > self.now)
+    }
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(SimulatedState {
+                now: 0,
+                followers: 0,
+                sleeping: vec![],
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_micros(&self) -> u128 {
+        self.state.lock().unwrap().now
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let wake_up = state.now + duration.as_micros().max(1);
+        state.sleeping.push(wake_up);
+        self.cvar.notify_all();
+        while state.now < wake_up {
+            state = self.cvar.wait(state).unwrap();
+        }
+        let index = state.sleeping.iter().position(|t| *t == wake_up).unwrap();
+        state.sleeping.swap_remove(index);
+        self.cvar.notify_all();
+    }
+
+    fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let target = state.now + duration.as_micros();
+        loop {
+            while !state.settled() {
+                state = self.cvar.wait(state).unwrap();
+            }
+            // wake up the followers due before the target one at a time
+            match state.sleeping.iter().min() {
+                Some(next) if *next <= target => state.now = *next,
+                _ => {
+                    state.now = target;
+                    self.cvar.notify_all();
+                    return;
+                }
+            }
+            self.cvar.notify_all();
+        }
+    }
+
+    fn join(&self) {
+        self.state.lock().unwrap().followers += 1;
+    }
+
+    fn leave(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.followers -= 1;
+        self.cvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use std::sync::Arc;
+
+    #[test]
+    fn seeded_rng() {
+        let draw = |stream| -> Vec<u64> {
+            let mut rng = rng(Some(7), stream);
+            (0..4).map(|_| rng.gen()).collect()
+        };
+        assert_eq!(draw(MINER_STREAM), draw(MINER_STREAM));
+        assert_ne!(draw(MINER_STREAM), draw(TRANSACTION_GENERATOR_STREAM));
+    }
+
+    #[test]
+    fn simulated_clock() {
+        let clock = Arc::new(SimulatedClock::new());
+        let log = Arc::new(Mutex::new(vec![]));
+        clock.join();
+        let follower = {
+            let clock = Arc::clone(&clock);
+            let log = Arc::clone(&log);
+            thread::spawn(move || {
+                for _ in 0..3 {
+                    clock.sleep(Duration::from_millis(40));
+                    log.lock().unwrap().push(clock.now());
+                }
+                clock.leave();
+            })
+        };
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(clock.now(), 100);
+        assert_eq!(*log.lock().unwrap(), vec![40, 80]);
+        clock.advance(Duration::from_millis(100));
+        follower.join().unwrap();
+        assert_eq!(clock.now(), 200);
+        assert_eq!(*log.lock().unwrap(), vec![40, 80, 120]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::experiment::simulation::Clock;
 use crate::handler::new_transaction;
 use crate::miner::memory_pool::MemoryPool;
 use crate::network::server::Handle as ServerHandle;
@@ -6,6 +7,7 @@ use crate::network::server::Handle as ServerHandle;
 use crate::wallet::Wallet;
 use crossbeam::channel;
 use log::{info, trace};
+use rand::rngs::StdRng;
 use rand::Rng;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -50,6 +52,8 @@ pub struct TransactionGenerator {
     arrival_distribution: ArrivalDistribution,
     value_distribution: ValueDistribution,
     state: State,
+    clock: Arc<dyn Clock>,
+    rng: StdRng,
 }
 
 impl TransactionGenerator {
@@ -57,6 +61,8 @@ impl TransactionGenerator {
         wallet: &Arc<Wallet>,
         server: &ServerHandle,
         mempool: &Arc<Mutex<MemoryPool>>,
+        clock: &Arc<dyn Clock>,
+        rng: StdRng,
     ) -> (Self, channel::Sender<ControlSignal>) {
         let (tx, rx) = channel::unbounded();
         let instance = Self {
@@ -67,6 +73,8 @@ impl TransactionGenerator {
             arrival_distribution: ArrivalDistribution::Uniform(UniformArrival { interval: 100 }),
             value_distribution: ValueDistribution::Uniform(UniformValue { min: 50, max: 100 }),
             state: State::Paused,
+            clock: Arc::clone(clock),
+            rng,
         };
         (instance, tx)
     }
@@ -99,13 +107,28 @@ impl TransactionGenerator {
 
     pub fn start(mut self) {
         thread::spawn(move || {
-            let mut rng = rand::thread_rng();
             // TODO: make it flexible
             let addr = self.wallet.addresses().unwrap()[0];
             let mut prev_coin = None;
+            // whether we follow the clock, which only matters to a simulated clock
+            let mut following = false;
             loop {
-                let tx_gen_start = time::Instant::now();
+                let tx_gen_start = self.clock.now_micros();
                 // check the current state and try to receive control message
+                match self.state {
+                    State::Continuous(_) | State::Step(_) => {
+                        if !following {
+                            self.clock.join();
+                            following = true;
+                        }
+                    }
+                    State::Paused => {
+                        if following {
+                            self.clock.leave();
+                            following = false;
+                        }
+                    }
+                }
                 match self.state {
                     State::Continuous(_) | State::Step(_) => match self.control_chan.try_recv() {
                         Ok(signal) => {
@@ -132,7 +155,7 @@ impl TransactionGenerator {
                             ArrivalDistribution::Uniform(d) => d.interval,
                         };
                         let interval = time::Duration::from_micros(interval);
-                        thread::sleep(interval);
+                        self.clock.sleep(interval);
                         continue;
                     }
                 }
@@ -141,7 +164,7 @@ impl TransactionGenerator {
                         if d.min == d.max {
                             d.min
                         } else {
-                            rng.gen_range(d.min, d.max)
+                            self.rng.gen_range(d.min, d.max)
                         }
                     }
                 };
@@ -169,7 +192,8 @@ impl TransactionGenerator {
                     ArrivalDistribution::Uniform(d) => d.interval,
                 };
                 let interval = time::Duration::from_micros(interval);
-                let time_spent = time::Instant::now().duration_since(tx_gen_start);
+                let time_spent =
+                    time::Duration::from_micros((self.clock.now_micros() - tx_gen_start) as u64);
                 let interval = {
                     if interval > time_spent {
                         interval - time_spent
@@ -177,7 +201,7 @@ impl TransactionGenerator {
                         time::Duration::new(0, 0)
                     }
                 };
-                thread::sleep(interval);
+                self.clock.sleep(interval);
             }
         });
         info!("Transaction generator initialized into paused mode");
@@ -0,0 +1,309 @@
+use crate::block::Content;
+use crate::blockchain::{BlockChain, Result};
+use crate::blockdb::BlockDatabase;
+use crate::config::UTXO_COMMITMENT_DELAY;
+use crate::crypto::hash::H256;
+use crate::network::message::Message;
+use crate::network::peer;
+use crate::network::server::Handle as ServerHandle;
+use crate::transaction::{CoinId, Output};
+use crate::utxodb::accumulator::{in_chunk, UtxoAccumulator};
+use crate::utxodb::{UtxoDatabase, SNAPSHOT_CHUNK_BITS, SNAPSHOT_INTERVAL};
+use crate::wallet::Wallet;
+use crossbeam::channel;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Time to wait for the next chunk before giving up on a snapshot.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to wait before looking for a snapshot again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of the most recent snapshot levels to try.
+const SNAPSHOT_CANDIDATES: u64 = 3;
+/// Number of ledger levels confirmed above the leader committing to a snapshot before the
+/// commitment is trusted.
+const CONFIRMATION_DEPTH: u64 = 10;
+/// Number of peers that must serve a snapshot with the root the leader commits to.
+const SNAPSHOT_QUORUM: usize = 2;
+
+/// A chunk of a UTXO snapshot received from a peer.
+pub struct SnapshotChunk {
+    pub peer: peer::Handle,
+    pub level: u64,
+    pub index: u32,
+    /// The root of the snapshot and the coins in the chunk, or `None` if the peer does not keep
+    /// the snapshot.
+    pub coins: Option<(H256, Vec<(CoinId, Output)>)>,
+}
+
+/// A snapshot level, and the confirmed leader that commits to the UTXO set at that level.
+#[derive(Debug, PartialEq)]
+pub(super) struct Target {
+    pub(super) level: u64,
+    pub(super) leader: H256,
+    pub(super) root: H256,
+}
+
+/// Fetches the UTXO set from peers at a ledger level committed by a trusted proposer block, so
+/// that the ledger does not have to be replayed from genesis.
+pub(super) struct Importer {
+    pub(super) blockdb: Arc<BlockDatabase>,
+    pub(super) chain: Arc<BlockChain>,
+    pub(super) utxodb: Arc<UtxoDatabase>,
+    pub(super) wallet: Arc<Wallet>,
+    pub(super) server: ServerHandle,
+    pub(super) chunk_chan: channel::Receiver<SnapshotChunk>,
+}
+
+impl Importer {
+    /// Keep trying until a snapshot is imported, and return its level and the leader committing
+    /// to it.
+    pub(super) fn run(&self) -> Target {
+        loop {
+            if let Some(target) = target(&self.blockdb, &self.chain) {
+                info!("Fetching the UTXO snapshot at level {}", target.level);
+                if let Some(snapshot) = self.fetch(target.level, &target.root) {
+                    let (added, removed) =
+                        self.utxodb.import_snapshot(target.level, snapshot).unwrap();
+                    self.wallet.apply_diff(&added, &removed).unwrap();
+                    info!(
+                        "Imported the UTXO snapshot at level {} with {} coins",
+                        target.level,
+                        added.len()
+                    );
+                    return target;
+                }
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    /// Fetch all chunks of the snapshot at the given level, and verify them against the root,
+    /// which `SNAPSHOT_QUORUM` peers and none other must serve.
+    fn fetch(&self, level: u64, root: &H256) -> Option<UtxoAccumulator> {
+        let num_chunks: u32 = 1 << SNAPSHOT_CHUNK_BITS;
+        // drop the chunks left over from earlier attempts
+        for _ in self.chunk_chan.try_iter() {}
+        // ask everyone for the first chunk, and fetch the rest from the first peer that has it
+        self.server
+            .broadcast(Message::GetUtxoSnapshotChunk(level, 0));
+        let mut source: Option<peer::Handle> = None;
+        let mut received: HashSet<u32> = HashSet::new();
+        let mut vouching: HashSet<std::net::SocketAddr> = HashSet::new();
+        let mut snapshot = UtxoAccumulator::new();
+        while received.len() < num_chunks as usize || vouching.len() < SNAPSHOT_QUORUM {
+            let chunk = match self.chunk_chan.recv_timeout(CHUNK_TIMEOUT) {
+                Ok(chunk) => chunk,
+                Err(_) => {
+                    warn!("Timed out fetching the UTXO snapshot at level {}", level);
+                    return None;
+                }
+            };
+            if chunk.level != level || chunk.index >= num_chunks {
+                continue;
+            }
+            let (chunk_root, coins) = match &chunk.coins {
+                Some(coins) => coins,
+                None => continue,
+            };
+            // honest peers agree on the utxo set at a confirmed level, so a peer serving another
+            // root means that either the peer or the leader lies
+            if chunk_root != root {
+                warn!(
+                    "Peer {} serves UTXO root {:.8} at level {}, but the leader commits to {:.8}",
+                    chunk.peer.addr(),
+                    chunk_root,
+                    level,
+                    root
+                );
+                return None;
+            }
+            vouching.insert(chunk.peer.addr());
+            if received.contains(&chunk.index) {
+                continue;
+            }
+            // once a source is chosen, only its chunks are trusted to add up to the root
+            if let Some(source) = &source {
+                if chunk.peer.addr() != source.addr() {
+                    continue;
+                }
+            }
+            if !coins
+                .iter()
+                .all(|(coin, _)| in_chunk(coin, chunk.index, SNAPSHOT_CHUNK_BITS))
+            {
+                warn!("Got coins outside of UTXO snapshot chunk {}", chunk.index);
+                chunk.peer.report_misbehavior();
+                return None;
+            }
+            if source.is_none() {
+                for index in 1..num_chunks {
+                    chunk
+                        .peer
+                        .write(Message::GetUtxoSnapshotChunk(level, index));
+                }
+                source = Some(chunk.peer.clone());
+            }
+            for (coin, output) in coins {
+                snapshot.insert(coin, output);
+            }
+            received.insert(chunk.index);
+        }
+        if snapshot.root() != *root {
+            warn!(
+                "UTXO snapshot at level {} does not match the commitment",
+                level
+            );
+            if let Some(peer) = source {
+                peer.report_misbehavior();
+            }
+            return None;
+        }
+        Some(snapshot)
+    }
+}
+
+/// Find the most recent snapshot level whose UTXO set is committed by the leader of the level
+/// `UTXO_COMMITMENT_DELAY` above it, be it confirmed at least `CONFIRMATION_DEPTH` levels below
+/// the ledger tip or pinned by a checkpoint.
+pub(super) fn target(blockdb: &BlockDatabase, chain: &BlockChain) -> Option<Target> {
+    let ledger_tip = chain.ledger_tip_level();
+    let latest = ledger_tip.checked_sub(UTXO_COMMITMENT_DELAY + CONFIRMATION_DEPTH)?
+        / SNAPSHOT_INTERVAL
+        * SNAPSHOT_INTERVAL;
+    for i in 0..SNAPSHOT_CANDIDATES {
+        let level = latest.checked_sub(i * SNAPSHOT_INTERVAL)?;
+        if level == 0 {
+            return None;
+        }
+        let leader = match chain
+            .proposer_leader(level + UTXO_COMMITMENT_DELAY)
+            .unwrap()
+        {
+            Some(leader) => leader,
+            None => continue,
+        };
+        let block = match blockdb.get(&leader).unwrap() {
+            Some(block) => block,
+            None => continue,
+        };
+        if let Content::Proposer(content) = block.content {
+            if let Some(root) = content.utxo_commitment {
+                return Some(Target {
+                    level,
+                    leader,
+                    root,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Check whether the leader that a snapshot was verified against is still the leader of its
+/// level, or `None` if the ledger does not reach that level.
+pub(super) fn still_committed(chain: &BlockChain, target: &Target) -> Result<Option<bool>> {
+    let leader = chain.proposer_leader(target.level + UTXO_COMMITMENT_DELAY)?;
+    Ok(leader.map(|leader| leader == target.leader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::{committing_proposer_block, proposer_block};
+    use crate::blockchain::confirmation::ConfirmationRuleKind;
+    use crate::blockchain::tests::vote;
+    use crate::config::BlockchainConfig;
+    use crate::crypto::hash::tests::generate_random_hash;
+    use crate::crypto::hash::Hashable;
+
+    #[test]
+    fn target_deeply_confirmed() {
+        let mut config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        let blockdb = BlockDatabase::new(
+            "/tmp/prism_test_fast_sync_target_blockdb.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        let chain = BlockChain::new(
+            "/tmp/prism_test_fast_sync_target_blockchain.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+
+        // the leader of the level committing to the first snapshot level, and the levels
+        // confirming it
+        let leader_level = SNAPSHOT_INTERVAL + UTXO_COMMITMENT_DELAY;
+        let root = generate_random_hash();
+        let mut parent = config.proposer_genesis;
+        let mut proposers = vec![];
+        for level in 1..=leader_level + CONFIRMATION_DEPTH {
+            let block = if level == leader_level {
+                committing_proposer_block(parent, root)
+            } else {
+                proposer_block(parent, 0, vec![], vec![])
+            };
+            blockdb.insert(&block).unwrap();
+            chain.insert_block(&block).unwrap();
+            parent = block.hash();
+            proposers.push(parent);
+        }
+        let leader = proposers[leader_level as usize - 1];
+        assert_eq!(target(&blockdb, &chain), None);
+
+        // vote up to one level short of the confirmation depth
+        let below = vote(
+            &chain,
+            0,
+            config.voter_genesis[0],
+            proposers[..leader_level as usize - 1].to_vec(),
+        );
+        let voter = vote(
+            &chain,
+            0,
+            below,
+            proposers[leader_level as usize - 1..proposers.len() - 1].to_vec(),
+        );
+        chain.update_ledger().unwrap();
+        assert_eq!(target(&blockdb, &chain), None);
+
+        vote(&chain, 0, voter, vec![*proposers.last().unwrap()]);
+        chain.update_ledger().unwrap();
+        let found = target(&blockdb, &chain).unwrap();
+        assert_eq!(
+            found,
+            Target {
+                level: SNAPSHOT_INTERVAL,
+                leader,
+                root
+            }
+        );
+        assert_eq!(still_committed(&chain, &found).unwrap(), Some(true));
+
+        // the voter chain switches to another block at the level of the leader, which stops
+        // committing to the snapshot, and the levels above are no longer confirmed
+        let other = committing_proposer_block(proposers[leader_level as usize - 2], root);
+        blockdb.insert(&other).unwrap();
+        chain.insert_block(&other).unwrap();
+        let fork = vote(&chain, 0, below, vec![other.hash()]);
+        let fork = vote(&chain, 0, fork, vec![]);
+        vote(&chain, 0, fork, vec![]);
+        chain.update_ledger().unwrap();
+        assert_eq!(
+            chain.proposer_leader(leader_level).unwrap(),
+            Some(other.hash())
+        );
+        assert_eq!(still_committed(&chain, &found).unwrap(), Some(false));
+        let next = Target {
+            level: found.level + 1,
+            leader,
+            root,
+        };
+        assert_eq!(still_committed(&chain, &next).unwrap(), None);
+        assert_eq!(target(&blockdb, &chain), None);
+    }
+}
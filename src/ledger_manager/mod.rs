@@ -1,22 +1,36 @@
+mod fast_sync;
+
+pub use self::fast_sync::SnapshotChunk;
+
+use self::fast_sync::{still_committed, Importer, Target};
 use crate::block::Content;
-use crate::blockchain::BlockChain;
+use crate::blockchain::{BlockChain, FastConfirmation, Result};
 use crate::blockdb::BlockDatabase;
+use crate::config::UTXO_COMMITMENT_DELAY;
 use crate::crypto::hash::{Hashable, H256};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::network::server::Handle as ServerHandle;
 
 use crate::transaction::{CoinId, Output, Transaction};
 use crate::utxodb::UtxoDatabase;
 use crate::wallet::Wallet;
-use crossbeam::channel;
-use std::collections::{HashMap, HashSet};
+use crossbeam::{channel, select};
+use log::{debug, error, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two updates of the ledger.
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
 
 pub struct LedgerManager {
     blockdb: Arc<BlockDatabase>,
     chain: Arc<BlockChain>,
     utxodb: Arc<UtxoDatabase>,
     wallet: Arc<Wallet>,
+    /// Source of the UTXO snapshot if fast sync is enabled.
+    fast_sync: Option<(ServerHandle, channel::Receiver<SnapshotChunk>)>,
 }
 
 impl LedgerManager {
@@ -31,112 +45,219 @@ impl LedgerManager {
             chain: Arc::clone(&chain),
             utxodb: Arc::clone(&utxodb),
             wallet: Arc::clone(&wallet),
+            fast_sync: None,
         }
     }
 
+    /// Import the UTXO set from a snapshot served by peers, instead of replaying the ledger from
+    /// genesis.
+    pub fn enable_fast_sync(
+        &mut self,
+        server: &ServerHandle,
+        chunk_chan: channel::Receiver<SnapshotChunk>,
+    ) {
+        self.fast_sync = Some((server.clone(), chunk_chan));
+    }
+
     pub fn start(self, buffer_size: usize, num_workers: usize) {
+        // start thread that imports the utxo snapshot
+        let mut syncing = false;
+        let (imported_tx, imported_rx) = channel::bounded(1);
+        let (reimport_tx, reimport_rx) = channel::bounded(1);
+        if let Some((server, chunk_chan)) = self.fast_sync {
+            syncing = true;
+            let importer = Importer {
+                blockdb: Arc::clone(&self.blockdb),
+                chain: Arc::clone(&self.chain),
+                utxodb: Arc::clone(&self.utxodb),
+                wallet: Arc::clone(&self.wallet),
+                server,
+                chunk_chan,
+            };
+            thread::spawn(move || {
+                loop {
+                    let target = importer.run();
+                    imported_tx.send(target).unwrap();
+                    // the ledger thread asks for another snapshot if the leader committing to
+                    // this one is reverted, and hangs up once it accepts it
+                    if reimport_rx.recv().is_err() {
+                        break;
+                    }
+                }
+                // drop the chunks that arrive late
+                for _ in importer.chunk_chan.iter() {}
+            });
+        }
+
+        // start thread that records fast confirmations
+        let blockdb = Arc::clone(&self.blockdb);
+        let fast_confirmations = self.chain.subscribe_fast_confirmations();
+        thread::spawn(move || loop {
+            match fast_confirmations.recv().unwrap() {
+                FastConfirmation::Confirmed(hash, level) => {
+                    debug!(
+                        "Transaction block {:.8} confirmed by list decoding at level {}",
+                        hash, level
+                    );
+                    if let Some(block) = blockdb.get(&hash).unwrap() {
+                        PERFORMANCE_COUNTER.record_fast_confirm_transaction_block(&block);
+                    }
+                }
+                FastConfirmation::Revoked(hash) => {
+                    debug!("Transaction block {:.8} lost its fast confirmation", hash);
+                }
+            }
+        });
+
         // start thread that updates transaction sequence
         let blockdb = Arc::clone(&self.blockdb);
         let chain = Arc::clone(&self.chain);
         let (tx_diff_tx, tx_diff_rx) = channel::bounded(buffer_size);
+        let ledger_signal = chain.ledger_signal();
+        let mut imported: Option<Target> = None;
+        let mut reimport_tx = Some(reimport_tx);
         thread::spawn(move || loop {
-            let tx_diff = update_transaction_sequence(&blockdb, &chain);
-            tx_diff_tx.send(tx_diff).unwrap();
+            // wait until a tip changes, and do not update more often than the minimum interval
+            // so that changes arriving in the meantime are handled together
+            ledger_signal.recv().unwrap();
+            let started = Instant::now();
+            if syncing {
+                // until the utxo snapshot is imported and the ledger reaches it, only follow the
+                // ledger without applying it. then replay the ledger above the snapshot
+                if let Err(e) = chain.update_ledger() {
+                    error!("Failed to update the ledger: {}", e);
+                }
+                if imported.is_none() {
+                    imported = imported_rx.try_recv().ok();
+                }
+                if let Some(target) = &imported {
+                    // the snapshot is only as good as the leader that commits to it, which must
+                    // still lead its level when the snapshot level becomes final
+                    match still_committed(&chain, target) {
+                        Ok(Some(true)) => {
+                            let level = target.level;
+                            chain.finalize_ledger(level);
+                            match replay_transaction_sequence(&blockdb, &chain, level + 1) {
+                                Ok(tx_diff) => {
+                                    tx_diff_tx
+                                        .send((tx_diff, chain.ledger_tip_level()))
+                                        .unwrap();
+                                    syncing = false;
+                                    reimport_tx = None;
+                                }
+                                Err(e) => error!("Failed to replay the ledger: {}", e),
+                            }
+                        }
+                        Ok(Some(false)) => {
+                            warn!(
+                                "The leader committing to the UTXO snapshot at level {} was reverted",
+                                target.level
+                            );
+                            imported = None;
+                            reimport_tx.as_ref().unwrap().send(()).unwrap();
+                        }
+                        Ok(None) => {}
+                        Err(e) => error!("Failed to check the UTXO snapshot: {}", e),
+                    }
+                }
+            } else {
+                match update_transaction_sequence(&blockdb, &chain) {
+                    Ok(tx_diff) => tx_diff_tx
+                        .send((tx_diff, chain.ledger_tip_level()))
+                        .unwrap(),
+                    Err(e) => error!("Failed to update the ledger: {}", e),
+                }
+            }
+            let elapsed = started.elapsed();
+            if elapsed < MIN_UPDATE_INTERVAL {
+                thread::sleep(MIN_UPDATE_INTERVAL - elapsed);
+            }
         });
 
         // start thread that dispatches jobs to utxo manager
-        let _utxodb = Arc::clone(&self.utxodb);
-        // Scoreboard notes the transaction ID of the coins that is being looked up, may be added,
-        // or may be deleted. Before dispatching a transaction, we first check whether the input
-        // and output are used by transactions being processed. If no, we will dispatch this
-        // transaction. Otherwise, we will wait until this situation clears. This prevents Read
-        // After Write (must ins/del then check), Write After Read (must check then ins/del), and
-        // Write After Write (must ins then del) hazards. We can also do this at CoinId level, but
-        // doing this at transaction hash level should be pretty sufficient.
-        let mut scoreboard: HashSet<H256> = HashSet::new();
-        // Transaction coins keeps the mapping between transaction ID and the entries in the
-        // scoreboard that this transaction is responsible for.
-        let mut transaction_coins: HashMap<H256, Vec<H256>> = HashMap::new();
         let (transaction_tx, transaction_rx) = channel::bounded(buffer_size * num_workers);
         let (notification_tx, notification_rx) = channel::unbounded();
         let (coin_diff_tx, coin_diff_rx) = channel::unbounded();
-
+        let (prune_tx, prune_rx) = channel::bounded(1);
+        let mut dispatcher = Dispatcher {
+            blockdb: Arc::clone(&self.blockdb),
+            chain: Arc::clone(&self.chain),
+            utxodb: Arc::clone(&self.utxodb),
+            transaction_chan: transaction_tx,
+            notification_chan: notification_rx.clone(),
+            scoreboard: HashSet::new(),
+            transaction_coins: HashMap::new(),
+            pending: VecDeque::new(),
+            first_pending: 0,
+        };
         thread::spawn(move || {
+            // the ledger tip when the last diff was dispatched
+            let mut dispatched_level: u64 = 0;
             loop {
-                // get the diff
-                let (mut added_tx, mut removed_tx) = tx_diff_rx.recv().unwrap();
-
-                // dispatch transactions
-                for (t, h) in removed_tx.drain(..).rev() {
-                    // drain the notification channel so that we mark all finished transaction as
-                    // finished
-                    for processed in notification_rx.try_iter() {
-                        let finished_coins = transaction_coins.remove(&processed).unwrap();
-                        for hash in &finished_coins {
-                            scoreboard.remove(&hash);
-                        }
-                    }
+                // once every dispatched transaction is applied, the utxo set reflects the ledger
+                // up to the last dispatched tip, and the blocks below it can be pruned
+                if dispatcher.is_idle() {
+                    prune_tx.try_send(dispatched_level).ok();
+                }
 
-                    // collect the tx hash of all coins this tx will touch
-                    let mut touched_coin_transaction_hash: HashSet<H256> = HashSet::new();
-                    touched_coin_transaction_hash.insert(h); // the transaction hash of all output coins
-                    for input in &t.input {
-                        touched_coin_transaction_hash.insert(input.coin.hash); // tx hash of input coin
+                // apply the transactions processed in the meantime until we get the next diff
+                let ((mut added_tx, mut removed_tx), level) = select! {
+                    recv(notification_rx) -> processed => {
+                        let (hash, diff) = processed.unwrap();
+                        dispatcher.finish(hash, diff);
+                        continue;
                     }
+                    recv(tx_diff_rx) -> tx_diff => tx_diff.unwrap(),
+                };
+                dispatched_level = level;
 
-                    // wait until we are not touching hot coins
-                    while !scoreboard.is_disjoint(&touched_coin_transaction_hash) {
-                        let processed = notification_rx.recv().unwrap();
-                        let finished_coins = transaction_coins.remove(&processed).unwrap();
-                        for hash in &finished_coins {
-                            scoreboard.remove(&hash);
-                        }
-                    }
+                // the commitments from the first level that changed are no longer valid
+                let change_begin = match added_tx.first() {
+                    Some((l, _)) => *l,
+                    None => level + 1,
+                };
+                dispatcher.forget_commitments(change_begin);
 
-                    // mark the coins that we will be touching as hot
-                    let mut touched: Vec<H256> = vec![];
-                    for hash in touched_coin_transaction_hash.drain() {
-                        touched.push(hash);
-                        scoreboard.insert(hash);
-                    }
-                    transaction_coins.insert(h, touched);
-                    transaction_tx.send((false, t, h)).unwrap();
+                // dispatch transactions
+                dispatcher.begin_group(None);
+                for (t, h) in removed_tx.drain(..).rev() {
+                    dispatcher.dispatch(false, t, h);
                 }
-                for (t, h) in added_tx.drain(..) {
-                    // drain the notification channel so that we mark all finished transaction as
-                    // finished
-                    for processed in notification_rx.try_iter() {
-                        let finished_coins = transaction_coins.remove(&processed).unwrap();
-                        for hash in &finished_coins {
-                            scoreboard.remove(&hash);
-                        }
+                dispatcher.seal_group();
+                for (level, mut level_tx) in added_tx.drain(..) {
+                    // the level is committed to once its transactions and those before are
+                    // applied, while we go on with the next levels
+                    dispatcher.begin_group(Some(level));
+                    for (t, h) in level_tx.drain(..) {
+                        dispatcher.dispatch(true, t, h);
                     }
-
-                    // collect the tx hash of all coins this tx will touch
-                    let mut touched_coin_transaction_hash: HashSet<H256> = HashSet::new();
-                    touched_coin_transaction_hash.insert(h); // the transaction hash of all output coins
-                    for input in &t.input {
-                        touched_coin_transaction_hash.insert(input.coin.hash); // tx hash of input coin
-                    }
-
-                    // wait until we are not touching hot coins
-                    while !scoreboard.is_disjoint(&touched_coin_transaction_hash) {
-                        let processed = notification_rx.recv().unwrap();
-                        let finished_coins = transaction_coins.remove(&processed).unwrap();
-                        for hash in &finished_coins {
-                            scoreboard.remove(&hash);
-                        }
+                    dispatcher.seal_group();
+                    // the leader of this level commits to an earlier level, which may already
+                    // be applied
+                    if let Some(committed_level) = level.checked_sub(UTXO_COMMITMENT_DELAY) {
+                        check_commitment(
+                            &dispatcher.blockdb,
+                            &dispatcher.chain,
+                            &dispatcher.utxodb,
+                            committed_level,
+                        );
                     }
+                }
+            }
+        });
 
-                    // mark the coins that we will be touching as hot
-                    let mut touched: Vec<H256> = vec![];
-                    for hash in touched_coin_transaction_hash.drain() {
-                        touched.push(hash);
-                        scoreboard.insert(hash);
+        // start thread that prunes the blocks reflected in the utxo set
+        let blockdb = Arc::clone(&self.blockdb);
+        let chain = Arc::clone(&self.chain);
+        thread::spawn(move || loop {
+            let level = prune_rx.recv().unwrap();
+            match chain.prune(level) {
+                Ok(hashes) => {
+                    for hash in hashes {
+                        blockdb.prune(&hash).unwrap();
                     }
-                    transaction_coins.insert(h, touched);
-                    transaction_tx.send((true, t, h)).unwrap();
                 }
+                Err(e) => error!("Failed to prune the blockchain: {}", e),
             }
         });
 
@@ -164,9 +285,10 @@ struct UtxoManager {
     /// Channel for dispatching jobs (add/delete, transaction, hash of transaction).
     transaction_chan: channel::Receiver<(bool, Transaction, H256)>,
     /// Channel for returning added and removed coins.
-    coin_chan: channel::Sender<(Vec<(CoinId, Output)>, Vec<CoinId>)>,
-    /// Channel for notifying the dispatcher about the completion of processing this transaction.
-    notification_chan: channel::Sender<H256>,
+    coin_chan: channel::Sender<CoinDiff>,
+    /// Channel for notifying the dispatcher about the completion of processing this transaction,
+    /// with the coins it added and removed.
+    notification_chan: channel::Sender<(H256, CoinDiff)>,
 }
 
 impl UtxoManager {
@@ -182,31 +304,213 @@ impl UtxoManager {
     fn worker_loop(&self) {
         loop {
             let (add, transaction, hash) = self.transaction_chan.recv().unwrap();
-            if add {
-                let diff = self.utxodb.add_transaction(&transaction, hash).unwrap();
-                self.coin_chan.send(diff).unwrap();
+            let diff = if add {
+                self.utxodb.add_transaction(&transaction, hash).unwrap()
             } else {
-                let diff = self.utxodb.remove_transaction(&transaction, hash).unwrap();
-                self.coin_chan.send(diff).unwrap();
+                self.utxodb.remove_transaction(&transaction, hash).unwrap()
+            };
+            self.coin_chan.send(diff.clone()).unwrap();
+            self.notification_chan.send((hash, diff)).unwrap();
+        }
+    }
+}
+
+/// Coins added and removed by a transaction.
+type CoinDiff = (Vec<(CoinId, Output)>, Vec<CoinId>);
+
+/// Coin updates of a group of dispatched transactions. They are applied to the accumulator over
+/// the UTXO set together, once the group and all groups dispatched before it are processed.
+struct PendingUpdate {
+    /// Proposer level to commit to after the update, or `None` for transactions removed from the
+    /// ledger and for levels that changed since.
+    level: Option<u64>,
+    /// Whether all transactions of the group are dispatched.
+    sealed: bool,
+    /// Number of transactions of the group being processed.
+    outstanding: usize,
+    /// Coins added and removed by the processed transactions, in the order they were processed.
+    diffs: Vec<CoinDiff>,
+}
+
+/// Dispatches transactions to the utxo manager.
+///
+/// Scoreboard notes the transaction ID of the coins that is being looked up, may be added, or may
+/// be deleted. Before dispatching a transaction, we first check whether the input and output are
+/// used by transactions being processed. If no, we will dispatch this transaction. Otherwise, we
+/// will wait until this situation clears. This prevents Read After Write (must ins/del then
+/// check), Write After Read (must check then ins/del), and Write After Write (must ins then del)
+/// hazards. We can also do this at CoinId level, but doing this at transaction hash level should
+/// be pretty sufficient.
+struct Dispatcher {
+    blockdb: Arc<BlockDatabase>,
+    chain: Arc<BlockChain>,
+    utxodb: Arc<UtxoDatabase>,
+    transaction_chan: channel::Sender<(bool, Transaction, H256)>,
+    notification_chan: channel::Receiver<(H256, CoinDiff)>,
+    scoreboard: HashSet<H256>,
+    /// Group of each transaction being processed, and the entries in the scoreboard that it is
+    /// responsible for.
+    transaction_coins: HashMap<H256, (u64, Vec<H256>)>,
+    /// Groups whose coin updates are not applied to the accumulator yet, oldest first.
+    pending: VecDeque<PendingUpdate>,
+    /// Sequence number of the oldest pending group.
+    first_pending: u64,
+}
+
+impl Dispatcher {
+    /// Start a group of transactions, which commits to the given level once applied.
+    fn begin_group(&mut self, level: Option<u64>) {
+        self.pending.push_back(PendingUpdate {
+            level,
+            sealed: false,
+            outstanding: 0,
+            diffs: vec![],
+        });
+    }
+
+    /// Mark all transactions of the current group as dispatched.
+    fn seal_group(&mut self) {
+        self.pending.back_mut().unwrap().sealed = true;
+        self.apply_finished_groups();
+    }
+
+    /// Dispatch a transaction of the current group once it does not touch hot coins.
+    fn dispatch(&mut self, add: bool, t: Transaction, h: H256) {
+        // mark all finished transaction as finished
+        while let Ok((processed, diff)) = self.notification_chan.try_recv() {
+            self.finish(processed, diff);
+        }
+
+        // collect the tx hash of all coins this tx will touch
+        let mut touched_coin_transaction_hash: HashSet<H256> = HashSet::new();
+        touched_coin_transaction_hash.insert(h); // the transaction hash of all output coins
+        for input in &t.input {
+            touched_coin_transaction_hash.insert(input.coin.hash); // tx hash of input coin
+        }
+
+        // wait until we are not touching hot coins
+        while !self.scoreboard.is_disjoint(&touched_coin_transaction_hash) {
+            let (processed, diff) = self.notification_chan.recv().unwrap();
+            self.finish(processed, diff);
+        }
+
+        // mark the coins that we will be touching as hot
+        let mut touched: Vec<H256> = vec![];
+        for hash in touched_coin_transaction_hash.drain() {
+            touched.push(hash);
+            self.scoreboard.insert(hash);
+        }
+        let group = self.first_pending + self.pending.len() as u64 - 1;
+        self.pending.back_mut().unwrap().outstanding += 1;
+        self.transaction_coins.insert(h, (group, touched));
+        self.transaction_chan.send((add, t, h)).unwrap();
+    }
+
+    /// Mark a transaction as processed.
+    fn finish(&mut self, hash: H256, diff: CoinDiff) {
+        let (group, finished_coins) = self.transaction_coins.remove(&hash).unwrap();
+        for hash in &finished_coins {
+            self.scoreboard.remove(hash);
+        }
+        let update = &mut self.pending[(group - self.first_pending) as usize];
+        update.outstanding -= 1;
+        update.diffs.push(diff);
+        self.apply_finished_groups();
+    }
+
+    /// Apply the oldest groups that are processed to the accumulator, and commit to the UTXO set
+    /// at their levels.
+    fn apply_finished_groups(&mut self) {
+        while let Some(update) = self.pending.front() {
+            if !update.sealed || update.outstanding > 0 {
+                break;
+            }
+            let update = self.pending.pop_front().unwrap();
+            self.first_pending += 1;
+            self.utxodb.update_accumulator(&update.diffs);
+            if let Some(level) = update.level {
+                self.utxodb.record_commitment(level);
+                check_commitment(&self.blockdb, &self.chain, &self.utxodb, level);
+            }
+        }
+    }
+
+    /// Forget the commitments of the given proposer level and above, including those of the
+    /// groups not applied yet.
+    fn forget_commitments(&mut self, level: u64) {
+        for update in self.pending.iter_mut() {
+            if update.level.map_or(false, |l| l >= level) {
+                update.level = None;
             }
-            self.notification_chan.send(hash).unwrap();
         }
+        self.utxodb.forget_commitments(level);
     }
+
+    /// Check whether all dispatched transactions are applied.
+    fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Compare our commitment to the UTXO set at the given proposer level with the one in the leader
+/// `UTXO_COMMITMENT_DELAY` levels above, if both are known, and count a mismatch. Return whether
+/// they match.
+fn check_commitment(
+    blockdb: &BlockDatabase,
+    chain: &BlockChain,
+    utxodb: &UtxoDatabase,
+    level: u64,
+) -> Option<bool> {
+    let root = utxodb.commitment_at(level)?;
+    let leader = match chain.proposer_leader(level + UTXO_COMMITMENT_DELAY) {
+        Ok(Some(leader)) => leader,
+        _ => return None,
+    };
+    let committed = match blockdb.get(&leader).unwrap()?.content {
+        Content::Proposer(content) => content.utxo_commitment?,
+        _ => unreachable!(),
+    };
+    if committed != root {
+        warn!(
+            "Proposer block {:.8} commits to UTXO root {:.8} at level {}, but ours is {:.8}",
+            leader, committed, level, root
+        );
+        PERFORMANCE_COUNTER.record_utxo_commitment_mismatch();
+        return Some(false);
+    }
+    Some(true)
 }
 
+/// Transactions added to the ledger, grouped by proposer level, and those removed from it.
+type TransactionDiff = (
+    Vec<(u64, Vec<(Transaction, H256)>)>,
+    Vec<(Transaction, H256)>,
+);
+
 fn update_transaction_sequence(
     blockdb: &BlockDatabase,
     chain: &BlockChain,
-) -> (Vec<(Transaction, H256)>, Vec<(Transaction, H256)>) {
-    let diff = chain.update_ledger().unwrap();
+) -> Result<TransactionDiff> {
+    let diff = chain.update_ledger()?;
     PERFORMANCE_COUNTER.record_deconfirm_transaction_blocks(diff.1.len());
+    if let Err(e) = chain.fast_confirm() {
+        warn!("Failed to fast confirm transaction blocks: {}", e);
+    }
 
     // gather the transaction diff
-    let mut add: Vec<(Transaction, H256)> = vec![];
+    let mut add: Vec<(u64, Vec<(Transaction, H256)>)> = vec![];
     let mut remove: Vec<(Transaction, H256)> = vec![];
-    for hash in diff.0 {
-        let block = blockdb.get(&hash).unwrap().unwrap();
-        PERFORMANCE_COUNTER.record_confirm_transaction_block(&block);
+    for (level, hashes) in diff.0 {
+        add.push((level, confirmed_transactions(blockdb, &hashes)?));
+    }
+    for hash in diff.1 {
+        let block = match blockdb.get(&hash)? {
+            Some(block) => block,
+            None => {
+                warn!("Skipping pruned transaction block {:.8}", hash);
+                continue;
+            }
+        };
         let content = match block.content {
             Content::Transaction(data) => data,
             _ => unreachable!(),
@@ -216,14 +520,42 @@ fn update_transaction_sequence(
             .iter()
             .map(|t| (t.clone(), t.hash()))
             .collect();
-        // TODO: precompute the hash here. Note that although lazy-eval for tx hash, and we could have
-        // just called hash() here without storing the results (the results will be cached in the struct),
-        // such function call will be optimized away by LLVM. As a result, we have to manually pass the hash
-        // here. The same for added transactions below. This is a very ugly hack.
-        add.append(&mut transactions);
+        remove.append(&mut transactions);
     }
-    for hash in diff.1 {
-        let block = blockdb.get(&hash).unwrap().unwrap();
+    Ok((add, remove))
+}
+
+/// Gather the transactions in the ledger from the given proposer level up to the ledger tip.
+fn replay_transaction_sequence(
+    blockdb: &BlockDatabase,
+    chain: &BlockChain,
+    from: u64,
+) -> Result<TransactionDiff> {
+    let mut add: Vec<(u64, Vec<(Transaction, H256)>)> = vec![];
+    for level in from..=chain.ledger_tip_level() {
+        let hashes = chain.ledger_transaction_blocks(level)?;
+        add.push((level, confirmed_transactions(blockdb, &hashes)?));
+    }
+    Ok((add, vec![]))
+}
+
+/// Get the transactions in the given transaction blocks that entered the ledger. The blocks
+/// whose bodies are pruned are skipped: pruning only drops blocks confirmed at final levels, so
+/// their transactions are already applied.
+fn confirmed_transactions(
+    blockdb: &BlockDatabase,
+    hashes: &[H256],
+) -> Result<Vec<(Transaction, H256)>> {
+    let mut add: Vec<(Transaction, H256)> = vec![];
+    for hash in hashes {
+        let block = match blockdb.get(hash)? {
+            Some(block) => block,
+            None => {
+                warn!("Skipping pruned transaction block {:.8}", hash);
+                continue;
+            }
+        };
+        PERFORMANCE_COUNTER.record_confirm_transaction_block(&block);
         let content = match block.content {
             Content::Transaction(data) => data,
             _ => unreachable!(),
@@ -233,7 +565,210 @@ fn update_transaction_sequence(
             .iter()
             .map(|t| (t.clone(), t.hash()))
             .collect();
-        remove.append(&mut transactions);
+        // TODO: precompute the hash here. Note that although lazy-eval for tx hash, and we could have
+        // just called hash() here without storing the results (the results will be cached in the struct),
+        // such function call will be optimized away by LLVM. As a result, we have to manually pass the hash
+        // here. The same for removed transactions. This is a very ugly hack.
+        add.append(&mut transactions);
+    }
+    Ok(add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::{committing_proposer_block, proposer_block, transaction_block};
+    use crate::blockchain::confirmation::ConfirmationRuleKind;
+    use crate::blockchain::tests::{propose, vote};
+    use crate::config::BlockchainConfig;
+    use crate::crypto::hash::tests::generate_random_hash;
+    use crate::transaction::tests::{input, transaction};
+    use crate::utxodb::accumulator::UtxoAccumulator;
+
+    #[test]
+    fn pruned_transaction_block_referred_again() {
+        let mut config = BlockchainConfig::new(3, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        config.pruning_depth = Some(1);
+        let blockdb = BlockDatabase::new(
+            "/tmp/prism_test_ledger_manager_pruned_blockdb.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        let chain = BlockChain::new(
+            "/tmp/prism_test_ledger_manager_pruned_blockchain.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        let genesis = config.proposer_genesis;
+
+        let t = transaction(vec![input(1, 10)], &[10]);
+        let tx_block = transaction_block(genesis, 0, vec![t.clone()]);
+        let tx_hash = tx_block.hash();
+        blockdb.insert(&tx_block).unwrap();
+        chain.insert_block(&tx_block).unwrap();
+
+        let p1 = propose(&chain, genesis, vec![], vec![tx_hash]);
+        let p2 = propose(&chain, p1, vec![], vec![]);
+        let p3 = propose(&chain, p2, vec![], vec![]);
+        let voters: Vec<H256> = (0..3)
+            .map(|i| {
+                vote(
+                    &chain,
+                    i,
+                    config.voter_genesis[i as usize],
+                    vec![p1, p2, p3],
+                )
+            })
+            .collect();
+        let (added, _) = update_transaction_sequence(&blockdb, &chain).unwrap();
+        let level_1: Vec<H256> = added[0].1.iter().map(|(_, hash)| *hash).collect();
+        assert_eq!((added[0].0, level_1), (1, vec![t.hash()]));
+
+        // level 1 is pruned once the ledger up to level 3 is applied
+        assert_eq!(chain.prune(3).unwrap(), vec![tx_hash]);
+        blockdb.prune(&tx_hash).unwrap();
+
+        // the leader of level 4 refers to the pruned block again
+        let p4a = propose(&chain, p3, vec![], vec![tx_hash]);
+        let p4b = propose(&chain, p3, vec![], vec![]);
+        vote(&chain, 0, voters[0], vec![p4a]);
+        vote(&chain, 1, voters[1], vec![p4a]);
+        vote(&chain, 2, voters[2], vec![p4b]);
+        let diff = update_transaction_sequence(&blockdb, &chain).unwrap();
+        assert_eq!(chain.proposer_leader(4).unwrap(), Some(p4a));
+        assert_eq!(diff, (vec![(4, vec![])], vec![]));
+
+        // a voter chain switches to the other block of level 4, which takes the pruned block out
+        // of the ledger again
+        let fork = vote(&chain, 0, voters[0], vec![p4b]);
+        vote(&chain, 0, fork, vec![]);
+        let diff = update_transaction_sequence(&blockdb, &chain).unwrap();
+        assert_eq!(chain.proposer_leader(4).unwrap(), Some(p4b));
+        assert_eq!(diff, (vec![(4, vec![])], vec![]));
+    }
+
+    #[test]
+    fn leader_with_wrong_commitment() {
+        let mut config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        let blockdb = BlockDatabase::new(
+            "/tmp/prism_test_ledger_manager_commitment_blockdb.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        let chain = BlockChain::new(
+            "/tmp/prism_test_ledger_manager_commitment_blockchain.rocksdb",
+            config.clone(),
+        )
+        .unwrap();
+        let utxodb =
+            UtxoDatabase::new("/tmp/prism_test_ledger_manager_commitment_utxodb.rocksdb").unwrap();
+
+        // commit to the utxo set at levels 1 and 2, with a coin added in between
+        utxodb.record_commitment(1);
+        let coin = CoinId {
+            hash: generate_random_hash(),
+            index: 0,
+        };
+        let output = Output {
+            value: 10,
+            recipient: H256::default(),
+        };
+        utxodb.add_coin(&coin, &output).unwrap();
+        utxodb.record_commitment(2);
+        let first_root = utxodb.commitment_at(1).unwrap();
+        assert_ne!(utxodb.commitment_at(2), Some(first_root));
+
+        // the leaders of the levels committing to levels 1 and 2 both commit to the first root
+        let mut parent = config.proposer_genesis;
+        let mut proposers = vec![];
+        for level in 1..=UTXO_COMMITMENT_DELAY + 2 {
+            let block = if level > UTXO_COMMITMENT_DELAY {
+                committing_proposer_block(parent, first_root)
+            } else {
+                proposer_block(parent, 0, vec![], vec![])
+            };
+            blockdb.insert(&block).unwrap();
+            chain.insert_block(&block).unwrap();
+            parent = block.hash();
+            proposers.push(parent);
+        }
+        vote(&chain, 0, config.voter_genesis[0], proposers);
+        chain.update_ledger().unwrap();
+
+        assert_eq!(check_commitment(&blockdb, &chain, &utxodb, 1), Some(true));
+        assert_eq!(check_commitment(&blockdb, &chain, &utxodb, 2), Some(false));
+        // no commitment recorded at level 3, and no leader commits to it
+        assert_eq!(check_commitment(&blockdb, &chain, &utxodb, 3), None);
+    }
+
+    #[test]
+    fn levels_committed_in_order() {
+        let config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        let (transaction_tx, transaction_rx) = channel::unbounded();
+        let (_notification_tx, notification_rx) = channel::unbounded();
+        let mut dispatcher = Dispatcher {
+            blockdb: Arc::new(
+                BlockDatabase::new(
+                    "/tmp/prism_test_ledger_manager_dispatcher_blockdb.rocksdb",
+                    config.clone(),
+                )
+                .unwrap(),
+            ),
+            chain: Arc::new(
+                BlockChain::new(
+                    "/tmp/prism_test_ledger_manager_dispatcher_blockchain.rocksdb",
+                    config,
+                )
+                .unwrap(),
+            ),
+            utxodb: Arc::new(
+                UtxoDatabase::new("/tmp/prism_test_ledger_manager_dispatcher_utxodb.rocksdb")
+                    .unwrap(),
+            ),
+            transaction_chan: transaction_tx,
+            notification_chan: notification_rx,
+            scoreboard: HashSet::new(),
+            transaction_coins: HashMap::new(),
+            pending: VecDeque::new(),
+            first_pending: 0,
+        };
+
+        // dispatch a transaction at each of levels 1 and 2, and a level without any
+        let first = transaction(vec![], &[10]);
+        let second = transaction(vec![], &[20]);
+        for (level, t) in vec![(1, first.clone()), (2, second.clone())] {
+            dispatcher.begin_group(Some(level));
+            dispatcher.dispatch(true, t.clone(), t.hash());
+            dispatcher.seal_group();
+        }
+        dispatcher.begin_group(Some(3));
+        dispatcher.seal_group();
+
+        // the transaction of level 2 finishes first, which commits to nothing yet
+        let jobs: Vec<(bool, Transaction, H256)> = transaction_rx.try_iter().collect();
+        assert_eq!(jobs.len(), 2);
+        let utxodb = Arc::clone(&dispatcher.utxodb);
+        for (_, t, hash) in jobs.into_iter().rev() {
+            let diff = utxodb.add_transaction(&t, hash).unwrap();
+            dispatcher.finish(hash, diff);
+            if hash == second.hash() {
+                assert_eq!(utxodb.commitment_at(1), None);
+                assert_eq!(utxodb.commitment_at(2), None);
+            }
+        }
+        assert!(dispatcher.is_idle());
+
+        let mut accumulator = UtxoAccumulator::new();
+        let coin = |t: &Transaction| CoinId {
+            hash: t.hash(),
+            index: 0,
+        };
+        accumulator.insert(&coin(&first), &first.output[0]);
+        assert_eq!(utxodb.commitment_at(1), Some(accumulator.root()));
+        accumulator.insert(&coin(&second), &second.output[0]);
+        assert_eq!(utxodb.commitment_at(2), Some(accumulator.root()));
+        assert_eq!(utxodb.commitment_at(3), Some(accumulator.root()));
     }
-    (add, remove)
 }
@@ -15,6 +15,7 @@ pub mod crypto;
 pub mod experiment;
 pub mod handler;
 pub mod ledger_manager;
+pub mod light_client;
 pub mod miner;
 pub mod network;
 pub mod transaction;
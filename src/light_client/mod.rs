@@ -0,0 +1,207 @@
+mod proof;
+
+pub use self::proof::{prove_transactions, TransactionProof};
+
+use crate::blockchain::BlockChain;
+use crate::config::BlockchainConfig;
+use crate::crypto::hash::{Hashable, H256};
+use crate::network::message::Message;
+use crate::network::peer;
+use crate::network::server::Handle as ServerHandle;
+use crate::transaction::{CoinId, Output, Transaction};
+use crate::wallet::Wallet;
+use crossbeam::channel;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time to wait for a peer to prove the transactions of a level.
+const PROOF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Proofs of the transactions at a proposer level, received from a peer.
+pub struct TransactionProofs {
+    pub peer: peer::Handle,
+    pub level: u64,
+    /// The leader of the level and the proofs, or `None` if the peer cannot prove the level.
+    pub proofs: Option<(H256, Vec<TransactionProof>)>,
+}
+
+/// Follows the ledger with only block headers and voter content, and keeps the wallet up to date
+/// with the transactions touching its addresses, as proven by full nodes.
+pub struct LightClient {
+    chain: Arc<BlockChain>,
+    wallet: Arc<Wallet>,
+    server: ServerHandle,
+    proof_chan: channel::Receiver<TransactionProofs>,
+    config: BlockchainConfig,
+}
+
+impl LightClient {
+    pub fn new(
+        chain: &Arc<BlockChain>,
+        wallet: &Arc<Wallet>,
+        server: &ServerHandle,
+        proof_chan: channel::Receiver<TransactionProofs>,
+        config: BlockchainConfig,
+    ) -> Self {
+        Self {
+            chain: Arc::clone(chain),
+            wallet: Arc::clone(wallet),
+            server: server.clone(),
+            proof_chan,
+            config,
+        }
+    }
+
+    pub fn start(self) {
+        thread::spawn(move || {
+            let ledger_signal = self.chain.ledger_signal();
+            // the leader and the proven transactions of each level, kept to roll back the wallet
+            // when the leader changes
+            let mut proven: BTreeMap<u64, (H256, Vec<Transaction>)> = BTreeMap::new();
+            loop {
+                ledger_signal.recv().unwrap();
+                self.chain.update_ledger().unwrap();
+
+                // roll back the levels from the first one whose leader has changed
+                let changed = proven
+                    .iter()
+                    .find(|(level, (leader, _))| {
+                        self.chain.proposer_leader(**level).unwrap() != Some(*leader)
+                    })
+                    .map(|(level, _)| *level);
+                if let Some(level) = changed {
+                    info!("Rolling back the transactions proven since level {}", level);
+                    for (_, (_, transactions)) in proven.split_off(&level).iter().rev() {
+                        self.rollback(transactions);
+                    }
+                }
+
+                // prove the newly confirmed levels
+                let mut level = match proven.keys().next_back() {
+                    Some(level) => level + 1,
+                    None => 1,
+                };
+                while level <= self.chain.ledger_tip_level() {
+                    match self.fetch(level) {
+                        Some((leader, transactions)) => {
+                            self.apply(&transactions);
+                            proven.insert(level, (leader, transactions));
+                        }
+                        None => break,
+                    }
+                    level += 1;
+                }
+            }
+        });
+    }
+
+    /// Ask peers for the transactions at the given level touching our addresses, and return the
+    /// leader and the transactions proven by all the valid answers agreeing with our leader, so
+    /// that no single peer can hide transactions from us.
+    fn fetch(&self, level: u64) -> Option<(H256, Vec<Transaction>)> {
+        let leader = self.chain.proposer_leader(level).unwrap()?;
+        let confirmed: HashSet<H256> = self
+            .chain
+            .ledger_proposers(level)
+            .unwrap()
+            .into_iter()
+            .collect();
+        let addresses = self.wallet.addresses().unwrap();
+        let mut unanswered = self.server.peers().len();
+        self.server
+            .broadcast(Message::GetTransactionProofs(level, addresses));
+        let started = Instant::now();
+        let mut proven = false;
+        let mut hashes: HashSet<H256> = HashSet::new();
+        let mut transactions: Vec<Transaction> = vec![];
+        // wait until every peer answers or the timeout
+        while unanswered > 0 {
+            let elapsed = started.elapsed();
+            if elapsed >= PROOF_TIMEOUT {
+                warn!("Timed out fetching transaction proofs at level {}", level);
+                break;
+            }
+            let response = match self.proof_chan.recv_timeout(PROOF_TIMEOUT - elapsed) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            if response.level != level {
+                continue;
+            }
+            unanswered -= 1;
+            let (their_leader, proofs) = match response.proofs {
+                Some(proofs) => proofs,
+                None => continue,
+            };
+            // the peer may not have confirmed the same leader yet
+            if their_leader != leader {
+                continue;
+            }
+            if !proofs
+                .iter()
+                .all(|p| p.verify(&leader, &confirmed, &self.config))
+            {
+                warn!("Got invalid transaction proofs at level {}", level);
+                response.peer.report_misbehavior();
+                continue;
+            }
+            proven = true;
+            for proof in proofs {
+                if hashes.insert(proof.transaction.hash()) {
+                    transactions.push(proof.transaction);
+                }
+            }
+        }
+        // give up for now if no peer can prove the level, and retry on the next ledger update
+        if !proven {
+            return None;
+        }
+        Some((leader, transactions))
+    }
+
+    /// Apply proven transactions to the wallet.
+    fn apply(&self, transactions: &[Transaction]) {
+        let mut add: Vec<(CoinId, Output)> = vec![];
+        let mut remove: Vec<CoinId> = vec![];
+        for transaction in transactions {
+            let hash = transaction.hash();
+            for (index, output) in transaction.output.iter().enumerate() {
+                let coin = CoinId {
+                    hash,
+                    index: index as u32,
+                };
+                add.push((coin, *output));
+            }
+            for input in &transaction.input {
+                remove.push(input.coin);
+            }
+        }
+        self.wallet.apply_diff(&add, &remove).unwrap();
+    }
+
+    /// Undo the effect of proven transactions on the wallet.
+    fn rollback(&self, transactions: &[Transaction]) {
+        let mut add: Vec<(CoinId, Output)> = vec![];
+        let mut remove: Vec<CoinId> = vec![];
+        for transaction in transactions.iter().rev() {
+            let hash = transaction.hash();
+            for input in &transaction.input {
+                let output = Output {
+                    value: input.value,
+                    recipient: input.owner,
+                };
+                add.push((input.coin, output));
+            }
+            for index in 0..transaction.output.len() {
+                remove.push(CoinId {
+                    hash,
+                    index: index as u32,
+                });
+            }
+        }
+        self.wallet.apply_diff(&add, &remove).unwrap();
+    }
+}
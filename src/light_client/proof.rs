@@ -0,0 +1,335 @@
+use crate::block::header::Header;
+use crate::block::{Block, Content};
+use crate::blockchain::BlockChain;
+use crate::blockdb::BlockDatabase;
+use crate::config::*;
+use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::merkle::{self, MerkleTree};
+use crate::transaction::{Address, Transaction};
+use crate::validation::{self, BlockResult};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Proof that a transaction entered the ledger at a proposer level. It links the transaction to
+/// its transaction block with a Merkle proof, and the transaction block to the leader of the level
+/// with the proposer blocks on a reference path from the leader.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionProof {
+    /// The transaction.
+    pub transaction: Transaction,
+    /// Index of the transaction in the transaction block.
+    pub index: u32,
+    /// Number of transactions in the transaction block.
+    pub num_transactions: u32,
+    /// Merkle proof of the transaction against the content of the transaction block.
+    pub merkle_proof: Vec<H256>,
+    /// Hash of the content of the transaction block.
+    pub content_hash: H256,
+    /// Header of the transaction block.
+    pub block_header: Header,
+    /// Sortition proof of the content of the transaction block.
+    pub sortition_proof: Vec<H256>,
+    /// Proposer blocks from the leader to one that refers the transaction block, each referring
+    /// the next one.
+    pub reference_path: Vec<Block>,
+}
+
+impl TransactionProof {
+    /// Verify that the transaction entered the ledger at the level of the given leader, where the
+    /// given proposer blocks are confirmed.
+    pub fn verify(
+        &self,
+        leader: &H256,
+        confirmed: &HashSet<H256>,
+        config: &BlockchainConfig,
+    ) -> bool {
+        // the transaction is in the transaction block
+        if !merkle::verify(
+            &self.content_hash,
+            &self.transaction.hash(),
+            &self.merkle_proof,
+            self.index as usize,
+            self.num_transactions as usize,
+        ) {
+            return false;
+        }
+        // the transaction block is mined
+        let block_hash = self.block_header.hash();
+        if config.sortition_hash(&block_hash, &self.block_header.difficulty)
+            != Some(TRANSACTION_INDEX)
+        {
+            return false;
+        }
+        if !merkle::verify(
+            &self.block_header.content_merkle_root,
+            &self.content_hash,
+            &self.sortition_proof,
+            TRANSACTION_INDEX as usize,
+            (config.voter_chains + FIRST_VOTER_INDEX) as usize,
+        ) {
+            return false;
+        }
+        // the leader refers the transaction block, possibly through other proposer blocks confirmed
+        // at the same level
+        let mut referred: Vec<H256> = vec![*leader];
+        let mut transaction_refs: &[H256] = &[];
+        for block in &self.reference_path {
+            let hash = block.hash();
+            if !referred.contains(&hash) || !confirmed.contains(&hash) {
+                return false;
+            }
+            let content = match &block.content {
+                Content::Proposer(content) => content,
+                _ => return false,
+            };
+            match validation::check_pow_sortition_id(block, config) {
+                BlockResult::Pass => {}
+                _ => return false,
+            }
+            match validation::check_sortition_proof(block, config) {
+                BlockResult::Pass => {}
+                _ => return false,
+            }
+            referred = content.proposer_refs.clone();
+            referred.push(block.header.parent);
+            transaction_refs = &content.transaction_refs;
+        }
+        transaction_refs.contains(&block_hash)
+    }
+}
+
+/// Prove the transactions that entered the ledger at the given proposer level and touch any of the
+/// given addresses. Return the leader of the level along with the proofs, or `None` if the level
+/// is not confirmed or some of its transaction blocks are pruned.
+pub fn prove_transactions(
+    level: u64,
+    addresses: &[Address],
+    chain: &BlockChain,
+    blockdb: &BlockDatabase,
+) -> Option<(H256, Vec<TransactionProof>)> {
+    let leader = chain.proposer_leader(level).unwrap()?;
+    let addresses: HashSet<Address> = addresses.iter().cloned().collect();
+    let confirmed: HashSet<H256> = chain.ledger_proposers(level).unwrap().into_iter().collect();
+
+    // walk the proposer blocks confirmed at this level from the leader, and remember the block
+    // through which each of them is reached
+    let mut proposers: HashMap<H256, (Block, Option<H256>)> = HashMap::new();
+    let mut queue: VecDeque<(H256, Option<H256>)> = VecDeque::new();
+    let mut visited: HashSet<H256> = HashSet::new();
+    let mut proven_blocks: HashSet<H256> = HashSet::new();
+    let mut proofs: Vec<TransactionProof> = vec![];
+    queue.push_back((leader, None));
+    visited.insert(leader);
+    while let Some((hash, referrer)) = queue.pop_front() {
+        let block = blockdb.get(&hash).unwrap()?;
+        let content = match &block.content {
+            Content::Proposer(content) => content.clone(),
+            _ => unreachable!(),
+        };
+        proposers.insert(hash, (block.clone(), referrer));
+        let mut refs = content.proposer_refs.clone();
+        refs.push(block.header.parent);
+        for r in refs {
+            if confirmed.contains(&r) && visited.insert(r) {
+                queue.push_back((r, Some(hash)));
+            }
+        }
+
+        for transaction_block_hash in &content.transaction_refs {
+            if !proven_blocks.insert(*transaction_block_hash) {
+                continue;
+            }
+            let transaction_block = blockdb.get(transaction_block_hash).unwrap()?;
+            let transactions = match transaction_block.content {
+                Content::Transaction(content) => content.transactions,
+                _ => unreachable!(),
+            };
+            let touching: Vec<usize> = transactions
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| {
+                    t.output.iter().any(|o| addresses.contains(&o.recipient))
+                        || t.input.iter().any(|i| addresses.contains(&i.owner))
+                })
+                .map(|(i, _)| i)
+                .collect();
+            if touching.is_empty() {
+                continue;
+            }
+            let tree = MerkleTree::new(&transactions);
+            let reference_path = reference_path(&proposers, &hash);
+            for index in touching {
+                proofs.push(TransactionProof {
+                    transaction: transactions[index].clone(),
+                    index: index as u32,
+                    num_transactions: transactions.len() as u32,
+                    merkle_proof: tree.proof(index),
+                    content_hash: tree.root(),
+                    block_header: transaction_block.header,
+                    sortition_proof: transaction_block.sortition_proof.clone(),
+                    reference_path: reference_path.clone(),
+                });
+            }
+        }
+    }
+    Some((leader, proofs))
+}
+
+/// Get the proposer blocks from the leader to the given one.
+fn reference_path(proposers: &HashMap<H256, (Block, Option<H256>)>, to: &H256) -> Vec<Block> {
+    let mut path: Vec<Block> = vec![];
+    let mut current = Some(*to);
+    while let Some(hash) = current {
+        let (block, referrer) = &proposers[&hash];
+        path.push(block.clone());
+        current = *referrer;
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::mined_block;
+    use crate::block::{proposer, transaction as transaction_block};
+    use crate::blockchain::confirmation::ConfirmationRuleKind;
+    use crate::blockchain::tests::vote;
+    use crate::crypto::hash::tests::generate_random_hash;
+    use crate::transaction::tests::transaction;
+
+    /// A blockchain where a transaction block enters the ledger at level 2, through a proposer
+    /// block at level 1 that the leader of level 2 refers to, and another proposer block at level
+    /// 2 that refers to the transaction block but is not confirmed.
+    struct Fixture {
+        config: BlockchainConfig,
+        chain: BlockChain,
+        blockdb: BlockDatabase,
+        leader: H256,
+        unconfirmed: Block,
+    }
+
+    fn fixture(name: &str) -> Fixture {
+        let mut config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        let chain = BlockChain::new(
+            format!("/tmp/prism_test_proof_{}_blockchain.rocksdb", name),
+            config.clone(),
+        )
+        .unwrap();
+        let blockdb = BlockDatabase::new(
+            format!("/tmp/prism_test_proof_{}_blockdb.rocksdb", name),
+            config.clone(),
+        )
+        .unwrap();
+        let genesis = config.proposer_genesis;
+        let propose = |parent: H256, proposer_refs: Vec<H256>, transaction_refs: Vec<H256>| {
+            let content = Content::Proposer(proposer::Content {
+                transaction_refs,
+                proposer_refs,
+                utxo_commitment: None,
+            });
+            let block = mined_block(parent, content, &config);
+            blockdb.insert(&block).unwrap();
+            chain.insert_block(&block).unwrap();
+            block
+        };
+
+        let transactions = vec![
+            transaction(vec![], &[10]),
+            transaction(vec![], &[20]),
+            transaction(vec![], &[30]),
+        ];
+        let content = Content::Transaction(transaction_block::Content { transactions });
+        let transaction_block = mined_block(genesis, content, &config);
+        blockdb.insert(&transaction_block).unwrap();
+        chain.insert_block(&transaction_block).unwrap();
+
+        let first_leader = propose(genesis, vec![], vec![]);
+        let referring = propose(genesis, vec![], vec![transaction_block.hash()]);
+        let leader = propose(first_leader.hash(), vec![referring.hash()], vec![]);
+        let unconfirmed = propose(first_leader.hash(), vec![], vec![transaction_block.hash()]);
+        vote(
+            &chain,
+            0,
+            config.voter_genesis[0],
+            vec![first_leader.hash(), leader.hash()],
+        );
+        chain.update_ledger().unwrap();
+        assert_eq!(chain.proposer_leader(2).unwrap(), Some(leader.hash()));
+
+        Fixture {
+            config,
+            chain,
+            blockdb,
+            leader: leader.hash(),
+            unconfirmed,
+        }
+    }
+
+    fn confirmed(chain: &BlockChain, level: u64) -> HashSet<H256> {
+        chain.ledger_proposers(level).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn valid_proofs() {
+        let f = fixture("valid");
+        let (leader, proofs) =
+            prove_transactions(2, &[H256::default()], &f.chain, &f.blockdb).unwrap();
+        assert_eq!(leader, f.leader);
+        assert_eq!(proofs.len(), 3);
+        for proof in &proofs {
+            assert_eq!(proof.reference_path.len(), 2);
+            assert!(proof.verify(&leader, &confirmed(&f.chain, 2), &f.config));
+        }
+        // nothing touches other addresses, and unconfirmed levels cannot be proven
+        let (_, proofs) =
+            prove_transactions(2, &[generate_random_hash()], &f.chain, &f.blockdb).unwrap();
+        assert!(proofs.is_empty());
+        assert!(prove_transactions(3, &[H256::default()], &f.chain, &f.blockdb).is_none());
+    }
+
+    #[test]
+    fn tampered_merkle_proof() {
+        let f = fixture("tampered");
+        let (leader, proofs) =
+            prove_transactions(2, &[H256::default()], &f.chain, &f.blockdb).unwrap();
+        let mut proof = proofs[0].clone();
+        proof.merkle_proof[0] = generate_random_hash();
+        assert!(!proof.verify(&leader, &confirmed(&f.chain, 2), &f.config));
+
+        // a sortition proof that does not link the content to the header
+        let mut proof = proofs[0].clone();
+        proof.sortition_proof[0] = generate_random_hash();
+        assert!(!proof.verify(&leader, &confirmed(&f.chain, 2), &f.config));
+    }
+
+    #[test]
+    fn wrong_index() {
+        let f = fixture("index");
+        let (leader, proofs) =
+            prove_transactions(2, &[H256::default()], &f.chain, &f.blockdb).unwrap();
+        let mut proof = proofs[0].clone();
+        proof.index = proofs[1].index;
+        assert!(!proof.verify(&leader, &confirmed(&f.chain, 2), &f.config));
+        let mut proof = proofs[0].clone();
+        proof.index = proof.num_transactions;
+        assert!(!proof.verify(&leader, &confirmed(&f.chain, 2), &f.config));
+    }
+
+    #[test]
+    fn unconfirmed_reference_path() {
+        let f = fixture("unconfirmed");
+        let (leader, proofs) =
+            prove_transactions(2, &[H256::default()], &f.chain, &f.blockdb).unwrap();
+
+        // claim that the transaction block entered the ledger through a proposer block that is
+        // not on the main chain
+        let mut proof = proofs[0].clone();
+        proof.reference_path = vec![f.unconfirmed.clone()];
+        assert!(!proof.verify(&leader, &confirmed(&f.chain, 2), &f.config));
+        assert!(!proof.verify(&f.unconfirmed.hash(), &confirmed(&f.chain, 2), &f.config));
+
+        // the proof does not hold at another level
+        assert!(!proofs[0].verify(&leader, &confirmed(&f.chain, 1), &f.config));
+    }
+}
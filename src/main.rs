@@ -4,23 +4,31 @@ extern crate clap;
 use crossbeam::channel;
 use ed25519_dalek::Keypair;
 use log::{debug, error, info};
+use prism::api::auth::{Auth as ApiAuth, Role as ApiRole};
+use prism::api::client::Client as ApiClient;
 use prism::api::Server as ApiServer;
+use prism::block::header::{tag_content, MAX_TAG_LENGTH};
+use prism::blockchain::confirmation::ConfirmationRuleKind;
 use prism::blockchain::BlockChain;
 use prism::blockdb::BlockDatabase;
 use prism::config::BlockchainConfig;
 use prism::crypto::hash::H256;
+use prism::experiment::performance_counter::PERFORMANCE_COUNTER;
+use prism::experiment::simulation::{self, Clock, SimulatedClock, SystemClock};
 use prism::experiment::transaction_generator::TransactionGenerator;
 use prism::ledger_manager::LedgerManager;
+use prism::light_client::LightClient;
 use prism::miner;
 use prism::miner::memory_pool::MemoryPool;
 use prism::network::server;
 use prism::network::worker;
+use prism::transaction::address::Bech32Address;
 use prism::transaction::Address;
 use prism::utxodb::UtxoDatabase;
 use prism::visualization::Server as VisualizationServer;
 use prism::wallet::Wallet;
 use rand::rngs::OsRng;
-use std::convert::TryInto;
+use std::collections::BTreeMap;
 use std::net;
 use std::process;
 use std::sync::Arc;
@@ -35,6 +43,11 @@ fn main() {
      (@arg verbose: -v ... "Increases the verbosity of logging")
      (@arg peer_addr: --p2p [ADDR] default_value("127.0.0.1:6000") "Sets the IP address and the port of the P2P server")
      (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
+     (@arg api_admin_token: --("api-admin-token") [TOKEN] "Sets the bearer token granting admin access to the API server")
+     (@arg api_read_token: --("api-read-token") [TOKEN] "Sets the bearer token granting read-only access to the API server")
+     (@arg api_cookie: --("api-cookie") [PATH] "Generates a random admin token for the API server and writes it to the given path")
+     (@arg api_tls_cert: --("api-tls-cert") [PATH] requires("api_tls_key") "Enables TLS on the API server with the given PEM certificate")
+     (@arg api_tls_key: --("api-tls-key") [PATH] requires("api_tls_cert") "Sets the PEM private key for TLS on the API server")
      (@arg visualization: --visual [ADDR] "Enables the visualization server and sets its address and port")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
      (@arg block_db: --blockdb [PATH] default_value("/tmp/prism-blocks.rocksdb") "Sets the path to the block database")
@@ -49,6 +62,8 @@ fn main() {
      (@arg execution_workers: --("execution-workers") [INT] default_value("8") "Sets the number of worker threads for transaction execution")
      (@arg execution_buffer: --("execution-buffer") [INT] default_value("3") "Sets the size of the buffer between pipeline stages in transaction execution")
      (@arg p2p_workers: --("p2p-workers") [INT] default_value("16") "Sets the number of worker threads for P2P server")
+     (@arg signature_workers: --("signature-workers") [INT] default_value("4") "Sets the number of worker threads for signature verification of received blocks")
+     (@arg signature_batch: --("signature-batch") [INT] default_value("256") "Sets the number of transactions whose signatures are verified in one batch")
      (@arg voter_chains: --("voter-chains") [INT] default_value("1000") "Sets the number of voter chains")
      (@arg tx_throughput: --("tx-throughput") [INT] default_value("80000") "Sets the target transaction throughput")
      (@arg tx_block_size: --("tx-block-size") [INT] default_value("64000") "Sets the maximum size of the transaction block in Bytes")
@@ -56,11 +71,65 @@ fn main() {
      (@arg voter_mining_rate: --("voter-mining-rate") [FLOAT] default_value("0.1") "Sets the voter chain mining rate")
      (@arg adv_ratio: --("adversary-ratio") [FLOAT] default_value("0.4") "Sets the ratio of adversary hashing power")
      (@arg log_epsilon: --("confirm-confidence") [FLOAT] default_value("20.0") "Sets -log(epsilon) for confirmation")
+     (@arg confirmation_rule: --("confirmation-rule") [RULE] default_value("prism") "Sets the rule to confirm proposer leaders: prism, majority:<depth>, longest-chain:<depth> or optimistic")
+     (@arg checkpoint: --checkpoint ... [CHECKPOINT] "Pins the proposer block with the given hash as the leader of its level, in the form of <level>:<hash>")
+     (@arg light_client: --light conflicts_with("fast_sync") "Runs as a light client, which only tracks block headers and voter content and verifies the transactions of the wallet with proofs from peers")
+     (@arg fast_sync: --("fast-sync") "Imports the UTXO set from a snapshot served by peers instead of replaying the ledger")
+     (@arg mining_threads: --("mining-threads") [INT] default_value("0") "Sets the number of threads doing real proof of work in the miner, or 0 to emulate the mining rate")
+     (@arg difficulty_bits: --("difficulty-bits") [INT] default_value("16") "Sets the number of leading zero bits of the difficulty when mining with real proof of work")
+     (@arg external_mining: --("external-mining") "Hands out header templates to external miners through the API, and mines the blocks they solve with real proof of work")
+     (@arg simulate: --simulate conflicts_with("external_mining") "Draws the mined blocks of each chain from the mining rates on a simulated clock, driving the transaction generator as well")
+     (@arg miner_address: --("miner-address") [ADDR] "Attributes the blocks we mine to the given address")
+     (@arg miner_tag: --("miner-tag") [TAG] "Writes the given tag of up to 32 bytes into the headers of the blocks we mine")
+     (@arg seed: --seed [INT] "Seeds the randomness of the miner and the transaction generator, which makes the schedule of a simulated run reproducible")
+     (@arg pruning_depth: --prune [DEPTH] "Prunes the consensus metadata and transaction blocks confirmed more than the given number of proposer levels below the ledger tip")
 
      (@subcommand keygen =>
       (about: "Generates Prism wallet key pair")
       (@arg display_address: --addr "Prints the address of the key pair to STDERR")
      )
+     (@subcommand wallet =>
+      (about: "Manages the wallet of a running node through its API server")
+      (@setting SubcommandRequiredElseHelp)
+      (@arg node: --node [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server of the node")
+      (@arg token: --token [TOKEN] "Sets the bearer token for the API server")
+      (@arg cookie: --cookie [PATH] conflicts_with("token") "Reads the bearer token for the API server from the given cookie file")
+      (@subcommand balance =>
+       (about: "Prints the balance of the wallet")
+      )
+      (@subcommand send =>
+       (about: "Sends coins to the given address")
+       (@arg recipient: +required "Sets the address of the recipient")
+       (@arg value: +required "Sets the value to send")
+      )
+      (@subcommand history =>
+       (about: "Prints the coins recently received and sent by the wallet")
+       (@arg limit: --limit [INT] default_value("20") "Sets the maximum number of entries to print")
+      )
+      (@subcommand addresses =>
+       (about: "Prints the addresses of the wallet")
+      )
+      (subcommand: clap::SubCommand::with_name("new-address").about("Generates a new address in the wallet"))
+     )
+     (@subcommand chain =>
+      (about: "Queries the blockchain of a running node through its API server")
+      (@setting SubcommandRequiredElseHelp)
+      (@arg node: --node [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server of the node")
+      (@arg token: --token [TOKEN] "Sets the bearer token for the API server")
+      (@arg cookie: --cookie [PATH] conflicts_with("token") "Reads the bearer token for the API server from the given cookie file")
+      (@subcommand status =>
+       (about: "Prints the status of the node")
+      )
+      (@subcommand block =>
+       (about: "Prints the block with the given hash")
+       (@arg hash: +required "Sets the hash of the block")
+      )
+      (@subcommand tx =>
+       (about: "Prints the transaction with the given hash")
+       (@arg hash: +required "Sets the hash of the transaction")
+       (@arg depth: --depth [INT] default_value("100") "Sets the number of ledger levels to search for the transaction")
+      )
+     )
     )
     .get_matches();
 
@@ -77,8 +146,67 @@ fn main() {
                     &keypair.public.as_bytes().as_ref(),
                 )
                 .into();
-                let base64_encoded = base64::encode(&addr);
-                eprintln!("{}", base64_encoded);
+                eprintln!("{}", Bech32Address(addr));
+            }
+            return;
+        }
+        ("wallet", Some(m)) | ("chain", Some(m)) => {
+            let token = match m.value_of("cookie") {
+                Some(path) => match std::fs::read_to_string(path) {
+                    Ok(t) => Some(t.trim().to_string()),
+                    Err(e) => {
+                        eprintln!("Error reading API cookie file {}: {}", path, e);
+                        process::exit(1);
+                    }
+                },
+                None => m.value_of("token").map(|t| t.to_string()),
+            };
+            let client = ApiClient::new(m.value_of("node").unwrap(), token);
+            let result = match m.subcommand() {
+                ("balance", _) => client.get("/wallet/balance"),
+                ("send", Some(m)) => {
+                    let recipient = m.value_of("recipient").unwrap();
+                    if let Err(e) = recipient.parse::<Bech32Address>() {
+                        eprintln!("Error parsing recipient address {}: {}", recipient, e);
+                        process::exit(1);
+                    }
+                    let value = m
+                        .value_of("value")
+                        .unwrap()
+                        .parse::<u64>()
+                        .unwrap_or_else(|e| {
+                            eprintln!("Error parsing value: {}", e);
+                            process::exit(1);
+                        });
+                    client.post(&format!(
+                        "/wallet/send?recipient={}&value={}",
+                        recipient, value
+                    ))
+                }
+                ("history", Some(m)) => client.get(&format!(
+                    "/wallet/history?limit={}",
+                    m.value_of("limit").unwrap()
+                )),
+                ("addresses", _) => client.get("/wallet/addresses"),
+                ("new-address", _) => client.post("/wallet/new-address"),
+                ("status", _) => client.get("/status"),
+                ("block", Some(m)) => client.get(&format!(
+                    "/chain/block?hash={}",
+                    m.value_of("hash").unwrap()
+                )),
+                ("tx", Some(m)) => client.get(&format!(
+                    "/chain/tx?hash={}&depth={}",
+                    m.value_of("hash").unwrap(),
+                    m.value_of("depth").unwrap()
+                )),
+                _ => unreachable!(),
+            };
+            match result {
+                Ok(v) => println!("{}", serde_json::to_string_pretty(&v).unwrap()),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
             }
             return;
         }
@@ -146,7 +274,90 @@ fn main() {
             error!("Error parsing confirm confidence: {}", e);
             process::exit(1);
         });
-    let config = BlockchainConfig::new(
+    let confirmation_rule = matches
+        .value_of("confirmation_rule")
+        .unwrap()
+        .parse::<ConfirmationRuleKind>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing confirmation rule: {}", e);
+            process::exit(1);
+        });
+    let pruning_depth = matches.value_of("pruning_depth").map(|depth| {
+        depth.parse::<u64>().unwrap_or_else(|e| {
+            error!("Error parsing pruning depth: {}", e);
+            process::exit(1);
+        })
+    });
+    let mining_threads = matches
+        .value_of("mining_threads")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing number of mining threads: {}", e);
+            process::exit(1);
+        });
+    let difficulty_bits = matches
+        .value_of("difficulty_bits")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing difficulty bits: {}", e);
+            process::exit(1);
+        });
+    // leave enough room below the difficulty for the sortition of the block types
+    if difficulty_bits > 128 {
+        error!("Difficulty bits must be at most 128");
+        process::exit(1);
+    }
+    let mut difficulty: [u8; 32] = [255; 32];
+    for bit in 0..difficulty_bits {
+        difficulty[bit / 8] &= !(0x80 >> (bit % 8));
+    }
+    let miner_address =
+        matches
+            .value_of("miner_address")
+            .map(|addr| match addr.parse::<Bech32Address>() {
+                Ok(addr) => addr.0,
+                Err(e) => {
+                    error!("Error parsing miner address: {}", e);
+                    process::exit(1);
+                }
+            });
+    let miner_tag = matches.value_of("miner_tag").map(|tag| {
+        tag_content(tag).unwrap_or_else(|| {
+            error!("Miner tag must be at most {} bytes", MAX_TAG_LENGTH);
+            process::exit(1);
+        })
+    });
+    let seed = matches.value_of("seed").map(|seed| {
+        seed.parse::<u64>().unwrap_or_else(|e| {
+            error!("Error parsing seed: {}", e);
+            process::exit(1);
+        })
+    });
+    let simulate = matches.is_present("simulate");
+    if simulate && mining_threads > 0 {
+        error!("Simulated mining cannot be combined with mining threads");
+        process::exit(1);
+    }
+    let mut checkpoints: BTreeMap<u64, H256> = BTreeMap::new();
+    if let Some(values) = matches.values_of("checkpoint") {
+        for value in values {
+            let mut parts = value.splitn(2, ':');
+            let level = parts.next().unwrap().parse::<u64>();
+            let hash = parts.next().unwrap_or("").parse::<H256>();
+            match (level, hash) {
+                (Ok(level), Ok(hash)) => {
+                    checkpoints.insert(level, hash);
+                }
+                _ => {
+                    error!("Error parsing checkpoint {}", value);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    let mut config = BlockchainConfig::new(
         voter_chains,
         tx_blk_size,
         tx_throughput,
@@ -155,6 +366,41 @@ fn main() {
         adv_ratio,
         log_epsilon,
     );
+    config.confirmation_rule = confirmation_rule;
+    info!("Using the {} confirmation rule", config.confirmation_rule);
+    config.pruning_depth = pruning_depth;
+    config.checkpoints = checkpoints;
+    config.light_client = matches.is_present("light_client");
+    config.mining_threads = mining_threads;
+    config.mining_difficulty = difficulty.into();
+    config.external_mining = matches.is_present("external_mining");
+    config.simulated_mining = simulate;
+    if let Some(miner_address) = miner_address {
+        info!(
+            "Attributing mined blocks to {}",
+            Bech32Address(miner_address)
+        );
+        config.miner_address = miner_address;
+    }
+    if let Some(miner_tag) = miner_tag {
+        config.miner_tag = miner_tag;
+    }
+    let clock: Arc<dyn Clock> = if simulate {
+        info!("Using a simulated clock");
+        Arc::new(SimulatedClock::new())
+    } else {
+        Arc::new(SystemClock)
+    };
+    PERFORMANCE_COUNTER.set_clock(Arc::clone(&clock));
+    for (level, hash) in &config.checkpoints {
+        info!("Checkpoint at proposer level {}: {:.8}", level, hash);
+    }
+    if let Some(depth) = config.pruning_depth {
+        info!(
+            "Pruning blocks confirmed {} levels below the ledger tip",
+            depth
+        );
+    }
     info!(
         "Proposer block mining rate set to {} blks/s",
         config.proposer_mining_rate
@@ -222,7 +468,7 @@ fn main() {
             };
             let keypair = Keypair::from_bytes(&decoded).unwrap();
             match wallet.load_keypair(keypair) {
-                Ok(a) => info!("Loaded key pair for address {}", &a),
+                Ok(a) => info!("Loaded key pair for address {}", Bech32Address(a)),
                 Err(e) => {
                     error!("Error loading key pair into wallet: {}", &e);
                     process::exit(1);
@@ -248,12 +494,6 @@ fn main() {
             error!("Error parsing transaction execution buffer size: {}", e);
             process::exit(1);
         });
-    let ledger_manager = LedgerManager::new(&blockdb, &blockchain, &utxodb, &wallet);
-    ledger_manager.start(tx_buffer, tx_workers);
-    debug!(
-        "Initialized ledger manager with buffer size {} and {} workers",
-        tx_buffer, tx_workers
-    );
 
     // parse p2p server address
     let p2p_addr = matches
@@ -275,6 +515,42 @@ fn main() {
             process::exit(1);
         });
 
+    // parse api server credentials
+    let mut api_auth = ApiAuth::new();
+    if let Some(token) = matches.value_of("api_admin_token") {
+        api_auth.add_token(token, ApiRole::Admin);
+    }
+    if let Some(token) = matches.value_of("api_read_token") {
+        api_auth.add_token(token, ApiRole::Read);
+    }
+    if let Some(path) = matches.value_of("api_cookie") {
+        api_auth.generate_cookie(path).unwrap_or_else(|e| {
+            error!("Error writing API cookie file {}: {}", path, e);
+            process::exit(1);
+        });
+        info!("Wrote API admin token to {}", path);
+    }
+    let api_ssl = match (
+        matches.value_of("api_tls_cert"),
+        matches.value_of("api_tls_key"),
+    ) {
+        (Some(cert_path), Some(key_path)) => {
+            let certificate = std::fs::read(cert_path).unwrap_or_else(|e| {
+                error!("Error reading API TLS certificate {}: {}", cert_path, e);
+                process::exit(1);
+            });
+            let private_key = std::fs::read(key_path).unwrap_or_else(|e| {
+                error!("Error reading API TLS private key {}: {}", key_path, e);
+                process::exit(1);
+            });
+            Some(tiny_http::SslConfig {
+                certificate,
+                private_key,
+            })
+        }
+        _ => None,
+    };
+
     // create channels between server and worker, worker and miner, miner and worker
     let (msg_tx, msg_rx) = channel::unbounded();
     let (ctx_tx, ctx_rx) = channel::unbounded();
@@ -284,6 +560,31 @@ fn main() {
     let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
     server_ctx.start().unwrap();
 
+    // start the light client, or the ledger manager that fetches the utxo snapshot from peers if
+    // fast sync is enabled
+    let mut snapshot_chunk_tx = None;
+    let mut transaction_proof_tx = None;
+    if config.light_client {
+        let (proof_tx, proof_rx) = channel::unbounded();
+        let light_client =
+            LightClient::new(&blockchain, &wallet, &server, proof_rx, config.clone());
+        light_client.start();
+        transaction_proof_tx = Some(proof_tx);
+        info!("Running as a light client");
+    } else {
+        let mut ledger_manager = LedgerManager::new(&blockdb, &blockchain, &utxodb, &wallet);
+        if matches.is_present("fast_sync") {
+            let (chunk_tx, chunk_rx) = channel::unbounded();
+            ledger_manager.enable_fast_sync(&server, chunk_rx);
+            snapshot_chunk_tx = Some(chunk_tx);
+        }
+        ledger_manager.start(tx_buffer, tx_workers);
+        debug!(
+            "Initialized ledger manager with buffer size {} and {} workers",
+            tx_buffer, tx_workers
+        );
+    }
+
     // start the worker
     let p2p_workers = matches
         .value_of("p2p_workers")
@@ -293,8 +594,30 @@ fn main() {
             error!("Error parsing P2P workers: {}", e);
             process::exit(1);
         });
+    let signature_workers = matches
+        .value_of("signature_workers")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing signature workers: {}", e);
+            process::exit(1);
+        });
+    let signature_batch = matches
+        .value_of("signature_batch")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing signature batch size: {}", e);
+            process::exit(1);
+        });
+    if signature_workers == 0 || signature_batch == 0 {
+        error!("Signature workers and batch size must be positive");
+        process::exit(1);
+    }
     let worker_ctx = worker::new(
         p2p_workers,
+        signature_workers,
+        signature_batch,
         msg_rx,
         &blockchain,
         &blockdb,
@@ -304,6 +627,8 @@ fn main() {
         ctx_tx,
         &server,
         config.clone(),
+        snapshot_chunk_tx,
+        transaction_proof_tx,
     );
     worker_ctx.start();
 
@@ -312,10 +637,13 @@ fn main() {
         &mempool,
         &blockchain,
         &blockdb,
+        &utxodb,
         ctx_rx,
         &ctx_tx_miner,
         &server,
         config.clone(),
+        &clock,
+        simulation::rng(seed, simulation::MINER_STREAM),
     );
     miner_ctx.start();
 
@@ -372,16 +700,14 @@ fn main() {
             });
         let mut addrs = vec![];
         for addr in fund_addrs {
-            let decoded = match base64::decode(&addr.trim()) {
+            let decoded = match addr.trim().parse::<Bech32Address>() {
                 Ok(d) => d,
                 Err(e) => {
                     error!("Error decoding address {}: {}", &addr.trim(), e);
                     process::exit(1);
                 }
             };
-            let addr_bytes: [u8; 32] = (&decoded[0..32]).try_into().unwrap();
-            let hash: H256 = addr_bytes.into();
-            addrs.push(hash);
+            addrs.push(decoded.into());
         }
         info!(
             "Funding {} addresses with {} initial coins of {}",
@@ -398,7 +724,13 @@ fn main() {
     }
 
     // start the transaction generator
-    let (txgen_ctx, txgen_control_chan) = TransactionGenerator::new(&wallet, &server, &mempool);
+    let (txgen_ctx, txgen_control_chan) = TransactionGenerator::new(
+        &wallet,
+        &server,
+        &mempool,
+        &clock,
+        simulation::rng(seed, simulation::TRANSACTION_GENERATOR_STREAM),
+    );
     txgen_ctx.start();
 
     // start the API server
@@ -406,11 +738,14 @@ fn main() {
         api_addr,
         &wallet,
         &blockchain,
+        &blockdb,
         &utxodb,
         &server,
         &miner,
         &mempool,
         txgen_control_chan,
+        api_auth,
+        api_ssl,
     );
 
     // start the visualization server
@@ -0,0 +1,217 @@
+use crate::block::{Block, Content};
+use crate::crypto::hash::{Hashable, H256};
+
+use std::collections::HashSet;
+
+/// Adversarial behaviors of the miner, for security experiments. The default strategy is honest.
+#[derive(Default, Clone, Debug)]
+pub struct Strategy {
+    /// Withhold our proposer blocks to build a private proposer chain, and release it when the
+    /// public chain catches up (selfish mining).
+    pub selfish_mining: bool,
+    /// Vote for the proposer block with the fewest votes at each level, so that competing
+    /// proposer blocks stay balanced.
+    pub balance_votes: bool,
+    /// Transactions to leave out of our transaction blocks. Our proposer blocks do not refer to
+    /// the transaction blocks containing them either.
+    pub censored_transactions: HashSet<H256>,
+    /// Time to withhold our voter blocks for, in milliseconds.
+    pub voter_delay: Option<u64>,
+}
+
+/// When a withheld block is released.
+enum Release {
+    /// Along with the private proposer chain, once the public chain reaches the given level.
+    Level(u64),
+    /// At the given time, in milliseconds.
+    Time(u128),
+}
+
+struct WithheldBlock {
+    hash: H256,
+    proposer: bool,
+    release: Release,
+}
+
+/// State of the adversarial miner: the strategy and the blocks it withholds from peers. Withheld
+/// blocks are in our databases, but their hashes are not announced until they are released, so
+/// peers never ask for them.
+#[derive(Default)]
+pub struct Adversary {
+    strategy: Strategy,
+    /// Withheld blocks, in the order they were mined.
+    withheld: Vec<WithheldBlock>,
+    /// Level of the public proposer chain when we last checked.
+    public_level: u64,
+}
+
+impl Adversary {
+    pub fn strategy(&self) -> &Strategy {
+        &self.strategy
+    }
+
+    /// Switch to a new strategy, and return the withheld blocks to release.
+    pub fn set_strategy(&mut self, strategy: Strategy) -> Vec<H256> {
+        self.strategy = strategy;
+        self.withheld.drain(..).map(|b| b.hash).collect()
+    }
+
+    /// Whether we have withheld proposer blocks, which form our private proposer chain.
+    pub fn has_private_chain(&self) -> bool {
+        self.withheld.iter().any(|b| b.proposer)
+    }
+
+    /// Whether the given block is withheld.
+    pub fn is_withheld(&self, hash: &H256) -> bool {
+        self.withheld.iter().any(|b| b.hash == *hash)
+    }
+
+    /// Decide whether to withhold a block we just mined at the given proposer level. Blocks built
+    /// on withheld blocks are withheld as well, so that peers do not learn about them early.
+    pub fn withhold(&mut self, block: &Block, level: u64, now: u128) -> bool {
+        let private_parent = self.is_withheld(&block.header.parent);
+        let release = match &block.content {
+            Content::Proposer(_) if self.strategy.selfish_mining || private_parent => {
+                Some(Release::Level(level))
+            }
+            Content::Voter(content) => {
+                if private_parent {
+                    Some(Release::Level(level))
+                } else if let Some(delay) = self.strategy.voter_delay {
+                    // release after the voter parent, if it is withheld as well
+                    let parent_release = self.withheld.iter().find_map(|b| match b.release {
+                        Release::Time(t) if b.hash == content.voter_parent => Some(t),
+                        _ => None,
+                    });
+                    let release = now + u128::from(delay);
+                    Some(Release::Time(release.max(parent_release.unwrap_or(0))))
+                } else {
+                    None
+                }
+            }
+            Content::Transaction(_) if private_parent => Some(Release::Level(level)),
+            _ => None,
+        };
+        match release {
+            Some(release) => {
+                self.withheld.push(WithheldBlock {
+                    hash: block.hash(),
+                    proposer: match block.content {
+                        Content::Proposer(_) => true,
+                        _ => false,
+                    },
+                    release,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Given the level of the public proposer chain and the current time, return the withheld
+    /// blocks to release.
+    ///
+    /// When the public chain grows, we publish the whole private chain if our lead has dropped to
+    /// one block or less, so that it wins or ties. Otherwise we only publish the private blocks up
+    /// to the public level, which keeps peers split while we stay ahead.
+    pub fn release(&mut self, public_level: u64, now: u128) -> Vec<H256> {
+        let mut release_level = None;
+        if public_level > self.public_level {
+            let private_level = self
+                .withheld
+                .iter()
+                .filter_map(|b| match b.release {
+                    Release::Level(l) if b.proposer => Some(l),
+                    _ => None,
+                })
+                .max();
+            if let Some(private_level) = private_level {
+                release_level = if private_level <= public_level + 1 {
+                    Some(private_level)
+                } else {
+                    Some(public_level)
+                };
+            }
+        }
+        self.public_level = public_level;
+
+        let mut released = vec![];
+        self.withheld.retain(|b| {
+            let due = match b.release {
+                Release::Level(l) => release_level.map_or(false, |r| l <= r),
+                Release::Time(t) => t <= now,
+            };
+            if due {
+                released.push(b.hash);
+            }
+            !due
+        });
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::header::Header;
+    use crate::block::{proposer, voter};
+
+    fn block(parent: H256, nonce: u32, content: Content) -> Block {
+        let header = Header::new(parent, 0, nonce, H256::default(), [0; 32], H256::default());
+        Block::from_header(header, content, vec![])
+    }
+
+    fn proposer_block(parent: H256, nonce: u32) -> Block {
+        block(
+            parent,
+            nonce,
+            Content::Proposer(proposer::Content::default()),
+        )
+    }
+
+    #[test]
+    fn selfish_mining() {
+        let mut adversary = Adversary::default();
+        adversary.set_strategy(Strategy {
+            selfish_mining: true,
+            ..Default::default()
+        });
+        let genesis = H256::default();
+        let a = proposer_block(genesis, 1);
+        let b = proposer_block(a.hash(), 2);
+        let c = proposer_block(b.hash(), 3);
+        assert!(adversary.withhold(&a, 1, 0));
+        assert!(adversary.withhold(&b, 2, 0));
+        assert!(adversary.withhold(&c, 3, 0));
+        assert!(adversary.release(0, 0).is_empty());
+        // the public chain reaches level 1, and we are two blocks ahead
+        assert_eq!(adversary.release(1, 0), vec![a.hash()]);
+        // the public chain reaches level 2, and we publish the rest to win
+        assert_eq!(adversary.release(2, 0), vec![b.hash(), c.hash()]);
+        assert!(!adversary.has_private_chain());
+    }
+
+    #[test]
+    fn voter_delay() {
+        let mut adversary = Adversary::default();
+        adversary.set_strategy(Strategy {
+            voter_delay: Some(100),
+            ..Default::default()
+        });
+        let content = |voter_parent| {
+            Content::Voter(voter::Content {
+                chain_number: 0,
+                voter_parent,
+                votes: vec![],
+            })
+        };
+        let a = block(H256::default(), 1, content(H256::default()));
+        let b = block(H256::default(), 2, content(a.hash()));
+        assert!(adversary.withhold(&a, 1, 0));
+        assert!(adversary.withhold(&b, 1, 50));
+        assert!(!adversary.withhold(&proposer_block(H256::default(), 3), 1, 50));
+        assert!(adversary.release(0, 99).is_empty());
+        assert_eq!(adversary.release(0, 100), vec![a.hash()]);
+        assert_eq!(adversary.release(0, 150), vec![b.hash()]);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::block::Content;
+use crate::blockdb::BlockDatabase;
+use crate::crypto::hash::{Hashable, H256};
+use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::transaction::{Input, Transaction};
+
+use std::collections::{HashMap, HashSet};
+
+/// Index of the transactions in transaction blocks that are not referred by any proposer block
+/// yet. A new transaction block should not include them again, nor spend the same coins.
+#[derive(Default)]
+pub struct InFlight {
+    /// Transaction blocks in the index, with the hashes and the inputs of their transactions.
+    blocks: HashMap<H256, (Vec<H256>, Vec<Input>)>,
+    /// Number of indexed blocks including each transaction.
+    transactions: HashMap<H256, usize>,
+    /// Number of indexed transactions spending each coin.
+    inputs: HashMap<Input, usize>,
+}
+
+/// Why a transaction is left out of a transaction block.
+#[derive(Debug, PartialEq)]
+pub enum Exclusion {
+    /// The transaction is already in an unreferred transaction block.
+    Duplicate,
+    /// The transaction spends a coin already spent in an unreferred transaction block.
+    Conflict,
+}
+
+impl InFlight {
+    /// Update the index to the given unreferred transaction blocks. Newly indexed transactions
+    /// that duplicate or conflict with the ones already indexed are recorded in the metrics.
+    pub fn update(&mut self, unreferred: &[H256], blockdb: &BlockDatabase) {
+        let unreferred_set: HashSet<H256> = unreferred.iter().cloned().collect();
+        let removed: Vec<H256> = self
+            .blocks
+            .keys()
+            .filter(|h| !unreferred_set.contains(h))
+            .cloned()
+            .collect();
+        for hash in removed {
+            self.remove(&hash);
+        }
+        for hash in unreferred {
+            if self.blocks.contains_key(hash) {
+                continue;
+            }
+            // the block may have been pruned
+            if let Some(block) = blockdb.get(hash).unwrap() {
+                if let Content::Transaction(content) = &block.content {
+                    self.insert(*hash, &content.transactions);
+                }
+            }
+        }
+    }
+
+    /// Check whether a transaction can go into a new transaction block.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), Exclusion> {
+        if self.transactions.contains_key(&transaction.hash()) {
+            Err(Exclusion::Duplicate)
+        } else if transaction
+            .input
+            .iter()
+            .any(|input| self.inputs.contains_key(input))
+        {
+            Err(Exclusion::Conflict)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn insert(&mut self, block: H256, transactions: &[Transaction]) {
+        let mut hashes = vec![];
+        let mut inputs = vec![];
+        let mut duplicates = 0;
+        let mut conflicts = 0;
+        for transaction in transactions {
+            match self.check(transaction) {
+                Ok(()) => {}
+                Err(Exclusion::Duplicate) => duplicates += 1,
+                Err(Exclusion::Conflict) => conflicts += 1,
+            }
+            hashes.push(transaction.hash());
+            inputs.extend_from_slice(&transaction.input);
+        }
+        PERFORMANCE_COUNTER.record_in_flight_transactions(
+            transactions.len(),
+            duplicates,
+            conflicts,
+        );
+        for hash in &hashes {
+            *self.transactions.entry(*hash).or_insert(0) += 1;
+        }
+        for input in &inputs {
+            *self.inputs.entry(*input).or_insert(0) += 1;
+        }
+        self.blocks.insert(block, (hashes, inputs));
+    }
+
+    fn remove(&mut self, block: &H256) {
+        let (hashes, inputs) = match self.blocks.remove(block) {
+            Some(entry) => entry,
+            None => return,
+        };
+        for hash in &hashes {
+            decrement(&mut self.transactions, hash);
+        }
+        for input in &inputs {
+            decrement(&mut self.inputs, input);
+        }
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: &K) {
+    if let Some(count) = counts.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::tests::{input, transaction};
+
+    #[test]
+    fn duplicates_and_conflicts() {
+        let mut in_flight = InFlight::default();
+        let a = transaction(vec![input(1, 100)], &[10]);
+        let conflicting = transaction(vec![input(1, 100)], &[20]);
+        let b = transaction(vec![input(2, 100)], &[10]);
+        in_flight.insert([1; 32].into(), &[a.clone()]);
+        in_flight.insert([2; 32].into(), &[a.clone()]);
+        assert_eq!(in_flight.check(&a), Err(Exclusion::Duplicate));
+        assert_eq!(in_flight.check(&conflicting), Err(Exclusion::Conflict));
+        assert_eq!(in_flight.check(&b), Ok(()));
+        // the transaction stays in flight until both blocks are referred
+        in_flight.remove(&[1; 32].into());
+        assert_eq!(in_flight.check(&a), Err(Exclusion::Duplicate));
+        in_flight.remove(&[2; 32].into());
+        assert_eq!(in_flight.check(&a), Ok(()));
+        assert_eq!(in_flight.check(&conflicting), Ok(()));
+    }
+}
@@ -134,6 +134,20 @@ impl MemoryPool {
             .collect()
     }
 
+    /// get up to n transactions by fifo, skipping the ones that the filter rejects
+    pub fn select_transactions<F>(&self, n: u32, mut filter: F) -> Vec<Transaction>
+    where
+        F: FnMut(&Transaction) -> bool,
+    {
+        self.by_storage_index
+            .values()
+            .map(|hash| &self.get(hash).unwrap().transaction)
+            .filter(|t| filter(t))
+            .take(n as usize)
+            .cloned()
+            .collect()
+    }
+
     /// get size/length
     pub fn len(&self) -> usize {
         self.by_hash.len()
@@ -1,4 +1,10 @@
+mod adversary;
+mod in_flight;
 pub mod memory_pool;
+mod pow;
+
+pub use adversary::Strategy;
+pub use pow::SubmitError;
 
 use crate::block::header::Header;
 use crate::block::{proposer, transaction, voter};
@@ -9,28 +15,40 @@ use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
 use crate::crypto::merkle::MerkleTree;
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+use crate::experiment::simulation::Clock;
 use crate::handler::new_validated_block;
 use crate::network::message::Message;
 use crate::network::server::Handle as ServerHandle;
+use crate::utxodb::UtxoDatabase;
 
 use log::info;
 
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use adversary::Adversary;
+use crossbeam::channel::{unbounded, Receiver, Select, Sender, TryRecvError};
+use in_flight::{Exclusion, InFlight};
 use memory_pool::MemoryPool;
+use pow::{Solution, Template};
 use std::time;
 use std::time::SystemTime;
 
 use rand::distributions::Distribution;
-use std::collections::BTreeSet;
+use rand::rngs::StdRng;
+use std::collections::{BTreeSet, HashSet};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 use rand::Rng;
 
+/// Longest time to wait for the PoW threads before checking the control and context channels.
+const SOLUTION_WAIT_TIMEOUT: time::Duration = time::Duration::from_millis(100);
+/// Interval between two reports of the hash rate.
+const HASH_RATE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
 enum ControlSignal {
     Start(u64, bool), // the number controls the lambda of interval between block generation
     Step,
     Exit,
+    SetStrategy(Strategy),
 }
 
 #[derive(Ord, Eq, PartialOrd, PartialEq)]
@@ -55,6 +73,7 @@ enum OperatingState {
 pub struct Context {
     blockdb: Arc<BlockDatabase>,
     blockchain: Arc<BlockChain>,
+    utxodb: Arc<UtxoDatabase>,
     mempool: Arc<Mutex<MemoryPool>>,
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
@@ -67,22 +86,42 @@ pub struct Context {
     contents: Vec<Content>,
     content_merkle_tree: MerkleTree,
     config: BlockchainConfig,
+    /// Header shared with the PoW threads, when mining with real proof of work.
+    template: Arc<Template>,
+    /// Channel for receiving headers found by the PoW threads
+    solution_chan: Receiver<Solution>,
+    solution_tx: Sender<Solution>,
+    /// Source of time for block timestamps, and of simulated time in simulated mining.
+    clock: Arc<dyn Clock>,
+    rng: StdRng,
+    adversary: Adversary,
+    /// Transactions in unreferred transaction blocks, to keep out of our transaction blocks
+    in_flight: InFlight,
+    /// Memory pool transactions already counted as left out of our transaction blocks
+    skipped_transactions: HashSet<H256>,
 }
 
 #[derive(Clone)]
 pub struct Handle {
     // Channel for sending signal to the miner thread
     control_chan: Sender<ControlSignal>,
+    // Header template handed out to external miners
+    template: Arc<Template>,
+    // Channel for sending headers found by external miners
+    solution_chan: Sender<Solution>,
 }
 
 pub fn new(
     mempool: &Arc<Mutex<MemoryPool>>,
     blockchain: &Arc<BlockChain>,
     blockdb: &Arc<BlockDatabase>,
+    utxodb: &Arc<UtxoDatabase>,
     ctx_update_source: Receiver<ContextUpdateSignal>,
     ctx_update_tx: &Sender<ContextUpdateSignal>,
     server: &ServerHandle,
     config: BlockchainConfig,
+    clock: &Arc<dyn Clock>,
+    rng: StdRng,
 ) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
     let mut contents: Vec<Content> = vec![];
@@ -90,6 +129,7 @@ pub fn new(
     let proposer_content = proposer::Content {
         transaction_refs: vec![],
         proposer_refs: vec![],
+        utxo_commitment: None,
     };
     contents.push(Content::Proposer(proposer_content));
 
@@ -108,31 +148,46 @@ pub fn new(
     }
 
     let content_merkle_tree = MerkleTree::new(&contents);
+    let header = Header {
+        parent: config.proposer_genesis,
+        timestamp: clock.now(),
+        nonce: 0,
+        content_merkle_root: H256::default(),
+        extra_content: config.miner_tag,
+        miner: config.miner_address,
+        difficulty: *DEFAULT_DIFFICULTY,
+    };
+    let (solution_tx, solution_rx) = unbounded();
+    let template = Arc::new(Template::new(header));
 
     let ctx = Context {
         blockdb: Arc::clone(blockdb),
         blockchain: Arc::clone(blockchain),
+        utxodb: Arc::clone(utxodb),
         mempool: Arc::clone(mempool),
         control_chan: signal_chan_receiver,
         context_update_chan: ctx_update_source,
         context_update_tx: ctx_update_tx.clone(),
         operating_state: OperatingState::Paused,
         server: server.clone(),
-        header: Header {
-            parent: config.proposer_genesis,
-            timestamp: get_time(),
-            nonce: 0,
-            content_merkle_root: H256::default(),
-            extra_content: [0; 32],
-            difficulty: *DEFAULT_DIFFICULTY,
-        },
+        header,
         contents,
         content_merkle_tree,
         config,
+        template: Arc::clone(&template),
+        solution_chan: solution_rx,
+        solution_tx: solution_tx.clone(),
+        clock: Arc::clone(clock),
+        rng,
+        adversary: Adversary::default(),
+        in_flight: InFlight::default(),
+        skipped_transactions: HashSet::new(),
     };
 
     let handle = Handle {
         control_chan: signal_chan_sender,
+        template,
+        solution_chan: solution_tx,
     };
 
     (ctx, handle)
@@ -152,10 +207,45 @@ impl Handle {
     pub fn step(&self) {
         self.control_chan.send(ControlSignal::Step).unwrap();
     }
+
+    /// Switch the miner to the given adversarial strategy. Blocks withheld under the previous
+    /// strategy are released.
+    pub fn set_strategy(&self, strategy: Strategy) {
+        self.control_chan
+            .send(ControlSignal::SetStrategy(strategy))
+            .unwrap();
+    }
+
+    /// Get the version and the header of the current superblock template for external miners, or
+    /// `None` if the miner is not running.
+    pub fn work(&self) -> Option<(u64, Header)> {
+        self.template.get()
+    }
+
+    /// Submit a nonce, and optionally a new timestamp, found by an external miner for the given
+    /// version of the template. Return the hash of the block to be mined.
+    pub fn submit(
+        &self,
+        version: u64,
+        nonce: u32,
+        timestamp: Option<u128>,
+    ) -> Result<H256, SubmitError> {
+        let solution = self.template.check_solution(version, nonce, timestamp)?;
+        let hash = solution.hash;
+        self.solution_chan.send(solution).unwrap();
+        Ok(hash)
+    }
 }
 
 impl Context {
     pub fn start(mut self) {
+        if self.config.mining_threads > 0 {
+            pow::start_workers(
+                self.config.mining_threads,
+                &self.template,
+                &self.solution_tx,
+            );
+        }
         thread::Builder::new()
             .name("miner".to_string())
             .spawn(move || {
@@ -182,6 +272,70 @@ impl Context {
                 info!("Miner starting in stepping mode");
                 self.operating_state = OperatingState::Step;
             }
+            ControlSignal::SetStrategy(strategy) => {
+                info!("Miner switching to strategy {:?}", strategy);
+                let released = self.adversary.set_strategy(strategy);
+                self.release_blocks(released);
+                // rebuild all content under the new strategy
+                self.context_update_tx
+                    .send(ContextUpdateSignal::NewProposerBlock)
+                    .unwrap();
+                self.context_update_tx
+                    .send(ContextUpdateSignal::NewTransactionBlock)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Announce blocks we have withheld to peers.
+    fn release_blocks(&self, hashes: Vec<H256>) {
+        if !hashes.is_empty() {
+            info!("Releasing {} withheld blocks", hashes.len());
+            self.server.broadcast(Message::NewBlockHashes(hashes));
+        }
+    }
+
+    /// Get the level of the longest public proposer chain, which does not count our withheld
+    /// blocks.
+    fn public_proposer_level(&self) -> u64 {
+        let mut level = self.blockchain.best_proposer_level();
+        while level > 0 {
+            let proposers = self.blockchain.proposers_at_level(level).unwrap();
+            if proposers.iter().any(|h| !self.adversary.is_withheld(h)) {
+                break;
+            }
+            level -= 1;
+        }
+        level
+    }
+
+    /// Get the transaction blocks for our proposer block to refer to.
+    fn transaction_refs(&self) -> Vec<H256> {
+        let mut refs = self.blockchain.unreferred_transactions();
+        let censored = &self.adversary.strategy().censored_transactions;
+        if !censored.is_empty() {
+            refs.retain(|hash| match self.blockdb.get(hash).unwrap() {
+                Some(Block {
+                    content: Content::Transaction(c),
+                    ..
+                }) => !c.transactions.iter().any(|t| censored.contains(&t.hash())),
+                _ => true,
+            });
+        }
+        refs.truncate(self.config.proposer_tx_refs as usize);
+        refs
+    }
+
+    /// Get the votes of a voter block extending the given voter parent.
+    fn votes(&self, voter_parent: &H256) -> Vec<H256> {
+        if self.adversary.strategy().balance_votes {
+            self.blockchain
+                .balancing_votes(voter_parent, &self.header.parent)
+                .unwrap()
+        } else {
+            self.blockchain
+                .unvoted_proposer(voter_parent, &self.header.parent)
+                .unwrap()
         }
     }
 
@@ -196,7 +350,11 @@ impl Context {
                 .send(ContextUpdateSignal::NewVoterBlock(voter_chain as u16)).unwrap();
         }
 
-        let mut rng = rand::thread_rng();
+        let real_work = self.config.mining_threads > 0 || self.config.external_mining;
+        let mut hash_rate_start = (time::Instant::now(), 0);
+        // time of the next block of each chain in simulated mining, in microseconds
+        let mut arrivals: Vec<u128> = vec![];
+        let mut simulated_time: u128 = 0;
 
         // main mining loop
         loop {
@@ -205,11 +363,13 @@ impl Context {
             // check and react to control signals
             match self.operating_state {
                 OperatingState::Paused => {
+                    self.template.pause();
                     let signal = self.control_chan.recv().unwrap();
                     self.handle_control_signal(signal);
                     continue;
                 }
                 OperatingState::ShutDown => {
+                    self.template.pause();
                     return;
                 }
                 _ => match self.control_chan.try_recv() {
@@ -221,6 +381,7 @@ impl Context {
                 },
             }
             if let OperatingState::ShutDown = self.operating_state {
+                self.template.pause();
                 return;
             }
 
@@ -238,6 +399,15 @@ impl Context {
                 }
             }
 
+            // release the withheld blocks that are due
+            let public_level = if self.adversary.has_private_chain() {
+                self.public_proposer_level()
+            } else {
+                self.blockchain.best_proposer_level()
+            };
+            let released = self.adversary.release(public_level, self.clock.now());
+            self.release_blocks(released);
+
             // handle context updates
             let mut touched_content: BTreeSet<u16> = BTreeSet::new();
             let mut voter_shift = false;
@@ -259,9 +429,39 @@ impl Context {
 
             // update transaction block content
             if new_transaction_block {
+                // leave out the transactions already in flight, so that parallel transaction
+                // blocks do not duplicate or conflict with each other
+                self.in_flight
+                    .update(&self.blockchain.unreferred_transactions(), &self.blockdb);
+                let in_flight = &self.in_flight;
+                let censored = &self.adversary.strategy().censored_transactions;
+                let skipped = &mut self.skipped_transactions;
+                let mut duplicates = 0;
+                let mut conflicts = 0;
                 let mempool = self.mempool.lock().unwrap();
-                let transactions = mempool.get_transactions(self.config.tx_txs);
+                // count each transaction once, however many times the template is rebuilt
+                // while it stays in the memory pool
+                skipped.retain(|hash| mempool.contains(hash));
+                let transactions = mempool.select_transactions(self.config.tx_txs, |t| {
+                    match in_flight.check(t) {
+                        Ok(()) => {}
+                        Err(Exclusion::Duplicate) => {
+                            if skipped.insert(t.hash()) {
+                                duplicates += 1;
+                            }
+                            return false;
+                        }
+                        Err(Exclusion::Conflict) => {
+                            if skipped.insert(t.hash()) {
+                                conflicts += 1;
+                            }
+                            return false;
+                        }
+                    }
+                    censored.is_empty() || !censored.contains(&t.hash())
+                });
                 drop(mempool);
+                PERFORMANCE_COUNTER.record_skip_transactions(duplicates, conflicts);
                 let _chain_id: usize = TRANSACTION_INDEX as usize;
                 if let Content::Transaction(c) = &mut self.contents[TRANSACTION_INDEX as usize] {
                     c.transactions = transactions;
@@ -276,12 +476,11 @@ impl Context {
             // note that if there are new proposer blocks, we will need to refresh tx refs in the
             // next step. In that case, don't bother doing it here.
             if new_transaction_block && !new_proposer_block {
+                let transaction_refs = self.transaction_refs();
                 if let Content::Proposer(c) = &mut self.contents[PROPOSER_INDEX as usize] {
                     // only update the references if we are not running out of quota
                     if c.transaction_refs.len() < self.config.proposer_tx_refs as usize {
-                        let mut refs = self.blockchain.unreferred_transactions();
-                        refs.truncate(self.config.proposer_tx_refs as usize);
-                        c.transaction_refs = refs;
+                        c.transaction_refs = transaction_refs;
                         touched_content.insert(PROPOSER_INDEX);
                     }
                 } else {
@@ -306,13 +505,21 @@ impl Context {
                 // first refresh the transaction and proposer refs if there has been a new proposer
                 // block
                 if new_proposer_block {
+                    let transaction_refs = self.transaction_refs();
                     if let Content::Proposer(c) = &mut self.contents[PROPOSER_INDEX as usize] {
-                        let mut refs = self.blockchain.unreferred_transactions();
-                        refs.truncate(self.config.proposer_tx_refs as usize);
-                        c.transaction_refs = refs;
+                        c.transaction_refs = transaction_refs;
                         c.proposer_refs = self.blockchain.unreferred_proposers();
                         let parent = self.header.parent;
                         c.proposer_refs.retain(|&x| x != parent);
+                        // commit to the utxo set of the level that is the commitment delay below
+                        // the block we are mining
+                        let level = self.blockchain.proposer_level(&parent).unwrap() + 1;
+                        c.utxo_commitment = match level.checked_sub(UTXO_COMMITMENT_DELAY) {
+                            Some(committed_level) if committed_level > 0 => {
+                                self.utxodb.commitment_at(committed_level)
+                            }
+                            _ => None,
+                        };
                         touched_content.insert(PROPOSER_INDEX);
                     } else {
                         unreachable!();
@@ -339,11 +546,9 @@ impl Context {
                     } else {
                         unreachable!();
                     };
+                    let votes = self.votes(&voter_parent);
                     if let Content::Voter(c) = &mut self.contents[chain_id] {
-                        c.votes = self
-                            .blockchain
-                            .unvoted_proposer(&voter_parent, &self.header.parent)
-                            .unwrap();
+                        c.votes = votes;
                         touched_content.insert(chain_id as u16);
                     } else {
                         unreachable!();
@@ -357,11 +562,9 @@ impl Context {
                     } else {
                         unreachable!();
                     };
+                    let votes = self.votes(&voter_parent);
                     if let Content::Voter(c) = &mut self.contents[chain_id] {
-                        c.votes = self
-                            .blockchain
-                            .unvoted_proposer(&voter_parent, &self.header.parent)
-                            .unwrap();
+                        c.votes = votes;
                         touched_content.insert(chain_id as u16);
                     } else {
                         unreachable!();
@@ -370,7 +573,11 @@ impl Context {
             }
 
             // update the difficulty
-            self.header.difficulty = self.get_difficulty(&self.header.parent);
+            self.header.difficulty = if real_work {
+                self.config.mining_difficulty
+            } else {
+                self.get_difficulty(&self.header.parent)
+            };
 
             // update or rebuild the merkle tree according to what we did in the last stage
             if new_proposer_block || voter_shift {
@@ -399,17 +606,31 @@ impl Context {
             }
 
             // update merkle root if anything happened in the last stage
-            if new_proposer_block || !new_voter_block.is_empty() || new_transaction_block {
+            let content_changed =
+                new_proposer_block || !new_voter_block.is_empty() || new_transaction_block;
+            if content_changed {
                 self.header.content_merkle_root = self.content_merkle_tree.root();
             }
 
-            // try a new nonce, and update the timestamp
-            self.header.nonce = rng.gen();
-            self.header.timestamp = get_time();
+            let mined = if real_work {
+                // hand the new header to the PoW threads, and wait for them to find a nonce or
+                // for the context to change
+                if content_changed || self.template.version() == 0 {
+                    self.template.publish(self.header);
+                }
+                self.record_hash_rate(&mut hash_rate_start);
+                self.wait_for_solution()
+            } else if self.config.simulated_mining {
+                Some(self.simulate_block(&mut arrivals, &mut simulated_time))
+            } else {
+                // try a new nonce, and update the timestamp
+                self.header.nonce = self.rng.gen();
+                self.header.timestamp = self.clock.now();
+                Some(self.header.hash())
+            };
 
             // Check if we successfully mined a block
-            let header_hash = self.header.hash();
-            if header_hash < self.header.difficulty {
+            if let Some(header_hash) = mined.filter(|h| *h < self.header.difficulty) {
                 // Create a block
                 let mined_block: Block = self.produce_block(header_hash);
                 //if the mined block is an empty tx block, we ignore it, and go straight to next mining loop
@@ -445,8 +666,14 @@ impl Context {
                     // broadcast after adding the new block to the blockchain, in case a peer mines
                     // a block immediately after we broadcast, leaving us non time to insert into
                     // the blockchain
-                    self.server
-                        .broadcast(Message::NewBlockHashes(vec![header_hash]));
+                    let level = self.blockchain.proposer_level(&self.header.parent).unwrap() + 1;
+                    if !self
+                        .adversary
+                        .withhold(&mined_block, level, self.clock.now())
+                    {
+                        self.server
+                            .broadcast(Message::NewBlockHashes(vec![header_hash]));
+                    }
                     // if we are stepping, pause the miner loop
                     if let OperatingState::Step = self.operating_state {
                         self.operating_state = OperatingState::Paused;
@@ -470,9 +697,9 @@ impl Context {
             }
 
             if let OperatingState::Run(i, _) = self.operating_state {
-                if i != 0 {
+                if i != 0 && !real_work && !self.config.simulated_mining {
                     let interval_dist = rand::distributions::Exp::new(1.0 / (i as f64));
-                    let interval = interval_dist.sample(&mut rng);
+                    let interval = interval_dist.sample(&mut self.rng);
                     let interval = time::Duration::from_micros(interval as u64);
                     let time_spent = time::Instant::now().duration_since(block_start);
                     if interval > time_spent {
@@ -483,6 +710,84 @@ impl Context {
         }
     }
 
+    /// Wait until the PoW threads find a nonce for the current template, or until there is a
+    /// control signal or context update. Return the hash of the header found, which is then
+    /// stored in our header.
+    fn wait_for_solution(&mut self) -> Option<H256> {
+        let mut sel = Select::new();
+        sel.recv(&self.solution_chan);
+        sel.recv(&self.control_chan);
+        sel.recv(&self.context_update_chan);
+        let _ = sel.ready_timeout(SOLUTION_WAIT_TIMEOUT);
+        let version = self.template.version();
+        for solution in self.solution_chan.try_iter() {
+            // drop the solutions for stale templates
+            if solution.version == version {
+                self.header = solution.header;
+                return Some(solution.hash);
+            }
+        }
+        None
+    }
+
+    /// Advance the clock to the next block among all chains, whose inter-arrival times are drawn
+    /// from the mining rates, and find a nonce that sortitions the header into that chain. Return
+    /// the hash of the header.
+    fn simulate_block(&mut self, arrivals: &mut Vec<u128>, simulated_time: &mut u128) -> H256 {
+        let num_chains = FIRST_VOTER_INDEX as usize + self.config.voter_chains as usize;
+        while arrivals.len() < num_chains {
+            let chain = arrivals.len();
+            let arrival = *simulated_time + self.block_interval(chain);
+            arrivals.push(arrival);
+        }
+        let (chain, arrival) = arrivals
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, t)| **t)
+            .map(|(i, t)| (i, *t))
+            .unwrap();
+        self.clock.advance(time::Duration::from_micros(
+            (arrival - *simulated_time) as u64,
+        ));
+        *simulated_time = arrival;
+        arrivals[chain] = arrival + self.block_interval(chain);
+
+        // search the nonces in order, so that the draws from the RNG do not depend on the content
+        self.header.timestamp = self.clock.now();
+        self.header.nonce = 0;
+        loop {
+            let hash = self.header.hash();
+            let sortition_id = self.config.sortition_hash(&hash, &self.header.difficulty);
+            if sortition_id == Some(chain as u16) {
+                return hash;
+            }
+            self.header.nonce += 1;
+        }
+    }
+
+    /// Draw the time until the next block of the given chain, in microseconds.
+    fn block_interval(&mut self, chain: usize) -> u128 {
+        let rate = match chain as u16 {
+            PROPOSER_INDEX => self.config.proposer_mining_rate,
+            TRANSACTION_INDEX => self.config.tx_mining_rate,
+            _ => self.config.voter_mining_rate,
+        };
+        let interval_dist = rand::distributions::Exp::new(f64::from(rate));
+        let interval = interval_dist.sample(&mut self.rng);
+        (interval * 1_000_000.0) as u128
+    }
+
+    /// Report the rate at which the PoW threads compute hashes, about once per second.
+    fn record_hash_rate(&self, start: &mut (time::Instant, usize)) {
+        let elapsed = start.0.elapsed();
+        if elapsed >= HASH_RATE_INTERVAL {
+            let hashes = PERFORMANCE_COUNTER.snapshot().computed_hashes;
+            let rate = (hashes - start.1) as f64 / elapsed.as_secs_f64();
+            PERFORMANCE_COUNTER.record_hash_rate(rate as usize);
+            *start = (time::Instant::now(), hashes);
+        }
+    }
+
     /// Given a valid header, sortition its hash and create the block
     fn produce_block(&self, header_hash: H256) -> Block {
         // Get sortition ID
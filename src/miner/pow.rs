@@ -0,0 +1,216 @@
+use crate::block::header::Header;
+use crate::crypto::hash::{Hashable, H256};
+use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
+
+use crossbeam::channel::Sender;
+use log::info;
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+/// Number of nonces a worker tries between two checks of the template version.
+const BATCH_SIZE: u64 = 1024;
+/// Time an idle worker sleeps before checking for work again.
+const IDLE_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+/// The header that the PoW workers search a nonce for. Its version is bumped whenever the miner
+/// changes the header, so that workers abandon stale work.
+pub struct Template {
+    header: Mutex<Header>,
+    version: AtomicU64,
+    active: AtomicBool,
+}
+
+/// A header whose hash is below the difficulty, found by a worker.
+pub struct Solution {
+    /// Version of the template that the header is built on.
+    pub version: u64,
+    pub header: Header,
+    pub hash: H256,
+}
+
+/// Reasons for rejecting a header submitted by an external miner.
+#[derive(Debug, PartialEq)]
+pub enum SubmitError {
+    /// The miner is not running, so there is no work to submit.
+    Inactive,
+    /// The work has been replaced by a newer template.
+    Stale,
+    /// The hash of the header is not below the difficulty.
+    AboveDifficulty,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubmitError::Inactive => write!(f, "miner is not running"),
+            SubmitError::Stale => write!(f, "stale work"),
+            SubmitError::AboveDifficulty => write!(f, "hash above difficulty"),
+        }
+    }
+}
+
+impl error::Error for SubmitError {}
+
+impl Template {
+    pub fn new(header: Header) -> Self {
+        Self {
+            header: Mutex::new(header),
+            version: AtomicU64::new(0),
+            active: AtomicBool::new(false),
+        }
+    }
+
+    /// Get the current version of the template.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Replace the header and put the workers to work on it.
+    pub fn publish(&self, header: Header) {
+        let mut current = self.header.lock().unwrap();
+        *current = header;
+        self.version.fetch_add(1, Ordering::AcqRel);
+        self.active.store(true, Ordering::Release);
+    }
+
+    /// Stop the workers until the next header is published.
+    pub fn pause(&self) {
+        self.active.store(false, Ordering::Release);
+        self.version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Get the version and the header to work on, or `None` if the miner is paused.
+    pub fn get(&self) -> Option<(u64, Header)> {
+        if !self.active.load(Ordering::Acquire) {
+            return None;
+        }
+        let header = self.header.lock().unwrap();
+        Some((self.version(), *header))
+    }
+
+    /// Check a nonce and optionally a new timestamp found for the given version of the template
+    /// by an external miner, and turn them into a solution.
+    pub fn check_solution(
+        &self,
+        version: u64,
+        nonce: u32,
+        timestamp: Option<u128>,
+    ) -> Result<Solution, SubmitError> {
+        let (current, mut header) = self.get().ok_or(SubmitError::Inactive)?;
+        if version != current {
+            return Err(SubmitError::Stale);
+        }
+        header.nonce = nonce;
+        if let Some(timestamp) = timestamp {
+            header.timestamp = timestamp;
+        }
+        let hash = header.hash();
+        if hash >= header.difficulty {
+            return Err(SubmitError::AboveDifficulty);
+        }
+        Ok(Solution {
+            version,
+            header,
+            hash,
+        })
+    }
+}
+
+/// Start the given number of PoW workers on the template. Each of them searches a disjoint range
+/// of nonces, and sends the headers it finds to the solution channel.
+pub fn start_workers(
+    num_workers: usize,
+    template: &Arc<Template>,
+    solution_chan: &Sender<Solution>,
+) {
+    let range_size = (u64::from(std::u32::MAX) + 1) / num_workers as u64;
+    for index in 0..num_workers {
+        let template = Arc::clone(template);
+        let solution_chan = solution_chan.clone();
+        let range_start = index as u64 * range_size;
+        thread::Builder::new()
+            .name(format!("pow-{}", index))
+            .spawn(move || loop {
+                let (version, mut header) = match template.get() {
+                    Some(work) => work,
+                    None => {
+                        thread::sleep(IDLE_INTERVAL);
+                        continue;
+                    }
+                };
+                let mut offset: u64 = 0;
+                header.timestamp = super::get_time();
+                // search our nonce range until the template changes
+                while template.version() == version {
+                    for _ in 0..BATCH_SIZE {
+                        if offset == range_size {
+                            // the range is exhausted, so move on to a new timestamp
+                            offset = 0;
+                            header.timestamp = super::get_time();
+                        }
+                        header.nonce = (range_start + offset) as u32;
+                        offset += 1;
+                        let hash = header.hash();
+                        if hash < header.difficulty {
+                            solution_chan
+                                .send(Solution {
+                                    version,
+                                    header,
+                                    hash,
+                                })
+                                .unwrap();
+                        }
+                    }
+                    PERFORMANCE_COUNTER.record_hashes(BATCH_SIZE as usize);
+                }
+            })
+            .unwrap();
+    }
+    info!("Started {} proof of work threads", num_workers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(difficulty: [u8; 32]) -> Header {
+        Header::new(
+            H256::default(),
+            0,
+            0,
+            H256::default(),
+            [0; 32],
+            difficulty.into(),
+        )
+    }
+
+    #[test]
+    fn check_solution() {
+        let template = Template::new(header([255; 32]));
+        assert_eq!(
+            template.check_solution(0, 1, None).err(),
+            Some(SubmitError::Inactive)
+        );
+        template.publish(header([255; 32]));
+        let version = template.version();
+        let solution = template.check_solution(version, 1, Some(42)).unwrap();
+        assert_eq!(solution.header.nonce, 1);
+        assert_eq!(solution.header.timestamp, 42);
+        assert_eq!(solution.hash, solution.header.hash());
+        assert_eq!(
+            template.check_solution(version - 1, 1, None).err(),
+            Some(SubmitError::Stale)
+        );
+        template.publish(header([0; 32]));
+        assert_eq!(
+            template.check_solution(template.version(), 1, None).err(),
+            Some(SubmitError::AboveDifficulty)
+        );
+        template.pause();
+        assert_eq!(template.get().map(|(v, _)| v), None);
+    }
+}
@@ -1,10 +1,33 @@
 use crate::block::Block;
 use crate::crypto::hash::{Hashable, H256};
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Order of eviction of buffered blocks: the deepest last, and among them the oldest.
+type Rank = (usize, Reverse<Instant>, H256);
+
+/// A block waiting for its dependencies.
+struct BufferedBlock {
+    block: Block,
+    /// The peer that sent the block.
+    peer: SocketAddr,
+    /// When the block was buffered.
+    received: Instant,
+    /// Number of buffered blocks on the longest dependency chain ending at this block.
+    depth: usize,
+}
+
+impl BufferedBlock {
+    fn rank(&self, hash: H256) -> Rank {
+        (self.depth, Reverse(self.received), hash)
+    }
+}
 
 pub struct BlockBuffer {
     /// All blocks that have been received but not processed.
-    blocks: HashMap<H256, Block>,
+    blocks: HashMap<H256, BufferedBlock>,
     // TODO: we could use a sorted vector for better performance
     /// Mapping between all blocks that have been received and not processed, and their
     /// dependencies
@@ -12,19 +35,47 @@ pub struct BlockBuffer {
     /// Mapping between all blocks that have not been processed (but either received or
     /// not), and their dependents
     dependent: HashMap<H256, HashSet<H256>>,
+    /// Buffered blocks in the order of eviction.
+    ranks: BTreeSet<Rank>,
+    /// Buffered blocks sent by each peer in the order of eviction.
+    peer_ranks: HashMap<SocketAddr, BTreeSet<Rank>>,
+    /// Maximum number of buffered blocks.
+    max_blocks: usize,
+    /// Maximum number of buffered blocks sent by one peer.
+    max_peer_blocks: usize,
+    /// Time after which a buffered block is dropped.
+    expiry: Duration,
 }
 
 impl BlockBuffer {
-    pub fn new() -> Self {
+    pub fn new(max_blocks: usize, max_peer_blocks: usize, expiry: Duration) -> Self {
         Self {
             blocks: HashMap::new(),
             dependency: HashMap::new(),
             dependent: HashMap::new(),
+            ranks: BTreeSet::new(),
+            peer_ranks: HashMap::new(),
+            max_blocks,
+            max_peer_blocks,
+            expiry,
         }
     }
 
-    /// Buffer a block whose parent and/or references are missing.
-    pub fn insert(&mut self, block: Block, dependencies: &[H256]) {
+    /// Get the number of buffered blocks.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Buffer a block whose parent and/or references are missing. If the buffer is then over the
+    /// limit of the peer or the global limit, the blocks at the end of the deepest dependency
+    /// chains are evicted, and their hashes are returned. They may include the new block.
+    pub fn insert(
+        &mut self,
+        block: Block,
+        dependencies: &[H256],
+        peer: SocketAddr,
+        now: Instant,
+    ) -> Vec<H256> {
         // Potential race condition here: if X depends on A. Suppose X is received first and
         // validation finds that we miss block A. Then we need to insert X. However, at this moment
         // A comes. Unaware of X, we just process A without marking X's deps as satisfied. Then X
@@ -32,8 +83,9 @@ impl BlockBuffer {
         // validation and buffer insert are not one atomic operation (and we probably don't want
         // to do so). Conclusion: for now we make validation and buffer an atomic operation.
         let hash = block.hash();
-
-        self.blocks.insert(hash, block);
+        if self.blocks.contains_key(&hash) {
+            return vec![];
+        }
 
         let mut dependency = HashSet::new();
         for dep_hash in dependencies {
@@ -46,6 +98,34 @@ impl BlockBuffer {
             dependent.insert(hash);
         }
         self.dependency.insert(hash, dependency);
+
+        let buffered = BufferedBlock {
+            block,
+            peer,
+            received: now,
+            depth: self.buffered_depth(&hash),
+        };
+        self.ranks.insert(buffered.rank(hash));
+        self.peer_ranks
+            .entry(peer)
+            .or_default()
+            .insert(buffered.rank(hash));
+        self.blocks.insert(hash, buffered);
+        // blocks received earlier may depend on this one
+        self.update_dependents(&hash);
+
+        let mut evicted = vec![];
+        while self.peer_ranks.get(&peer).map_or(0, |r| r.len()) > self.max_peer_blocks {
+            let (_, _, deepest) = *self.peer_ranks[&peer].iter().next_back().unwrap();
+            self.remove(&deepest);
+            evicted.push(deepest);
+        }
+        while self.blocks.len() > self.max_blocks {
+            let (_, _, deepest) = *self.ranks.iter().next_back().unwrap();
+            self.remove(&deepest);
+            evicted.push(deepest);
+        }
+        evicted
     }
 
     /// Mark that the given block has been processed.
@@ -59,10 +139,227 @@ impl BlockBuffer {
                 dependency.remove(&hash);
                 if dependency.is_empty() {
                     self.dependency.remove(&node).unwrap();
-                    resolved_blocks.push(self.blocks.remove(&node).unwrap());
+                    let buffered = self.unbuffer(node);
+                    resolved_blocks.push(buffered.block);
                 }
             }
         }
+        for block in &resolved_blocks {
+            self.update_dependents(&block.hash());
+        }
         resolved_blocks
     }
+
+    /// Drop the blocks buffered for longer than the expiry time, and return their hashes.
+    pub fn expire(&mut self, now: Instant) -> Vec<H256> {
+        let expired: Vec<H256> = self
+            .blocks
+            .iter()
+            .filter(|(_, b)| now.duration_since(b.received) >= self.expiry)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in &expired {
+            self.remove(hash);
+        }
+        expired
+    }
+
+    /// Get the dependencies of the buffered blocks that are not buffered themselves.
+    pub fn missing(&self) -> Vec<H256> {
+        let mut missing: Vec<H256> = self
+            .dependent
+            .keys()
+            .filter(|hash| !self.blocks.contains_key(hash))
+            .cloned()
+            .collect();
+        missing.sort();
+        missing
+    }
+
+    /// Drop a buffered block. Blocks depending on it keep waiting for it.
+    fn remove(&mut self, hash: &H256) {
+        if !self.blocks.contains_key(hash) {
+            return;
+        }
+        self.unbuffer(hash);
+        for dep_hash in self.dependency.remove(hash).unwrap() {
+            let dependent = self.dependent.get_mut(&dep_hash).unwrap();
+            dependent.remove(hash);
+            if dependent.is_empty() {
+                self.dependent.remove(&dep_hash);
+            }
+        }
+        self.update_dependents(hash);
+    }
+
+    /// Take a block out of the buffered blocks and their ranks.
+    fn unbuffer(&mut self, hash: &H256) -> BufferedBlock {
+        let buffered = self.blocks.remove(hash).unwrap();
+        let rank = buffered.rank(*hash);
+        self.ranks.remove(&rank);
+        let peer_ranks = self.peer_ranks.get_mut(&buffered.peer).unwrap();
+        peer_ranks.remove(&rank);
+        if peer_ranks.is_empty() {
+            self.peer_ranks.remove(&buffered.peer);
+        }
+        buffered
+    }
+
+    /// Get the depth of a block from the depths of its buffered dependencies.
+    fn buffered_depth(&self, hash: &H256) -> usize {
+        self.dependency[hash]
+            .iter()
+            .filter_map(|d| self.blocks.get(d))
+            .map(|b| b.depth)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    /// Update the depths of the buffered blocks depending on the given block, after it is buffered
+    /// or no longer buffered.
+    fn update_dependents(&mut self, hash: &H256) {
+        let mut queue: VecDeque<H256> = VecDeque::new();
+        if let Some(dependents) = self.dependent.get(hash) {
+            queue.extend(dependents.iter().filter(|d| self.blocks.contains_key(d)));
+        }
+        while let Some(node) = queue.pop_front() {
+            let depth = self.buffered_depth(&node);
+            let buffered = self.blocks.get_mut(&node).unwrap();
+            if buffered.depth == depth {
+                continue;
+            }
+            let old_rank = buffered.rank(node);
+            buffered.depth = depth;
+            let new_rank = buffered.rank(node);
+            let peer_ranks = self.peer_ranks.get_mut(&buffered.peer).unwrap();
+            peer_ranks.remove(&old_rank);
+            peer_ranks.insert(new_rank);
+            self.ranks.remove(&old_rank);
+            self.ranks.insert(new_rank);
+            if let Some(dependents) = self.dependent.get(&node) {
+                queue.extend(dependents.iter().filter(|d| self.blocks.contains_key(d)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::header::Header;
+    use crate::block::{proposer, Content};
+
+    fn block(parent: H256) -> Block {
+        let header = Header::new(parent, 0, 0, H256::default(), [0; 32], H256::default());
+        Block::from_header(
+            header,
+            Content::Proposer(proposer::Content::default()),
+            vec![],
+        )
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn evict_deepest() {
+        let mut buffer = BlockBuffer::new(3, 3, Duration::from_secs(60));
+        let now = Instant::now();
+        let missing: H256 = [1; 32].into();
+        // a chain of three blocks waiting for a missing block
+        let a = block(missing);
+        let b = block(a.hash());
+        let c = block(b.hash());
+        assert!(buffer
+            .insert(b.clone(), &[a.hash()], peer(1), now)
+            .is_empty());
+        assert!(buffer
+            .insert(c.clone(), &[b.hash()], peer(2), now)
+            .is_empty());
+        assert!(buffer
+            .insert(a.clone(), &[missing], peer(1), now)
+            .is_empty());
+        assert_eq!(buffer.missing(), vec![missing]);
+        // the end of the chain is evicted to make room
+        let d = block([2; 32].into());
+        assert_eq!(
+            buffer.insert(d, &[[2; 32].into()], peer(2), now),
+            vec![c.hash()]
+        );
+        assert_eq!(buffer.len(), 3);
+        // the rest of the chain is still resolved
+        let resolved = buffer.satisfy(missing);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].hash(), a.hash());
+        assert_eq!(buffer.satisfy(a.hash())[0].hash(), b.hash());
+        assert!(buffer.satisfy(b.hash()).is_empty());
+    }
+
+    #[test]
+    fn peer_limit() {
+        let mut buffer = BlockBuffer::new(10, 2, Duration::from_secs(60));
+        let now = Instant::now();
+        let blocks: Vec<Block> = (0..3).map(|i| block([i; 32].into())).collect();
+        for (i, b) in blocks.iter().enumerate() {
+            let later = now + Duration::from_secs(i as u64);
+            let evicted = buffer.insert(b.clone(), &[[i as u8; 32].into()], peer(1), later);
+            if i < 2 {
+                assert!(evicted.is_empty());
+            } else {
+                // all blocks are as deep, and the oldest goes first
+                assert_eq!(evicted, vec![blocks[0].hash()]);
+            }
+        }
+        assert!(buffer
+            .insert(block([9; 32].into()), &[[9; 32].into()], peer(2), now)
+            .is_empty());
+        assert_eq!(buffer.len(), 3);
+    }
+
+    #[test]
+    fn depth_after_removal() {
+        let mut buffer = BlockBuffer::new(5, 5, Duration::from_secs(60));
+        let now = Instant::now();
+        let later = now + Duration::from_secs(30);
+        // a chain of three blocks, whose first block expires
+        let a = block([1; 32].into());
+        let b = block(a.hash());
+        let c = block(b.hash());
+        buffer.insert(a.clone(), &[[1; 32].into()], peer(1), now);
+        buffer.insert(b.clone(), &[a.hash()], peer(1), later);
+        buffer.insert(c.clone(), &[b.hash()], peer(1), later);
+        assert_eq!(buffer.expire(now + Duration::from_secs(60)), vec![a.hash()]);
+        assert_eq!(buffer.missing(), vec![a.hash()]);
+        // a newer chain of three blocks is now the deepest
+        let d = block([2; 32].into());
+        let e = block(d.hash());
+        let f = block(e.hash());
+        let latest = now + Duration::from_secs(40);
+        buffer.insert(d.clone(), &[[2; 32].into()], peer(2), latest);
+        buffer.insert(e.clone(), &[d.hash()], peer(2), latest);
+        assert!(buffer
+            .insert(f.clone(), &[e.hash()], peer(2), latest)
+            .is_empty());
+        let g = block([3; 32].into());
+        assert_eq!(
+            buffer.insert(g, &[[3; 32].into()], peer(2), latest),
+            vec![f.hash()]
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let mut buffer = BlockBuffer::new(10, 10, Duration::from_secs(60));
+        let now = Instant::now();
+        let a = block([1; 32].into());
+        let b = block([2; 32].into());
+        buffer.insert(a.clone(), &[[1; 32].into()], peer(1), now);
+        buffer.insert(b, &[[2; 32].into()], peer(1), now + Duration::from_secs(30));
+        assert!(buffer.expire(now + Duration::from_secs(59)).is_empty());
+        assert_eq!(buffer.expire(now + Duration::from_secs(60)), vec![a.hash()]);
+        assert!(buffer.satisfy([1; 32].into()).is_empty());
+        assert_eq!(buffer.len(), 1);
+    }
 }
@@ -1,5 +1,7 @@
+use crate::block::light::LightBlock;
 use crate::crypto::hash::H256;
-use crate::transaction::Transaction;
+use crate::light_client::TransactionProof;
+use crate::transaction::{Address, CoinId, Output, Transaction};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
@@ -12,4 +14,10 @@ pub enum Message {
     GetTransactions(Vec<H256>),
     Transactions(Vec<Transaction>),
     Bootstrap(H256),
+    GetUtxoSnapshotChunk(u64, u32),
+    UtxoSnapshotChunk(u64, u32, Option<(H256, Vec<(CoinId, Output)>)>),
+    GetHeaders(Vec<H256>),
+    Headers(Vec<LightBlock>),
+    GetTransactionProofs(u64, Vec<Address>),
+    TransactionProofs(u64, Option<(H256, Vec<TransactionProof>)>),
 }
@@ -4,7 +4,9 @@ use mio;
 use mio_extras::channel;
 use std::convert::TryInto;
 use std::io::{Read, Write};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::SystemTime;
 
 enum DecodeState {
     Length,
@@ -23,6 +25,7 @@ pub struct ReadContext {
     msg_length: usize,
     read_length: usize,
     state: DecodeState,
+    stats: Arc<Stats>,
 }
 
 impl ReadContext {
@@ -56,6 +59,11 @@ impl ReadContext {
                         }
                         DecodeState::Payload => {
                             let new_payload: Vec<u8> = self.buffer[0..self.msg_length].to_vec();
+                            self.stats.messages_in.fetch_add(1, Ordering::Relaxed);
+                            self.stats.bytes_in.fetch_add(
+                                self.msg_length + std::mem::size_of::<u32>(),
+                                Ordering::Relaxed,
+                            );
                             self.state = DecodeState::Length;
                             self.read_length = 0;
                             self.msg_length = std::mem::size_of::<u32>();
@@ -91,6 +99,7 @@ pub struct WriteContext {
     msg_length: usize,
     written_length: usize,
     state: WriteState,
+    stats: Arc<Stats>,
 }
 
 impl WriteContext {
@@ -137,6 +146,11 @@ impl WriteContext {
                             .copy_from_slice(&(self.msg_length as u32).to_be_bytes());
                         self.written_length = 0;
                         self.state = WriteState::Length;
+                        self.stats.messages_out.fetch_add(1, Ordering::Relaxed);
+                        self.stats.bytes_out.fetch_add(
+                            self.msg_length + std::mem::size_of::<u32>(),
+                            Ordering::Relaxed,
+                        );
                         continue;
                     } else {
                         // we are still sending the payload
@@ -162,6 +176,7 @@ pub fn new(
     let reader_stream = stream.try_clone()?;
     let writer_stream = stream.try_clone()?;
     let addr = stream.peer_addr()?;
+    let stats = Arc::new(Stats::default());
     let bufreader = std::io::BufReader::new(reader_stream);
     let read_ctx = ReadContext {
         reader: bufreader,
@@ -169,6 +184,7 @@ pub fn new(
         msg_length: std::mem::size_of::<u32>(),
         read_length: 0,
         state: DecodeState::Length,
+        stats: Arc::clone(&stats),
     };
     let bufwriter = std::io::BufWriter::new(writer_stream);
     let (write_sender, write_receiver) = channel::channel();
@@ -180,10 +196,12 @@ pub fn new(
         msg_length: 0,
         written_length: 0,
         state: WriteState::Payload,
+        stats: Arc::clone(&stats),
     };
     let handle = Handle {
         write_queue: write_sender,
         addr,
+        stats,
     };
     let ctx = Context {
         addr,
@@ -192,11 +210,13 @@ pub fn new(
         writer: write_ctx,
         handle: handle.clone(),
         direction,
+        connected_since: SystemTime::now(),
     };
     Ok((ctx, handle))
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     Incoming,
     Outgoing,
@@ -209,12 +229,24 @@ pub struct Context {
     pub writer: WriteContext,
     pub handle: Handle,
     pub direction: Direction,
+    pub connected_since: SystemTime,
+}
+
+/// Traffic and behavior statistics of a peer, shared between its context and handles.
+#[derive(Default)]
+pub struct Stats {
+    pub bytes_in: AtomicUsize,
+    pub bytes_out: AtomicUsize,
+    pub messages_in: AtomicUsize,
+    pub messages_out: AtomicUsize,
+    pub misbehavior: AtomicUsize,
 }
 
 #[derive(Clone)]
 pub struct Handle {
     addr: std::net::SocketAddr,
     write_queue: channel::Sender<Vec<u8>>,
+    stats: Arc<Stats>,
 }
 
 impl Handle {
@@ -225,4 +257,17 @@ impl Handle {
             warn!("Failed to send write request for peer {}, channel detached", self.addr);
         }
     }
+
+    /// Record that the peer has sent us invalid data.
+    pub fn report_misbehavior(&self) {
+        self.stats.misbehavior.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
 }
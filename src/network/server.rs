@@ -5,8 +5,10 @@ use crossbeam::channel as cbchannel;
 use log::{debug, error, info, trace, warn};
 use mio::{self, net};
 use mio_extras::channel;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::thread;
+use std::time::SystemTime;
 
 const MAX_INCOMING_CLIENT: usize = 256;
 const MAX_EVENT: usize = 1024;
@@ -125,6 +127,49 @@ impl Context {
         Ok(())
     }
 
+    /// Disconnect from a peer. Returns whether the peer was connected.
+    fn disconnect(&mut self, addr: &std::net::SocketAddr) -> bool {
+        let peer_id = match self
+            .peer_list
+            .iter()
+            .position(|&x| self.peers[x].addr == *addr)
+        {
+            Some(index) => self.peer_list.swap_remove(index),
+            None => return false,
+        };
+        let peer = self.peers.remove(peer_id);
+        if let Err(e) = peer.stream.shutdown(std::net::Shutdown::Both) {
+            warn!("Error shutting down connection to peer {}: {}", addr, e);
+        }
+        info!("Disconnected from peer {}", addr);
+        true
+    }
+
+    /// Collect the information of all connected peers.
+    fn peer_info(&self) -> Vec<PeerInfo> {
+        self.peer_list
+            .iter()
+            .map(|&peer_id| {
+                let peer = &self.peers[peer_id];
+                let stats = peer.handle.stats();
+                PeerInfo {
+                    addr: peer.addr,
+                    direction: peer.direction,
+                    bytes_in: stats.bytes_in.load(Ordering::Relaxed),
+                    bytes_out: stats.bytes_out.load(Ordering::Relaxed),
+                    messages_in: stats.messages_in.load(Ordering::Relaxed),
+                    messages_out: stats.messages_out.load(Ordering::Relaxed),
+                    connected_since: peer
+                        .connected_since
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64,
+                    misbehavior_score: stats.misbehavior.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+
     fn process_control(&mut self, req: ControlSignal) -> std::io::Result<()> {
         match req {
             ControlSignal::ConnectNewPeer(req) => {
@@ -138,6 +183,15 @@ impl Context {
                     self.peers[*peer_id].handle.write(msg.clone());
                 }
             }
+            ControlSignal::DisconnectPeer(req) => {
+                trace!("Processing DisconnectPeer command");
+                let result = self.disconnect(&req.addr);
+                req.result_chan.send(result).unwrap();
+            }
+            ControlSignal::GetPeers(result_chan) => {
+                trace!("Processing GetPeers command");
+                result_chan.send(self.peer_info()).unwrap();
+            }
         }
         Ok(())
     }
@@ -389,14 +443,55 @@ impl Handle {
             .send(ControlSignal::BroadcastMessage(msg))
             .unwrap();
     }
+
+    /// Disconnect from the peer at the given address. Returns whether the peer was connected.
+    pub fn disconnect(&self, addr: std::net::SocketAddr) -> bool {
+        let (sender, receiver) = cbchannel::unbounded();
+        let request = DisconnectRequest {
+            addr,
+            result_chan: sender,
+        };
+        self.control_chan
+            .send(ControlSignal::DisconnectPeer(request))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let (sender, receiver) = cbchannel::unbounded();
+        self.control_chan
+            .send(ControlSignal::GetPeers(sender))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+}
+
+#[derive(Serialize)]
+pub struct PeerInfo {
+    pub addr: std::net::SocketAddr,
+    pub direction: peer::Direction,
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub messages_in: usize,
+    pub messages_out: usize,
+    /// Time of connection in milliseconds since the UNIX epoch.
+    pub connected_since: u64,
+    pub misbehavior_score: usize,
 }
 
 enum ControlSignal {
     ConnectNewPeer(ConnectRequest),
     BroadcastMessage(message::Message),
+    DisconnectPeer(DisconnectRequest),
+    GetPeers(cbchannel::Sender<Vec<PeerInfo>>),
 }
 
 struct ConnectRequest {
     addr: std::net::SocketAddr,
     result_chan: cbchannel::Sender<std::io::Result<peer::Handle>>,
 }
+
+struct DisconnectRequest {
+    addr: std::net::SocketAddr,
+    result_chan: cbchannel::Sender<bool>,
+}
@@ -1,6 +1,7 @@
 use super::buffer::BlockBuffer;
 use super::message::Message;
 use super::peer;
+use crate::block::light::LightBlock;
 use crate::block::{Block, Content};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
@@ -9,9 +10,12 @@ use crate::crypto::hash::{Hashable, H256};
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::handler::new_transaction;
 use crate::handler::new_validated_block;
+use crate::ledger_manager::SnapshotChunk;
+use crate::light_client::{prove_transactions, TransactionProofs};
 use crate::miner::memory_pool::MemoryPool;
 use crate::miner::ContextUpdateSignal;
 use crate::network::server::Handle as ServerHandle;
+use crate::transaction::Transaction;
 use crate::utxodb::UtxoDatabase;
 use crate::validation::{self, BlockResult};
 use crate::wallet::Wallet;
@@ -19,13 +23,55 @@ use crossbeam::channel;
 use log::{debug, warn};
 use std::collections::HashSet;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum number of blocks waiting for the blocks they refer to.
+const MAX_BUFFERED_BLOCKS: usize = 10_000;
+/// Maximum number of blocks from one peer waiting for the blocks they refer to.
+const MAX_BUFFERED_BLOCKS_PER_PEER: usize = 2_000;
+/// Time after which a block still waiting for the blocks it refers to is dropped.
+const BUFFER_EXPIRY: Duration = Duration::from_secs(300);
+/// Interval between two requests for the blocks that buffered blocks are waiting for.
+const REREQUEST_INTERVAL: Duration = Duration::from_secs(5);
+/// Interval between two samples of the depth of the queues between the stages of block processing.
+const QUEUE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A transaction block whose signatures are being verified.
+struct PendingBlock {
+    /// The block and the peer it came from, taken by the thread verifying the last chunk.
+    block: Mutex<Option<(Block, peer::Handle)>>,
+    /// Number of chunks of the block whose signatures are not verified yet.
+    remaining_chunks: AtomicUsize,
+    /// Results of the chunks that fail signature verification.
+    failures: Mutex<Vec<BlockResult>>,
+}
+
+/// A chunk of transactions of a pending block to verify the signatures of.
+struct VerificationJob {
+    pending: Arc<PendingBlock>,
+    transactions: Vec<Transaction>,
+    /// Position of the first transaction of the chunk in the block.
+    offset: usize,
+}
 
 #[derive(Clone)]
 pub struct Context {
     msg_chan: channel::Receiver<(Vec<u8>, peer::Handle)>,
     num_worker: usize,
+    num_verifier: usize,
+    // Number of transactions whose signatures are verified in one batch
+    signature_batch: usize,
+    verification_chan: (
+        channel::Sender<VerificationJob>,
+        channel::Receiver<VerificationJob>,
+    ),
+    insertion_chan: (
+        channel::Sender<(Block, peer::Handle)>,
+        channel::Receiver<(Block, peer::Handle)>,
+    ),
     chain: Arc<BlockChain>,
     blockdb: Arc<BlockDatabase>,
     utxodb: Arc<UtxoDatabase>,
@@ -37,10 +83,14 @@ pub struct Context {
     recent_blocks: Arc<Mutex<HashSet<H256>>>, // blocks that we have received but not yet inserted
     requested_blocks: Arc<Mutex<HashSet<H256>>>, // blocks that we have requested but not yet received
     config: BlockchainConfig,
+    snapshot_chunk_chan: Option<channel::Sender<SnapshotChunk>>,
+    transaction_proof_chan: Option<channel::Sender<TransactionProofs>>,
 }
 
 pub fn new(
     num_worker: usize,
+    num_verifier: usize,
+    signature_batch: usize,
     msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
     blockchain: &Arc<BlockChain>,
     blockdb: &Arc<BlockDatabase>,
@@ -50,10 +100,16 @@ pub fn new(
     ctx_update_sink: channel::Sender<ContextUpdateSignal>,
     server: &ServerHandle,
     config: BlockchainConfig,
+    snapshot_chunk_sink: Option<channel::Sender<SnapshotChunk>>,
+    transaction_proof_sink: Option<channel::Sender<TransactionProofs>>,
 ) -> Context {
     Context {
         msg_chan: msg_src,
         num_worker,
+        num_verifier,
+        signature_batch,
+        verification_chan: channel::unbounded(),
+        insertion_chan: channel::unbounded(),
         chain: Arc::clone(blockchain),
         blockdb: Arc::clone(blockdb),
         utxodb: Arc::clone(utxodb),
@@ -61,10 +117,16 @@ pub fn new(
         mempool: Arc::clone(mempool),
         context_update_chan: ctx_update_sink,
         server: server.clone(),
-        buffer: Arc::new(Mutex::new(BlockBuffer::new())),
+        buffer: Arc::new(Mutex::new(BlockBuffer::new(
+            MAX_BUFFERED_BLOCKS,
+            MAX_BUFFERED_BLOCKS_PER_PEER,
+            BUFFER_EXPIRY,
+        ))),
         recent_blocks: Arc::new(Mutex::new(HashSet::new())),
         requested_blocks: Arc::new(Mutex::new(HashSet::new())),
         config,
+        snapshot_chunk_chan: snapshot_chunk_sink,
+        transaction_proof_chan: transaction_proof_sink,
     }
 }
 
@@ -78,6 +140,31 @@ impl Context {
                 warn!("Worker thread {} exited", i);
             });
         }
+        for i in 0..self.num_verifier {
+            let cloned = self.clone();
+            thread::spawn(move || {
+                cloned.verification_loop();
+                warn!("Signature verification thread {} exited", i);
+            });
+        }
+        // blocks only reach insertion once the blocks they refer to are in the blockchain, so they
+        // can be inserted in parallel, as many at a time as there are workers feeding them
+        for i in 0..num_worker {
+            let cloned = self.clone();
+            thread::spawn(move || {
+                cloned.insertion_loop();
+                warn!("Block insertion thread {} exited", i);
+            });
+        }
+        let cloned = self.clone();
+        thread::spawn(move || {
+            cloned.buffer_loop();
+            warn!("Block buffer maintenance thread exited");
+        });
+        thread::spawn(move || loop {
+            thread::sleep(QUEUE_SAMPLE_INTERVAL);
+            self.record_queues();
+        });
     }
 
     fn worker_loop(&self) {
@@ -143,11 +230,15 @@ impl Context {
                     }
                     drop(requested_blocks);
                     if !hashes_to_request.is_empty() {
-                        peer.write(Message::GetBlocks(hashes_to_request));
+                        self.request_blocks(hashes_to_request, &peer);
                     }
                 }
                 Message::GetBlocks(hashes) => {
                     debug!("Asked for {} blocks", hashes.len());
+                    // light clients do not keep the content of proposer blocks
+                    if self.config.light_client {
+                        continue;
+                    }
                     let mut blocks = vec![];
                     for hash in hashes {
                         match self.blockdb.get_encoded(&hash).unwrap() {
@@ -183,40 +274,16 @@ impl Context {
                         let pow_check = validation::check_pow_sortition_id(&block, &self.config);
                         match pow_check {
                             BlockResult::Pass => {}
-                            _ => continue,
+                            _ => {
+                                peer.report_misbehavior();
+                                continue;
+                            }
                         }
 
-                        // check whether the block is being processed. note that here we use lock
-                        // to make sure that the hash either in recent_blocks, or blockdb, so we
-                        // don't have a single duplicate
-                        let mut recent_blocks = self.recent_blocks.lock().unwrap();
-                        if recent_blocks.contains(&hash) {
-                            drop(recent_blocks);
-                            continue;
-                        }
-                        // register this block as being processed
-                        recent_blocks.insert(hash);
-                        drop(recent_blocks);
-
-                        // TODO: consider the ordering here. I'd expect a lot of duplicate blocks
-                        // to proceed to this step, which means a lot of useless database lookups
-                        // and lock/unlocks
-                        // detect duplicates
-                        if self.blockdb.contains(&hash).unwrap() {
-                            let mut recent_blocks = self.recent_blocks.lock().unwrap();
-                            recent_blocks.remove(&hash);
-                            drop(recent_blocks);
+                        if !self.store_new_block(&hash, &encoded_block) {
                             continue;
                         }
 
-                        // store the block into database
-                        self.blockdb.insert_encoded(&hash, &encoded_block).unwrap();
-
-                        // now that this block is store, remove the reference
-                        let mut recent_blocks = self.recent_blocks.lock().unwrap();
-                        recent_blocks.remove(&hash);
-                        drop(recent_blocks);
-
                         blocks.push(block);
                         hashes.push(hash);
                     }
@@ -232,98 +299,60 @@ impl Context {
                     }
                     self.server
                         .broadcast(Message::NewBlockHashes(hashes.clone()));
-
-                    // process each block
-                    let mut to_process: Vec<Block> = blocks;
-                    let mut to_request: Vec<H256> = vec![];
-                    let mut context_update_sig = vec![];
-                    while let Some(block) = to_process.pop() {
-                        // check data availability
-                        // make sure checking data availability and buffering are one atomic
-                        // operation. see the comments in buffer.rs
-                        let mut buffer = self.buffer.lock().unwrap();
-                        let data_availability =
-                            validation::check_data_availability(&block, &self.chain, &self.blockdb);
-                        match data_availability {
-                            BlockResult::Pass => drop(buffer),
-                            BlockResult::MissingReferences(r) => {
-                                debug!(
-                                    "Missing {} referred blocks for block {:.8}",
-                                    r.len(),
-                                    block.hash()
-                                );
-                                buffer.insert(block, &r);
-                                to_request.extend_from_slice(&r);
-                                drop(buffer);
-                                continue;
-                            }
-                            _ => unreachable!(),
+                    self.process_blocks(blocks, &peer);
+                }
+                Message::GetHeaders(hashes) => {
+                    debug!("Asked for {} headers", hashes.len());
+                    if self.config.light_client {
+                        continue;
+                    }
+                    let mut light_blocks = vec![];
+                    for hash in hashes {
+                        if let Some(block) = self.blockdb.get(&hash).unwrap() {
+                            light_blocks.push(LightBlock::from_block(&block));
                         }
+                    }
+                    peer.write(Message::Headers(light_blocks));
+                }
+                Message::Headers(light_blocks) => {
+                    debug!("Got {} headers", light_blocks.len());
+                    // full nodes never ask for headers
+                    if !self.config.light_client {
+                        peer.report_misbehavior();
+                        continue;
+                    }
+                    let mut blocks: Vec<Block> = vec![];
+                    for light_block in light_blocks {
+                        let hash = light_block.hash();
+                        let mut requested_blocks = self.requested_blocks.lock().unwrap();
+                        requested_blocks.remove(&hash);
+                        drop(requested_blocks);
 
-                        // check sortition proof and content semantics
-                        let sortition_proof =
-                            validation::check_sortition_proof(&block, &self.config);
-                        match sortition_proof {
-                            BlockResult::Pass => {}
-                            _ => {
-                                warn!(
-                                    "Ignoring invalid block {:.8}: {}",
-                                    block.hash(),
-                                    sortition_proof
-                                );
-                                continue;
-                            }
-                        }
-                        let content_semantic =
-                            validation::check_content_semantic(&block, &self.chain, &self.blockdb);
-                        match content_semantic {
+                        // check the PoW and the sortition proof here, since proposer blocks lose
+                        // their transaction block references when they are turned into full blocks
+                        let light_check = validation::check_light_block(&light_block, &self.config);
+                        match light_check {
                             BlockResult::Pass => {}
                             _ => {
-                                warn!(
-                                    "Ignoring invalid block {:.8}: {}",
-                                    block.hash(),
-                                    content_semantic
-                                );
+                                warn!("Ignoring invalid header {:.8}: {}", hash, light_check);
+                                peer.report_misbehavior();
                                 continue;
                             }
                         }
 
-                        debug!("Processing block {:.8}", block.hash());
-                        new_validated_block(
-                            &block,
-                            &self.mempool,
-                            &self.blockdb,
-                            &self.chain,
-                            &self.server,
-                        );
-                        context_update_sig.push(match &block.content {
-                            Content::Proposer(_) => ContextUpdateSignal::NewProposerBlock,
-                            Content::Voter(c) => ContextUpdateSignal::NewVoterBlock(c.chain_number),
-                            Content::Transaction(_) => ContextUpdateSignal::NewTransactionBlock,
-                        });
-                        let mut buffer = self.buffer.lock().unwrap();
-                        let mut resolved_by_current = buffer.satisfy(block.hash());
-                        drop(buffer);
-                        if !resolved_by_current.is_empty() {
-                            debug!(
-                                "Resolved dependency for {} buffered blocks",
-                                resolved_by_current.len()
-                            );
-                        }
-                        for b in resolved_by_current.drain(..) {
-                            to_process.push(b);
+                        // transaction blocks are not tracked
+                        let block = match light_block.into_block() {
+                            Some(block) => block,
+                            None => continue,
+                        };
+                        let encoded_block = bincode::serialize(&block).unwrap();
+                        if !self.store_new_block(&hash, &encoded_block) {
+                            continue;
                         }
+                        PERFORMANCE_COUNTER.record_receive_block(&block);
+                        blocks.push(block);
                     }
-                    // tell the miner to update the context
-                    for sig in context_update_sig {
-                        self.context_update_chan.send(sig).unwrap();
-                    }
-
-                    if !to_request.is_empty() {
-                        to_request.sort();
-                        to_request.dedup();
-                        peer.write(Message::GetBlocks(to_request));
-                    }
+                    self.process_blocks(blocks, &peer);
                 }
                 Message::Bootstrap(after) => {
                     debug!("Asked for all blocks after {}", &after);
@@ -334,7 +363,461 @@ impl Context {
                     }
                     */
                 }
+                Message::GetUtxoSnapshotChunk(level, index) => {
+                    debug!("Asked for UTXO snapshot chunk {} at level {}", index, level);
+                    let coins = self.utxodb.snapshot_chunk(level, index);
+                    peer.write(Message::UtxoSnapshotChunk(level, index, coins));
+                }
+                Message::UtxoSnapshotChunk(level, index, coins) => {
+                    debug!("Got UTXO snapshot chunk {} at level {}", index, level);
+                    if let Some(chan) = &self.snapshot_chunk_chan {
+                        chan.send(SnapshotChunk {
+                            peer: peer.clone(),
+                            level,
+                            index,
+                            coins,
+                        })
+                        .unwrap();
+                    }
+                }
+                Message::GetTransactionProofs(level, addresses) => {
+                    debug!(
+                        "Asked for transactions of {} addresses at level {}",
+                        addresses.len(),
+                        level
+                    );
+                    if self.config.light_client {
+                        continue;
+                    }
+                    let proofs = prove_transactions(level, &addresses, &self.chain, &self.blockdb);
+                    peer.write(Message::TransactionProofs(level, proofs));
+                }
+                Message::TransactionProofs(level, proofs) => {
+                    debug!("Got transaction proofs at level {}", level);
+                    if let Some(chan) = &self.transaction_proof_chan {
+                        chan.send(TransactionProofs {
+                            peer: peer.clone(),
+                            level,
+                            proofs,
+                        })
+                        .unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Store a newly arrived block, unless it is a duplicate. Return whether it is stored.
+    fn store_new_block(&self, hash: &H256, encoded_block: &[u8]) -> bool {
+        // check whether the block is being processed. note that here we use lock
+        // to make sure that the hash either in recent_blocks, or blockdb, so we
+        // don't have a single duplicate
+        let mut recent_blocks = self.recent_blocks.lock().unwrap();
+        if recent_blocks.contains(hash) {
+            drop(recent_blocks);
+            return false;
+        }
+        // register this block as being processed
+        recent_blocks.insert(*hash);
+        drop(recent_blocks);
+
+        // TODO: consider the ordering here. I'd expect a lot of duplicate blocks
+        // to proceed to this step, which means a lot of useless database lookups
+        // and lock/unlocks
+        // detect duplicates
+        if self.blockdb.contains(hash).unwrap() {
+            let mut recent_blocks = self.recent_blocks.lock().unwrap();
+            recent_blocks.remove(hash);
+            drop(recent_blocks);
+            return false;
+        }
+
+        // store the block into database
+        self.blockdb.insert_encoded(hash, encoded_block).unwrap();
+
+        // now that this block is store, remove the reference
+        let mut recent_blocks = self.recent_blocks.lock().unwrap();
+        recent_blocks.remove(hash);
+        drop(recent_blocks);
+        true
+    }
+
+    /// Check newly arrived blocks up to signature verification, buffering those whose references
+    /// are missing and asking the peer for the missing blocks. Transaction blocks are handed to the
+    /// signature verification threads in chunks, and other blocks go straight to insertion.
+    fn process_blocks(&self, blocks: Vec<Block>, peer: &peer::Handle) {
+        let mut to_request: Vec<H256> = vec![];
+        for block in blocks {
+            // check data availability
+            // make sure checking data availability and buffering are one atomic
+            // operation. see the comments in buffer.rs
+            let mut buffer = self.buffer.lock().unwrap();
+            let data_availability =
+                validation::check_data_availability(&block, &self.chain, &self.blockdb);
+            match data_availability {
+                BlockResult::Pass => drop(buffer),
+                BlockResult::MissingReferences(r) => {
+                    debug!(
+                        "Missing {} referred blocks for block {:.8}",
+                        r.len(),
+                        block.hash()
+                    );
+                    let evicted = buffer.insert(block, &r, peer.addr(), Instant::now());
+                    drop(buffer);
+                    self.forget_blocks(&evicted);
+                    PERFORMANCE_COUNTER.record_drop_buffered_blocks(evicted.len(), 0);
+                    to_request.extend_from_slice(&r);
+                    continue;
+                }
+                _ => unreachable!(),
+            }
+
+            // check sortition proof and content semantics. light clients have checked the
+            // sortition proof against the original content when the block arrived
+            if !self.config.light_client {
+                let sortition_proof = validation::check_sortition_proof(&block, &self.config);
+                match sortition_proof {
+                    BlockResult::Pass => {}
+                    _ => {
+                        warn!(
+                            "Ignoring invalid block {:.8}: {}",
+                            block.hash(),
+                            sortition_proof
+                        );
+                        peer.report_misbehavior();
+                        continue;
+                    }
+                }
+            }
+            // signatures of transaction blocks are verified by the verification threads
+            let content_semantic = validation::check_content_semantic_except_signatures(
+                &block,
+                &self.chain,
+                &self.blockdb,
+                &self.config,
+            );
+            match content_semantic {
+                BlockResult::Pass => {}
+                _ => {
+                    warn!(
+                        "Ignoring invalid block {:.8}: {}",
+                        block.hash(),
+                        content_semantic
+                    );
+                    peer.report_misbehavior();
+                    continue;
+                }
             }
+
+            let chunks: Vec<Vec<Transaction>> = match &block.content {
+                Content::Transaction(c) => c
+                    .transactions
+                    .chunks(self.signature_batch)
+                    .map(|chunk| chunk.to_vec())
+                    .collect(),
+                _ => vec![],
+            };
+            if chunks.is_empty() {
+                self.insertion_chan.0.send((block, peer.clone())).unwrap();
+                continue;
+            }
+            let pending = Arc::new(PendingBlock {
+                block: Mutex::new(Some((block, peer.clone()))),
+                remaining_chunks: AtomicUsize::new(chunks.len()),
+                failures: Mutex::new(vec![]),
+            });
+            for (idx, transactions) in chunks.into_iter().enumerate() {
+                self.verification_chan
+                    .0
+                    .send(VerificationJob {
+                        pending: Arc::clone(&pending),
+                        transactions,
+                        offset: idx * self.signature_batch,
+                    })
+                    .unwrap();
+            }
+        }
+
+        if !to_request.is_empty() {
+            to_request.sort();
+            to_request.dedup();
+            self.request_blocks(to_request, peer);
+        }
+    }
+
+    /// Verify the signatures of chunks of transaction blocks. The thread verifying the last chunk
+    /// of a block hands it to insertion, or drops it if any chunk fails.
+    fn verification_loop(&self) {
+        loop {
+            let job = self.verification_chan.1.recv().unwrap();
+            let pending = job.pending;
+            let result = validation::check_signatures(&job.transactions, job.offset);
+            if result != BlockResult::Pass {
+                pending.failures.lock().unwrap().push(result);
+            }
+            if pending.remaining_chunks.fetch_sub(1, Ordering::AcqRel) == 1 {
+                let (block, peer) = pending.block.lock().unwrap().take().unwrap();
+                let mut failures = pending.failures.lock().unwrap();
+                if failures.is_empty() {
+                    self.insertion_chan.0.send((block, peer)).unwrap();
+                } else {
+                    failures.sort_by_key(|r| match r {
+                        BlockResult::MalformedAuthorization(idx)
+                        | BlockResult::WrongOwner(idx)
+                        | BlockResult::WrongSignature(idx) => idx.first().cloned(),
+                        _ => None,
+                    });
+                    warn!(
+                        "Ignoring invalid block {:.8}: {}",
+                        block.hash(),
+                        failures[0]
+                    );
+                    peer.report_misbehavior();
+                }
+            }
+        }
+    }
+
+    /// Insert validated blocks into the blockchain, and process the buffered blocks waiting for
+    /// them.
+    fn insertion_loop(&self) {
+        loop {
+            let (block, peer) = self.insertion_chan.1.recv().unwrap();
+            debug!("Processing block {:.8}", block.hash());
+            new_validated_block(
+                &block,
+                &self.mempool,
+                &self.blockdb,
+                &self.chain,
+                &self.server,
+            );
+            // tell the miner to update the context
+            self.context_update_chan
+                .send(match &block.content {
+                    Content::Proposer(_) => ContextUpdateSignal::NewProposerBlock,
+                    Content::Voter(c) => ContextUpdateSignal::NewVoterBlock(c.chain_number),
+                    Content::Transaction(_) => ContextUpdateSignal::NewTransactionBlock,
+                })
+                .unwrap();
+            let mut buffer = self.buffer.lock().unwrap();
+            let resolved_by_current = buffer.satisfy(block.hash());
+            drop(buffer);
+            if !resolved_by_current.is_empty() {
+                debug!(
+                    "Resolved dependency for {} buffered blocks",
+                    resolved_by_current.len()
+                );
+            }
+            self.process_blocks(resolved_by_current, &peer);
+        }
+    }
+
+    /// Periodically drop the blocks that have waited for too long for the blocks they refer to, and
+    /// ask all peers again for the blocks that the remaining ones are waiting for.
+    fn buffer_loop(&self) {
+        loop {
+            thread::sleep(REREQUEST_INTERVAL);
+            let mut buffer = self.buffer.lock().unwrap();
+            let expired = buffer.expire(Instant::now());
+            let missing = buffer.missing();
+            drop(buffer);
+            if !expired.is_empty() {
+                debug!("Dropped {} expired buffered blocks", expired.len());
+                self.forget_blocks(&expired);
+                PERFORMANCE_COUNTER.record_drop_buffered_blocks(0, expired.len());
+            }
+
+            // blocks in the database are being processed already
+            let mut to_request = vec![];
+            for hash in missing {
+                if !self.blockdb.contains(&hash).unwrap() {
+                    to_request.push(hash);
+                }
+            }
+            if !to_request.is_empty() {
+                debug!("Requesting {} missing referred blocks", to_request.len());
+                if self.config.light_client {
+                    self.server.broadcast(Message::GetHeaders(to_request));
+                } else {
+                    self.server.broadcast(Message::GetBlocks(to_request));
+                }
+            }
+        }
+    }
+
+    /// Remove blocks dropped from the buffer from the database, so that they are processed again
+    /// if they arrive again.
+    fn forget_blocks(&self, hashes: &[H256]) {
+        for hash in hashes {
+            self.blockdb.remove(hash).unwrap();
+        }
+    }
+
+    /// Record the depth of the queues between the stages of block processing.
+    fn record_queues(&self) {
+        let buffered = self.buffer.lock().unwrap().len();
+        PERFORMANCE_COUNTER.record_block_queues(
+            buffered,
+            self.verification_chan.0.len(),
+            self.insertion_chan.0.len(),
+        );
+    }
+
+    /// Ask the peer for blocks, or only for their headers if we are a light client.
+    fn request_blocks(&self, hashes: Vec<H256>, peer: &peer::Handle) {
+        if self.config.light_client {
+            peer.write(Message::GetHeaders(hashes));
+        } else {
+            peer.write(Message::GetBlocks(hashes));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::mined_block;
+    use crate::block::{proposer, transaction as transaction_block};
+    use crate::blockchain::confirmation::ConfirmationRuleKind;
+    use crate::network::server;
+    use crate::transaction::tests::signed_transaction;
+    use std::net::{TcpListener, TcpStream};
+
+    struct Fixture {
+        ctx: Context,
+        peer: peer::Handle,
+        genesis: H256,
+        config: BlockchainConfig,
+        _context_updates: channel::Receiver<ContextUpdateSignal>,
+        _server: server::Context,
+        _peer: peer::Context,
+        _listener: TcpListener,
+    }
+
+    /// Build a worker context with its signature verification and insertion threads running, and
+    /// a peer to feed it blocks from.
+    fn fixture(name: &str) -> Fixture {
+        let mut config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.confirmation_rule = ConfirmationRuleKind::Optimistic;
+        config.tx_txs = 4;
+        let path = |db: &str| format!("/tmp/prism_test_worker_{}_{}.rocksdb", name, db);
+        let chain = Arc::new(BlockChain::new(path("blockchain"), config.clone()).unwrap());
+        let blockdb = Arc::new(BlockDatabase::new(path("blockdb"), config.clone()).unwrap());
+        let utxodb = Arc::new(UtxoDatabase::new(path("utxodb")).unwrap());
+        let wallet = Arc::new(Wallet::new(path("wallet")).unwrap());
+        let mempool = Arc::new(Mutex::new(MemoryPool::new(100)));
+        let (msg_sink, msg_src) = channel::unbounded();
+        let (server, server_handle) =
+            server::new("127.0.0.1:0".parse().unwrap(), msg_sink).unwrap();
+        let (context_update_sink, context_updates) = channel::unbounded();
+        let ctx = new(
+            2,
+            2,
+            2,
+            msg_src,
+            &chain,
+            &blockdb,
+            &utxodb,
+            &wallet,
+            &mempool,
+            context_update_sink,
+            &server_handle,
+            config.clone(),
+            None,
+            None,
+        );
+        for _ in 0..2 {
+            let cloned = ctx.clone();
+            thread::spawn(move || cloned.verification_loop());
+            let cloned = ctx.clone();
+            thread::spawn(move || cloned.insertion_loop());
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let stream = mio::net::TcpStream::from_stream(stream).unwrap();
+        let (peer_ctx, peer) = peer::new(stream, peer::Direction::Outgoing).unwrap();
+        Fixture {
+            ctx,
+            peer,
+            genesis: config.proposer_genesis,
+            config,
+            _context_updates: context_updates,
+            _server: server,
+            _peer: peer_ctx,
+            _listener: listener,
+        }
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn wrong_signature_not_inserted() {
+        let f = fixture("wrong_signature");
+        let transaction_block = |transactions| {
+            mined_block(
+                f.genesis,
+                Content::Transaction(transaction_block::Content { transactions }),
+                &f.config,
+            )
+        };
+        // the last transaction is changed after signing, in the second verification chunk
+        let mut transactions: Vec<Transaction> = (0..3).map(|i| signed_transaction(i, i)).collect();
+        transactions[2].output[0].value = 9;
+        let bad = transaction_block(transactions);
+        let good = transaction_block((3..6).map(|i| signed_transaction(i, i)).collect());
+        f.ctx
+            .process_blocks(vec![bad.clone(), good.clone()], &f.peer);
+
+        let chain = &f.ctx.chain;
+        wait_until(|| chain.contains_transaction(&good.hash()).unwrap());
+        wait_until(|| f.peer.stats().misbehavior.load(Ordering::Relaxed) == 1);
+        assert!(!chain.contains_transaction(&bad.hash()).unwrap());
+        assert!(f.ctx.verification_chan.0.is_empty());
+        assert!(f.ctx.insertion_chan.0.is_empty());
+    }
+
+    #[test]
+    fn insertion_follows_references() {
+        let f = fixture("references");
+        let propose = |parent| {
+            mined_block(
+                parent,
+                Content::Proposer(proposer::Content {
+                    transaction_refs: vec![],
+                    proposer_refs: vec![],
+                    utxo_commitment: None,
+                }),
+                &f.config,
+            )
+        };
+        let first = propose(f.genesis);
+        let second = propose(first.hash());
+        let third = propose(second.hash());
+
+        // the descendants arrive first and wait for their parents
+        f.ctx
+            .process_blocks(vec![third.clone(), second.clone()], &f.peer);
+        let chain = &f.ctx.chain;
+        assert_eq!(f.ctx.buffer.lock().unwrap().len(), 2);
+        assert!(!chain.contains_proposer(&second.hash()).unwrap());
+        assert!(!chain.contains_proposer(&third.hash()).unwrap());
+
+        f.ctx.process_blocks(vec![first.clone()], &f.peer);
+        wait_until(|| chain.contains_proposer(&third.hash()).unwrap());
+        assert_eq!(f.ctx.buffer.lock().unwrap().len(), 0);
+        for (level, block) in [first, second, third].iter().enumerate() {
+            assert_eq!(
+                chain.proposer_level(&block.hash()).unwrap(),
+                level as u64 + 1
+            );
         }
+        assert_eq!(f.peer.stats().misbehavior.load(Ordering::Relaxed), 0);
     }
 }
@@ -0,0 +1,258 @@
+//! Human-readable encoding of addresses, following the bech32 format of BIP 173: a network prefix,
+//! the separator `1`, the address in base32, and a 6-character checksum.
+
+use super::Address;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
+use std::{error, fmt, str};
+
+/// The human-readable prefix of addresses on this network.
+pub const ADDRESS_PREFIX: &str = "prism";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+const CHECKSUM_LENGTH: usize = 6;
+
+/// An address together with its human-readable encoding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Bech32Address(pub Address);
+
+#[derive(Debug, PartialEq)]
+pub enum AddressError {
+    MissingSeparator,
+    WrongPrefix(String),
+    InvalidCharacter(char),
+    MixedCase,
+    WrongChecksum,
+    WrongLength,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddressError::MissingSeparator => write!(f, "missing separator"),
+            AddressError::WrongPrefix(ref p) => {
+                write!(f, "wrong prefix {}, expecting {}", p, ADDRESS_PREFIX)
+            }
+            AddressError::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+            AddressError::MixedCase => write!(f, "mixed upper and lower case"),
+            AddressError::WrongChecksum => write!(f, "wrong checksum"),
+            AddressError::WrongLength => write!(f, "wrong length"),
+        }
+    }
+}
+
+impl error::Error for AddressError {}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for v in values {
+        let top = chk >> 25;
+        chk = (chk & 0x01ff_ffff) << 5 ^ u32::from(*v);
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = prefix.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(prefix.bytes().map(|c| c & 0x1f));
+    expanded
+}
+
+fn create_checksum(prefix: &str, data: &[u8]) -> Vec<u8> {
+    let mut values = expand_prefix(prefix);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; CHECKSUM_LENGTH]);
+    let polymod = polymod(&values) ^ 1;
+    (0..CHECKSUM_LENGTH)
+        .map(|i| ((polymod >> (5 * (5 - i))) & 0x1f) as u8)
+        .collect()
+}
+
+fn verify_checksum(prefix: &str, data: &[u8]) -> bool {
+    let mut values = expand_prefix(prefix);
+    values.extend_from_slice(data);
+    polymod(&values) == 1
+}
+
+/// Regroup a sequence of `from`-bit values into `to`-bit values. Without padding, leftover bits
+/// must be zero and shorter than `from`.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let mut result = vec![];
+    let max: u32 = (1 << to) - 1;
+    for value in data {
+        acc = (acc << from) | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+impl fmt::Display for Bech32Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = convert_bits(self.0.as_ref(), 8, 5, true).unwrap();
+        let checksum = create_checksum(ADDRESS_PREFIX, &data);
+        let encoded: String = data
+            .iter()
+            .chain(checksum.iter())
+            .map(|d| CHARSET[*d as usize] as char)
+            .collect();
+        write!(f, "{}1{}", ADDRESS_PREFIX, encoded)
+    }
+}
+
+impl str::FromStr for Bech32Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let has_lower = s.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = s.chars().any(|c| c.is_ascii_uppercase());
+        if has_lower && has_upper {
+            return Err(AddressError::MixedCase);
+        }
+        let s = s.to_ascii_lowercase();
+        let separator = match s.rfind('1') {
+            Some(pos) => pos,
+            None => return Err(AddressError::MissingSeparator),
+        };
+        let (prefix, encoded) = (&s[..separator], &s[separator + 1..]);
+        if prefix != ADDRESS_PREFIX {
+            return Err(AddressError::WrongPrefix(prefix.to_string()));
+        }
+        if encoded.len() < CHECKSUM_LENGTH {
+            return Err(AddressError::WrongLength);
+        }
+        let mut data = vec![];
+        for c in encoded.chars() {
+            match CHARSET.iter().position(|x| *x as char == c) {
+                Some(d) => data.push(d as u8),
+                None => return Err(AddressError::InvalidCharacter(c)),
+            }
+        }
+        if !verify_checksum(prefix, &data) {
+            return Err(AddressError::WrongChecksum);
+        }
+        let payload = &data[..data.len() - CHECKSUM_LENGTH];
+        let bytes = convert_bits(payload, 5, 8, false).ok_or(AddressError::WrongLength)?;
+        let bytes: [u8; 32] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| AddressError::WrongLength)?;
+        Ok(Bech32Address(bytes.into()))
+    }
+}
+
+impl From<Address> for Bech32Address {
+    fn from(addr: Address) -> Self {
+        Bech32Address(addr)
+    }
+}
+
+impl From<Bech32Address> for Address {
+    fn from(addr: Bech32Address) -> Self {
+        addr.0
+    }
+}
+
+impl Serialize for Bech32Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Bech32Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::tests::generate_random_hash;
+
+    fn to_values(s: &str) -> Vec<u8> {
+        s.bytes()
+            .map(|c| CHARSET.iter().position(|x| *x == c).unwrap() as u8)
+            .collect()
+    }
+
+    #[test]
+    fn checksum_test_vectors() {
+        // valid strings from BIP 173
+        assert!(verify_checksum("a", &to_values("2uel5l")));
+        let data = to_values("qpzry9x8gf2tvdw0s3jn54khce6mua7l");
+        assert!(verify_checksum(
+            "abcdef",
+            &[&data[..], &to_values("mqqqxw")].concat()
+        ));
+        assert_eq!(create_checksum("abcdef", &data), to_values("mqqqxw"));
+        assert!(!verify_checksum(
+            "abcdef",
+            &[&data[..], &to_values("mqqqxq")].concat()
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let addr: Address = generate_random_hash();
+        let encoded = Bech32Address(addr).to_string();
+        assert!(encoded.starts_with("prism1"));
+        let decoded: Bech32Address = encoded.parse().unwrap();
+        assert_eq!(decoded.0, addr);
+        let decoded: Bech32Address = encoded.to_ascii_uppercase().parse().unwrap();
+        assert_eq!(decoded.0, addr);
+    }
+
+    #[test]
+    fn detect_errors() {
+        let encoded = Bech32Address(generate_random_hash()).to_string();
+        let mut corrupted: Vec<char> = encoded.chars().collect();
+        let pos = corrupted.len() - 10;
+        corrupted[pos] = if corrupted[pos] == 'q' { 'p' } else { 'q' };
+        let corrupted: String = corrupted.into_iter().collect();
+        assert_eq!(
+            corrupted.parse::<Bech32Address>(),
+            Err(AddressError::WrongChecksum)
+        );
+        assert_eq!(
+            encoded
+                .replacen("prism", "prizm", 1)
+                .parse::<Bech32Address>(),
+            Err(AddressError::WrongPrefix("prizm".to_string()))
+        );
+        assert_eq!(
+            encoded[..encoded.len() - 1].parse::<Bech32Address>(),
+            Err(AddressError::WrongChecksum)
+        );
+        assert_eq!(
+            "prism1qqqqqq".parse::<Bech32Address>(),
+            Err(AddressError::WrongChecksum)
+        );
+    }
+}
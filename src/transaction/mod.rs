@@ -1,3 +1,5 @@
+pub mod address;
+
 use crate::crypto::hash::{Hashable, H256};
 use crate::experiment::performance_counter::PayloadSize;
 use bincode::serialize;
@@ -90,4 +92,52 @@ pub struct Authorization {
 }
 
 #[cfg(any(test))]
-pub mod tests {}
+pub mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+    /// An input spending the first output of the transaction whose hash is made of `coin` bytes.
+    pub fn input(coin: u8, value: u64) -> Input {
+        Input {
+            coin: CoinId {
+                hash: [coin; 32].into(),
+                index: 0,
+            },
+            value,
+            owner: H256::default(),
+        }
+    }
+
+    /// An unsigned transaction paying the given values to the zero address.
+    pub fn transaction(input: Vec<Input>, output_values: &[u64]) -> Transaction {
+        Transaction {
+            input,
+            output: output_values
+                .iter()
+                .map(|value| Output {
+                    value: *value,
+                    recipient: H256::default(),
+                })
+                .collect(),
+            authorization: vec![],
+            hash: RefCell::new(None),
+        }
+    }
+    /// Build a transaction spending a coin of the owner of the given key, and sign it.
+    pub fn signed_transaction(coin: u8, key: u8) -> Transaction {
+        let secret = SecretKey::from_bytes(&[key; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        let keypair = Keypair { secret, public };
+        let owner = ring::digest::digest(&ring::digest::SHA256, public.as_bytes()).into();
+        let mut t = transaction(vec![input(coin, 10)], &[10]);
+        t.input[0].owner = owner;
+        let raw_inputs = bincode::serialize(&t.input).unwrap();
+        let raw_outputs = bincode::serialize(&t.output).unwrap();
+        let raw = [&raw_inputs[..], &raw_outputs[..]].concat();
+        t.authorization.push(Authorization {
+            pubkey: public.to_bytes().to_vec(),
+            signature: keypair.sign(&raw).to_bytes().to_vec(),
+        });
+        t
+    }
+}
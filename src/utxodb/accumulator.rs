@@ -0,0 +1,314 @@
+use crate::crypto::hash::H256;
+use crate::transaction::{CoinId, Output};
+use bincode::serialize;
+use std::sync::Arc;
+
+/// A node of the sparse Merkle tree. A subtree holding a single coin is collapsed into its leaf,
+/// so that the shape of the tree, and hence its root, only depends on the set of coins and not on
+/// the order in which they were added. Nodes are shared between versions of the tree, so cloning
+/// a tree to keep a snapshot of it is cheap.
+enum Node {
+    Empty,
+    Leaf {
+        key: H256,
+        hash: H256,
+        coin: CoinId,
+        output: Output,
+    },
+    Internal {
+        hash: H256,
+        left: Arc<Node>,
+        right: Arc<Node>,
+    },
+}
+
+impl Node {
+    fn hash(&self) -> H256 {
+        match self {
+            Node::Empty => H256::default(),
+            Node::Leaf { hash, .. } => *hash,
+            Node::Internal { hash, .. } => *hash,
+        }
+    }
+
+    /// Join two subtrees, collapsing the result if it holds at most one coin.
+    fn join(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+        match (&*left, &*right) {
+            (Node::Empty, Node::Empty) => left,
+            (Node::Leaf { .. }, Node::Empty) => left,
+            (Node::Empty, Node::Leaf { .. }) => right,
+            _ => Arc::new(Node::Internal {
+                hash: internal_hash(&left.hash(), &right.hash()),
+                left,
+                right,
+            }),
+        }
+    }
+
+    fn insert(node: &Arc<Node>, leaf: &Arc<Node>, key: &H256, depth: usize) -> Arc<Node> {
+        match &**node {
+            Node::Empty => Arc::clone(leaf),
+            Node::Leaf { key: k, .. } if k == key => Arc::clone(leaf),
+            Node::Leaf { key: k, .. } => {
+                // push the existing leaf one level down, and insert next to it
+                let empty = Arc::new(Node::Empty);
+                let (left, right) = if bit(k, depth) {
+                    (empty, Arc::clone(node))
+                } else {
+                    (Arc::clone(node), empty)
+                };
+                if bit(key, depth) {
+                    Node::join(left, Node::insert(&right, leaf, key, depth + 1))
+                } else {
+                    Node::join(Node::insert(&left, leaf, key, depth + 1), right)
+                }
+            }
+            Node::Internal { left, right, .. } => {
+                if bit(key, depth) {
+                    Node::join(Arc::clone(left), Node::insert(right, leaf, key, depth + 1))
+                } else {
+                    Node::join(Node::insert(left, leaf, key, depth + 1), Arc::clone(right))
+                }
+            }
+        }
+    }
+
+    fn remove(node: &Arc<Node>, key: &H256, depth: usize) -> Arc<Node> {
+        match &**node {
+            Node::Leaf { key: k, .. } if k == key => Arc::new(Node::Empty),
+            Node::Internal { left, right, .. } => {
+                if bit(key, depth) {
+                    Node::join(Arc::clone(left), Node::remove(right, key, depth + 1))
+                } else {
+                    Node::join(Node::remove(left, key, depth + 1), Arc::clone(right))
+                }
+            }
+            _ => Arc::clone(node),
+        }
+    }
+
+    /// Collect the coins under this node whose keys start with the given prefix.
+    fn collect(&self, prefix: u32, bits: usize, depth: usize, coins: &mut Vec<(CoinId, Output)>) {
+        match self {
+            Node::Empty => {}
+            Node::Leaf {
+                key, coin, output, ..
+            } => {
+                if has_prefix(key, prefix, bits) {
+                    coins.push((*coin, *output));
+                }
+            }
+            Node::Internal { left, right, .. } => {
+                if depth >= bits {
+                    left.collect(prefix, bits, depth + 1, coins);
+                    right.collect(prefix, bits, depth + 1, coins);
+                } else if prefix & (1 << (bits - 1 - depth)) != 0 {
+                    right.collect(prefix, bits, depth + 1, coins);
+                } else {
+                    left.collect(prefix, bits, depth + 1, coins);
+                }
+            }
+        }
+    }
+}
+
+/// An authenticated accumulator over the UTXO set, in the form of a sparse Merkle tree keyed by
+/// the hash of the coin id.
+#[derive(Clone)]
+pub struct UtxoAccumulator {
+    root: Arc<Node>,
+}
+
+impl Default for UtxoAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UtxoAccumulator {
+    /// Create an accumulator over an empty set.
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(Node::Empty),
+        }
+    }
+
+    /// Get the root of the tree, which commits to the whole UTXO set.
+    pub fn root(&self) -> H256 {
+        self.root.hash()
+    }
+
+    /// Add a coin to the set, or update its output if it is already there.
+    pub fn insert(&mut self, coin: &CoinId, output: &Output) {
+        let key = coin_key(coin);
+        let leaf = Arc::new(Node::Leaf {
+            key,
+            hash: leaf_hash(&key, output),
+            coin: *coin,
+            output: *output,
+        });
+        self.root = Node::insert(&self.root, &leaf, &key, 0);
+    }
+
+    /// Remove a coin from the set.
+    pub fn remove(&mut self, coin: &CoinId) {
+        let key = coin_key(coin);
+        self.root = Node::remove(&self.root, &key, 0);
+    }
+
+    /// Prove that a coin is in the set. The proof is the list of the siblings on the path from
+    /// the root to the coin, and is `None` if the coin is not in the set.
+    pub fn prove(&self, coin: &CoinId) -> Option<Vec<H256>> {
+        let key = coin_key(coin);
+        let mut proof: Vec<H256> = vec![];
+        let mut node = &self.root;
+        loop {
+            match &**node {
+                Node::Empty => return None,
+                Node::Leaf { key: k, .. } => {
+                    if *k == key {
+                        return Some(proof);
+                    } else {
+                        return None;
+                    }
+                }
+                Node::Internal { left, right, .. } => {
+                    if bit(&key, proof.len()) {
+                        proof.push(left.hash());
+                        node = right;
+                    } else {
+                        proof.push(right.hash());
+                        node = left;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the coins in the given chunk, when the set is split into `2^bits` chunks by the first
+    /// bits of the key of each coin.
+    pub fn chunk(&self, index: u32, bits: usize) -> Vec<(CoinId, Output)> {
+        let mut coins = vec![];
+        self.root.collect(index, bits, 0, &mut coins);
+        coins
+    }
+}
+
+/// Check whether a coin belongs to the given chunk, when the set is split into `2^bits` chunks.
+pub fn in_chunk(coin: &CoinId, index: u32, bits: usize) -> bool {
+    has_prefix(&coin_key(coin), index, bits)
+}
+
+/// Verify that a coin with the given output is in the UTXO set committed by the given root.
+pub fn verify(root: &H256, coin: &CoinId, output: &Output, proof: &[H256]) -> bool {
+    let key = coin_key(coin);
+    let mut hash = leaf_hash(&key, output);
+    for (depth, sibling) in proof.iter().enumerate().rev() {
+        hash = if bit(&key, depth) {
+            internal_hash(sibling, &hash)
+        } else {
+            internal_hash(&hash, sibling)
+        };
+    }
+    hash == *root
+}
+
+fn coin_key(coin: &CoinId) -> H256 {
+    ring::digest::digest(&ring::digest::SHA256, &serialize(coin).unwrap()).into()
+}
+
+fn leaf_hash(key: &H256, output: &Output) -> H256 {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(&[0]);
+    ctx.update(key.as_ref());
+    ctx.update(&serialize(output).unwrap());
+    ctx.finish().into()
+}
+
+fn internal_hash(left: &H256, right: &H256) -> H256 {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(&[1]);
+    ctx.update(left.as_ref());
+    ctx.update(right.as_ref());
+    ctx.finish().into()
+}
+
+fn has_prefix(key: &H256, prefix: u32, bits: usize) -> bool {
+    (0..bits).all(|depth| bit(key, depth) == (prefix & (1 << (bits - 1 - depth)) != 0))
+}
+
+/// Get the bit of the key that decides the branch taken at the given depth.
+fn bit(key: &H256, depth: usize) -> bool {
+    key.as_ref()[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coins(n: u32) -> Vec<(CoinId, Output)> {
+        (0..n)
+            .map(|i| {
+                let coin = CoinId {
+                    hash: H256::default(),
+                    index: i,
+                };
+                let output = Output {
+                    value: u64::from(i) + 1,
+                    recipient: H256::default(),
+                };
+                (coin, output)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn root_and_proofs() {
+        let coins = coins(100);
+        let mut forward = UtxoAccumulator::new();
+        let mut backward = UtxoAccumulator::new();
+        for (coin, output) in &coins {
+            forward.insert(coin, output);
+        }
+        for (coin, output) in coins.iter().rev() {
+            backward.insert(coin, output);
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        let root = forward.root();
+        for (coin, output) in &coins {
+            let proof = forward.prove(coin).unwrap();
+            assert!(verify(&root, coin, output, &proof));
+            let wrong = Output {
+                value: output.value + 1,
+                recipient: output.recipient,
+            };
+            assert!(!verify(&root, coin, &wrong, &proof));
+        }
+
+        // removing coins gives the same root as never adding them
+        let mut partial = UtxoAccumulator::new();
+        for (coin, output) in &coins[..50] {
+            partial.insert(coin, output);
+        }
+        for (coin, _) in &coins[50..] {
+            forward.remove(coin);
+            assert!(forward.prove(coin).is_none());
+        }
+        assert_eq!(forward.root(), partial.root());
+
+        // the chunks partition the set
+        let mut chunked: Vec<(CoinId, Output)> = vec![];
+        for index in 0..16 {
+            let chunk = forward.chunk(index, 4);
+            assert!(chunk.iter().all(|(coin, _)| in_chunk(coin, index, 4)));
+            chunked.extend(chunk);
+        }
+        chunked.sort_by_key(|(coin, _)| coin.index);
+        assert_eq!(chunked, coins[..50].to_vec());
+        for (coin, _) in &coins[..50] {
+            forward.remove(coin);
+        }
+        assert_eq!(forward.root(), H256::default());
+    }
+}
@@ -1,12 +1,31 @@
+pub mod accumulator;
+
+use self::accumulator::UtxoAccumulator;
+use crate::config::UTXO_COMMITMENT_DELAY;
 use crate::crypto::hash::H256;
 use crate::experiment::performance_counter::PERFORMANCE_COUNTER;
 use crate::transaction::{Address, CoinId, Output, Transaction};
 use bincode::{deserialize, serialize};
 use rocksdb::*;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Mutex;
+
+/// Interval in proposer levels between two snapshots of the UTXO set kept for fast sync.
+pub const SNAPSHOT_INTERVAL: u64 = 50;
+/// Number of the most recent snapshots kept for fast sync.
+const SNAPSHOTS_KEPT: usize = 4;
+/// A snapshot is served in `2^SNAPSHOT_CHUNK_BITS` chunks.
+pub const SNAPSHOT_CHUNK_BITS: usize = 8;
 
 pub struct UtxoDatabase {
     pub db: rocksdb::DB, // coin id to output
+    /// Accumulator over the current UTXO set.
+    accumulator: Mutex<UtxoAccumulator>,
+    /// Root of the accumulator after applying the ledger up to each proposer level.
+    commitments: Mutex<BTreeMap<u64, H256>>,
+    /// Snapshots of the accumulator at recent proposer levels that are multiples of
+    /// `SNAPSHOT_INTERVAL`, served to peers doing fast sync.
+    snapshots: Mutex<BTreeMap<u64, UtxoAccumulator>>,
 }
 
 impl UtxoDatabase {
@@ -31,7 +50,12 @@ impl UtxoDatabase {
         opts.set_max_write_buffer_number(32);
 
         let db = DB::open_cf_descriptors(&opts, path, cfs)?;
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            accumulator: Mutex::new(UtxoAccumulator::new()),
+            commitments: Mutex::new(BTreeMap::new()),
+            snapshots: Mutex::new(BTreeMap::new()),
+        })
     }
 
     /// Create a new database at the given path, and initialize the content.
@@ -52,28 +76,122 @@ impl UtxoDatabase {
     }
 
     pub fn snapshot(&self) -> Result<Vec<u8>, rocksdb::Error> {
+        Ok(self.commitment().as_ref().to_vec())
+    }
+
+    /// Get the root of the accumulator over the current UTXO set.
+    pub fn commitment(&self) -> H256 {
+        self.accumulator.lock().unwrap().root()
+    }
+
+    /// Prove that the given coin is in the current UTXO set.
+    pub fn prove(&self, coin: &CoinId) -> Option<Vec<H256>> {
+        self.accumulator.lock().unwrap().prove(coin)
+    }
+
+    /// Record the current root of the accumulator as the commitment of the given proposer level.
+    /// This must be called when the ledger up to that level, and nothing more, is applied.
+    pub fn record_commitment(&self, level: u64) {
+        let accumulator = self.accumulator.lock().unwrap();
+        let root = accumulator.root();
+        let snapshot = if level % SNAPSHOT_INTERVAL == 0 {
+            Some(accumulator.clone())
+        } else {
+            None
+        };
+        drop(accumulator);
+
+        // keep the commitments that miners may still refer to, and those of the kept snapshots
+        let mut commitments = self.commitments.lock().unwrap();
+        commitments.insert(level, root);
+        let oldest_kept = level
+            .saturating_sub(UTXO_COMMITMENT_DELAY)
+            .saturating_sub(SNAPSHOT_INTERVAL * SNAPSHOTS_KEPT as u64);
+        *commitments = commitments.split_off(&oldest_kept);
+        drop(commitments);
+
+        if let Some(snapshot) = snapshot {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            snapshots.insert(level, snapshot);
+            while snapshots.len() > SNAPSHOTS_KEPT {
+                let oldest = *snapshots.keys().next().unwrap();
+                snapshots.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forget the commitments of the given proposer level and above, after the ledger changes
+    /// from that level.
+    pub fn forget_commitments(&self, level: u64) {
+        let mut commitments = self.commitments.lock().unwrap();
+        commitments.split_off(&level);
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.split_off(&level);
+    }
+
+    /// Get the commitment of the given proposer level, if the ledger has been applied up to it.
+    pub fn commitment_at(&self, level: u64) -> Option<H256> {
+        self.commitments.lock().unwrap().get(&level).cloned()
+    }
+
+    /// Get the root of the snapshot of the UTXO set at the given proposer level and a chunk of
+    /// it, if we keep one.
+    pub fn snapshot_chunk(&self, level: u64, index: u32) -> Option<(H256, Vec<(CoinId, Output)>)> {
+        let snapshots = self.snapshots.lock().unwrap();
+        snapshots
+            .get(&level)
+            .map(|snapshot| (snapshot.root(), snapshot.chunk(index, SNAPSHOT_CHUNK_BITS)))
+    }
+
+    /// Replace the UTXO set with a snapshot taken at the given proposer level, which must have
+    /// been verified against a trusted commitment. Return the coins added and removed.
+    pub fn import_snapshot(
+        &self,
+        level: u64,
+        snapshot: UtxoAccumulator,
+    ) -> Result<(Vec<(CoinId, Output)>, Vec<CoinId>), rocksdb::Error> {
+        let mut accumulator = self.accumulator.lock().unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut removed_coins: Vec<CoinId> = vec![];
         let mut iter_opt = rocksdb::ReadOptions::default();
         iter_opt.set_prefix_same_as_start(false);
         iter_opt.set_total_order_seek(true);
         let iter = self
             .db
             .iterator_opt(rocksdb::IteratorMode::Start, &iter_opt);
-        let mut inited = false;
-        let mut checksum: Vec<u8> = vec![];
         for (k, _) in iter {
-            if !inited {
-                checksum = vec![0; k.as_ref().len()];
-                inited = true;
-            }
-            checksum = checksum
-                .iter()
-                .zip(k.as_ref())
-                .map(|(&c, &k)| c ^ k)
-                .collect();
+            removed_coins.push(deserialize(&k).unwrap());
+            batch.delete(&k)?;
         }
-        Ok(checksum)
+        let added_coins = snapshot.chunk(0, 0);
+        for (coin, output) in &added_coins {
+            batch.put(serialize(coin).unwrap(), serialize(output).unwrap())?;
+        }
+        self.db.write(batch)?;
+
+        let mut commitments = self.commitments.lock().unwrap();
+        commitments.clear();
+        commitments.insert(level, snapshot.root());
+        self.snapshots.lock().unwrap().clear();
+        *accumulator = snapshot;
+        Ok((added_coins, removed_coins))
     }
 
+    /// Add a coin to the UTXO set out of thin air, e.g. in the ICO.
+    pub fn add_coin(&self, coin: &CoinId, output: &Output) -> Result<(), rocksdb::Error> {
+        let mut write_opt = WriteOptions::default();
+        write_opt.disable_wal(true);
+        self.db.put_opt(
+            serialize(&coin).unwrap(),
+            serialize(&output).unwrap(),
+            &write_opt,
+        )?;
+        self.accumulator.lock().unwrap().insert(coin, output);
+        Ok(())
+    }
+
+    /// Apply a transaction to the UTXO set and return the coins it adds and removes. The
+    /// accumulator is left to `update_accumulator`.
     pub fn add_transaction(
         &self,
         t: &Transaction,
@@ -135,6 +253,8 @@ impl UtxoDatabase {
         Ok((added_coins, removed_coins))
     }
 
+    /// Revert a transaction from the UTXO set and return the coins it adds and removes. The
+    /// accumulator is left to `update_accumulator`.
     pub fn remove_transaction(
         &self,
         t: &Transaction,
@@ -185,6 +305,20 @@ impl UtxoDatabase {
         Ok((added_coins, removed_coins))
     }
 
+    /// Apply the coins added and removed by a batch of transactions, in the order the
+    /// transactions were applied to the UTXO set, to the accumulator.
+    pub fn update_accumulator(&self, diffs: &[(Vec<(CoinId, Output)>, Vec<CoinId>)]) {
+        let mut accumulator = self.accumulator.lock().unwrap();
+        for (added, removed) in diffs {
+            for coin in removed {
+                accumulator.remove(coin);
+            }
+            for (coin, output) in added {
+                accumulator.insert(coin, output);
+            }
+        }
+    }
+
     pub fn flush(&self) -> Result<(), rocksdb::Error> {
         let mut flush_opt = rocksdb::FlushOptions::default();
         flush_opt.set_wait(true);
@@ -193,4 +327,65 @@ impl UtxoDatabase {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    fn coin(i: u32) -> (CoinId, Output) {
+        let hash: H256 = ring::digest::digest(&ring::digest::SHA256, &i.to_be_bytes()).into();
+        let coin = CoinId { hash, index: i };
+        let output = Output {
+            value: u64::from(i) + 1,
+            recipient: H256::default(),
+        };
+        (coin, output)
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let source = UtxoDatabase::new("/tmp/prism_test_utxodb_snapshot_source.rocksdb").unwrap();
+        let coins: Vec<(CoinId, Output)> = (0..1000).map(coin).collect();
+        for (coin, output) in &coins {
+            source.add_coin(coin, output).unwrap();
+        }
+        source.record_commitment(SNAPSHOT_INTERVAL);
+        let root = source.commitment_at(SNAPSHOT_INTERVAL).unwrap();
+
+        // export all chunks, and rebuild the accumulator from them
+        let mut snapshot = UtxoAccumulator::new();
+        for index in 0..1 << SNAPSHOT_CHUNK_BITS {
+            let (chunk_root, coins) = source.snapshot_chunk(SNAPSHOT_INTERVAL, index).unwrap();
+            assert_eq!(chunk_root, root);
+            for (coin, output) in coins {
+                snapshot.insert(&coin, &output);
+            }
+        }
+        assert_eq!(snapshot.root(), root);
+
+        // import them into a database holding a stale coin
+        let target = UtxoDatabase::new("/tmp/prism_test_utxodb_snapshot_target.rocksdb").unwrap();
+        let (stale, output) = coin(1000);
+        target.add_coin(&stale, &output).unwrap();
+        let (added, removed) = target.import_snapshot(SNAPSHOT_INTERVAL, snapshot).unwrap();
+        assert_eq!(removed, vec![stale]);
+        assert_eq!(added.len(), coins.len());
+        assert_eq!(target.commitment(), root);
+        assert_eq!(target.commitment_at(SNAPSHOT_INTERVAL), Some(root));
+        assert!(!target.contains(&stale).unwrap());
+        for (coin, _) in &coins {
+            assert!(target.contains(coin).unwrap());
+        }
+    }
+
+    #[test]
+    fn commitments_trimmed() {
+        let db = UtxoDatabase::new("/tmp/prism_test_utxodb_commitments_trimmed.rocksdb").unwrap();
+        let kept = UTXO_COMMITMENT_DELAY + SNAPSHOT_INTERVAL * SNAPSHOTS_KEPT as u64;
+        for level in 1..=2 * kept {
+            db.record_commitment(level);
+        }
+        assert!(db.commitment_at(kept - 1).is_none());
+        assert!(db.commitment_at(kept).is_some());
+        assert!(db.commitment_at(2 * kept).is_some());
+        assert_eq!(db.snapshots.lock().unwrap().len(), SNAPSHOTS_KEPT);
+    }
+}
@@ -1,16 +1,18 @@
 mod proposer_block;
 mod transaction;
 mod voter_block;
+use crate::block::light::{LightBlock, LightContent};
 use crate::block::{Block, Content};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::config::*;
 use crate::crypto::hash::{Hashable, H256};
 use crate::crypto::merkle::verify;
+use crate::transaction::Transaction;
 extern crate bigint;
 
 /// The result of block validation.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum BlockResult {
     /// The validation passes.
     Pass,
@@ -28,10 +30,27 @@ pub enum BlockResult {
     WrongChainNumber,
     /// A voter block votes for incorrect proposer levels.
     WrongVoteLevel,
+    /// A proposer block is at a checkpointed level but is not the checkpoint.
+    ConflictingCheckpoint,
+    /// A proposer block refers to more transaction blocks than allowed.
+    TooManyTransactionRefs,
+    /// A transaction block has more transactions than allowed.
+    TooManyTransactions,
+    /// A transaction block includes the same transaction twice.
+    DuplicateTransaction,
     EmptyTransaction,
     ZeroValue,
+    /// A transaction spends the same coin twice.
+    DuplicateInput,
+    /// The input or output values of a transaction add up to more than `u64::MAX`.
+    ValueOverflow,
     InsufficientInput,
-    WrongSignature,
+    /// The public keys or signatures of the transactions at the given indices are malformed.
+    MalformedAuthorization(Vec<usize>),
+    /// The signers of the transactions at the given indices are not the owners of the inputs.
+    WrongOwner(Vec<usize>),
+    /// The signatures of the transactions at the given indices are incorrect.
+    WrongSignature(Vec<usize>),
 }
 
 impl std::fmt::Display for BlockResult {
@@ -47,12 +66,32 @@ impl std::fmt::Display for BlockResult {
             }
             BlockResult::WrongChainNumber => write!(f, "chain number out of range"),
             BlockResult::WrongVoteLevel => write!(f, "incorrent vote levels"),
+            BlockResult::ConflictingCheckpoint => write!(f, "conflicting with a checkpoint"),
+            BlockResult::TooManyTransactionRefs => {
+                write!(f, "too many transaction block references")
+            }
+            BlockResult::TooManyTransactions => write!(f, "too many transactions"),
+            BlockResult::DuplicateTransaction => write!(f, "duplicate transaction"),
             BlockResult::EmptyTransaction => write!(f, "empty transaction input or output"),
             BlockResult::ZeroValue => {
                 write!(f, "transaction input or output value contains a zero")
             }
+            BlockResult::DuplicateInput => write!(f, "transaction spends a coin twice"),
+            BlockResult::ValueOverflow => write!(f, "transaction value overflow"),
             BlockResult::InsufficientInput => write!(f, "insufficient input"),
-            BlockResult::WrongSignature => write!(f, "signature mismatch"),
+            BlockResult::MalformedAuthorization(idx) => {
+                write!(f, "malformed key or signature in transactions {:?}", idx)
+            }
+            BlockResult::WrongOwner(idx) => {
+                write!(
+                    f,
+                    "signers are not the input owners in transactions {:?}",
+                    idx
+                )
+            }
+            BlockResult::WrongSignature(idx) => {
+                write!(f, "signature mismatch in transactions {:?}", idx)
+            }
         }
     }
 }
@@ -93,6 +132,32 @@ pub fn check_sortition_proof(block: &Block, config: &BlockchainConfig) -> BlockR
     }
     BlockResult::Pass
 }
+/// Check PoW, sortition id and sortition proof of a light block, against its original content.
+pub fn check_light_block(block: &LightBlock, config: &BlockchainConfig) -> BlockResult {
+    let sortition_id = match config.sortition_hash(&block.hash(), &block.header.difficulty) {
+        Some(sortition_id) => sortition_id,
+        None => return BlockResult::WrongPoW,
+    };
+    let correct_sortition_id = match &block.content {
+        LightContent::Proposer { .. } => PROPOSER_INDEX,
+        LightContent::Transaction(_) => TRANSACTION_INDEX,
+        LightContent::Voter(content) => content.chain_number + FIRST_VOTER_INDEX,
+    };
+    if sortition_id != correct_sortition_id {
+        return BlockResult::WrongSortitionId;
+    }
+    if !verify(
+        &block.header.content_merkle_root,
+        &block.content_hash(),
+        &block.sortition_proof,
+        sortition_id as usize,
+        (config.voter_chains + FIRST_VOTER_INDEX) as usize,
+    ) {
+        return BlockResult::WrongSortitionProof;
+    }
+    BlockResult::Pass
+}
+
 /// Validate a block that already passes pow and sortition test. See if parents/refs are missing.
 pub fn check_data_availability(
     block: &Block,
@@ -138,12 +203,76 @@ pub fn check_data_availability(
     }
 }
 
+/// Check block content against the size and structural limits, which do not depend on the
+/// blockchain state.
+pub fn check_content_limits(block: &Block, config: &BlockchainConfig) -> BlockResult {
+    match &block.content {
+        Content::Proposer(content) => {
+            if !proposer_block::check_transaction_ref_count(&content, config) {
+                return BlockResult::TooManyTransactionRefs;
+            }
+            BlockResult::Pass
+        }
+        Content::Voter(_) => BlockResult::Pass,
+        Content::Transaction(content) => {
+            if !transaction::check_transaction_count(&content, config) {
+                return BlockResult::TooManyTransactions;
+            }
+            if !transaction::check_distinct_transactions(&content) {
+                return BlockResult::DuplicateTransaction;
+            }
+            // check each transaction
+            for transaction in content.transactions.iter() {
+                if !transaction::check_non_empty(&transaction) {
+                    return BlockResult::EmptyTransaction;
+                }
+                if !transaction::check_non_zero(&transaction) {
+                    return BlockResult::ZeroValue;
+                }
+                if !transaction::check_distinct_inputs(&transaction) {
+                    return BlockResult::DuplicateInput;
+                }
+                if !transaction::check_no_overflow(&transaction) {
+                    return BlockResult::ValueOverflow;
+                }
+                if !transaction::check_sufficient_input(&transaction) {
+                    return BlockResult::InsufficientInput;
+                }
+            }
+            BlockResult::Pass
+        }
+    }
+}
+
 /// Check block content semantic
 pub fn check_content_semantic(
+    block: &Block,
+    blockchain: &BlockChain,
+    blockdb: &BlockDatabase,
+    config: &BlockchainConfig,
+) -> BlockResult {
+    match check_content_semantic_except_signatures(block, blockchain, blockdb, config) {
+        BlockResult::Pass => {}
+        result => return result,
+    }
+    match &block.content {
+        Content::Transaction(content) => check_signatures(&content.transactions, 0),
+        _ => BlockResult::Pass,
+    }
+}
+
+/// Check block content semantic, except the signatures of transaction blocks, which are left to
+/// `check_signatures` so that they can be verified in chunks in parallel.
+pub fn check_content_semantic_except_signatures(
     block: &Block,
     blockchain: &BlockChain,
     _blockdb: &BlockDatabase,
+    config: &BlockchainConfig,
 ) -> BlockResult {
+    match check_content_limits(block, config) {
+        BlockResult::Pass => {}
+        result => return result,
+    }
     let parent = block.header.parent;
     match &block.content {
         Content::Proposer(content) => {
@@ -151,6 +280,10 @@ pub fn check_content_semantic(
             if !proposer_block::check_ref_proposer_level(&parent, &content, blockchain) {
                 return BlockResult::WrongProposerRef;
             }
+            // check that the block does not replace a checkpoint
+            if !proposer_block::check_checkpoint(&block.hash(), &parent, blockchain) {
+                return BlockResult::ConflictingCheckpoint;
+            }
             BlockResult::Pass
         }
         Content::Voter(content) => {
@@ -164,24 +297,19 @@ pub fn check_content_semantic(
             }
             BlockResult::Pass
         }
-        Content::Transaction(content) => {
-            // check each transaction
-            for transaction in content.transactions.iter() {
-                if !transaction::check_non_empty(&transaction) {
-                    return BlockResult::EmptyTransaction;
-                }
-                if !transaction::check_non_zero(&transaction) {
-                    return BlockResult::ZeroValue;
-                }
-                if !transaction::check_sufficient_input(&transaction) {
-                    return BlockResult::InsufficientInput;
-                }
-            }
-            if !transaction::check_signature_batch(&content.transactions) {
-                return BlockResult::WrongSignature;
-            }
-            BlockResult::Pass
-        }
+        Content::Transaction(_) => BlockResult::Pass,
+    }
+}
+
+/// Check the authorizations of a chunk of transactions of a transaction block, starting at the
+/// given position in the block. The indices of offending transactions are positions in the block.
+pub fn check_signatures(transactions: &[Transaction], offset: usize) -> BlockResult {
+    let shift = |idx: Vec<usize>| -> Vec<usize> { idx.into_iter().map(|i| i + offset).collect() };
+    match transaction::check_authorizations(transactions) {
+        BlockResult::MalformedAuthorization(idx) => BlockResult::MalformedAuthorization(shift(idx)),
+        BlockResult::WrongOwner(idx) => BlockResult::WrongOwner(shift(idx)),
+        BlockResult::WrongSignature(idx) => BlockResult::WrongSignature(shift(idx)),
+        result => result,
     }
 }
 
@@ -208,3 +336,219 @@ fn check_transaction_block_exists(hash: H256, blockchain: &BlockChain) -> bool {
         Ok(b) => b,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::mined_block;
+    use crate::block::{proposer, transaction as transaction_block, voter};
+    use crate::crypto::hash::tests::generate_random_hash;
+    use crate::transaction::tests::{input, signed_transaction, transaction};
+
+    fn config() -> BlockchainConfig {
+        let mut config = BlockchainConfig::new(10, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        config.tx_txs = 2;
+        config.proposer_tx_refs = 1;
+        config
+    }
+
+    fn block(content: Content) -> Block {
+        Block::new(
+            H256::default(),
+            0,
+            0,
+            H256::default(),
+            vec![],
+            content,
+            [0; 32],
+            H256::default(),
+        )
+    }
+
+    fn transaction_block(transactions: Vec<Transaction>) -> Block {
+        block(Content::Transaction(transaction_block::Content {
+            transactions,
+        }))
+    }
+
+    #[test]
+    fn transaction_ref_limit() {
+        let refs = |n: u8| {
+            block(Content::Proposer(proposer::Content {
+                transaction_refs: (0..n).map(|i| [i; 32].into()).collect(),
+                ..Default::default()
+            }))
+        };
+        assert_eq!(check_content_limits(&refs(1), &config()), BlockResult::Pass);
+        assert_eq!(
+            check_content_limits(&refs(2), &config()),
+            BlockResult::TooManyTransactionRefs
+        );
+    }
+
+    #[test]
+    fn transaction_limit() {
+        let transactions: Vec<Transaction> = (1..=3)
+            .map(|i| transaction(vec![input(i, 10)], &[10]))
+            .collect();
+        let block = transaction_block(transactions[..2].to_vec());
+        assert_eq!(check_content_limits(&block, &config()), BlockResult::Pass);
+        let block = transaction_block(transactions);
+        assert_eq!(
+            check_content_limits(&block, &config()),
+            BlockResult::TooManyTransactions
+        );
+    }
+
+    #[test]
+    fn duplicate_transaction() {
+        let t = transaction(vec![input(1, 10)], &[10]);
+        let block = transaction_block(vec![t.clone(), t]);
+        assert_eq!(
+            check_content_limits(&block, &config()),
+            BlockResult::DuplicateTransaction
+        );
+    }
+
+    #[test]
+    fn duplicate_input() {
+        let t = transaction(vec![input(1, 10), input(1, 20)], &[30]);
+        let block = transaction_block(vec![t]);
+        assert_eq!(
+            check_content_limits(&block, &config()),
+            BlockResult::DuplicateInput
+        );
+    }
+
+    #[test]
+    fn value_overflow() {
+        // the input sum wraps around to 1 without the overflow check
+        let t = transaction(vec![input(1, std::u64::MAX), input(2, 2)], &[1]);
+        let block = transaction_block(vec![t]);
+        assert_eq!(
+            check_content_limits(&block, &config()),
+            BlockResult::ValueOverflow
+        );
+        let t = transaction(vec![input(1, 10)], &[std::u64::MAX, 1]);
+        let block = transaction_block(vec![t]);
+        assert_eq!(
+            check_content_limits(&block, &config()),
+            BlockResult::ValueOverflow
+        );
+    }
+
+    #[test]
+    fn insufficient_input() {
+        let t = transaction(vec![input(1, 10)], &[11]);
+        let block = transaction_block(vec![t]);
+        assert_eq!(
+            check_content_limits(&block, &config()),
+            BlockResult::InsufficientInput
+        );
+    }
+
+    #[test]
+    fn authorizations() {
+        let transactions: Vec<Transaction> = (0..5).map(|i| signed_transaction(i, i)).collect();
+        assert_eq!(
+            transaction::check_authorizations(&transactions),
+            BlockResult::Pass
+        );
+
+        let mut malformed = transactions.clone();
+        malformed[2].authorization[0].pubkey = vec![1, 2, 3];
+        malformed[4].authorization[0].signature.pop();
+        assert_eq!(
+            transaction::check_authorizations(&malformed),
+            BlockResult::MalformedAuthorization(vec![2, 4])
+        );
+
+        // signed by the owner of another coin
+        let mut wrong_owner = transactions.clone();
+        wrong_owner[1].authorization = transactions[0].authorization.clone();
+        assert_eq!(
+            transaction::check_authorizations(&wrong_owner),
+            BlockResult::WrongOwner(vec![1])
+        );
+
+        // the value is changed after signing
+        let mut wrong_signature = transactions.clone();
+        wrong_signature[1].output[0].value = 9;
+        wrong_signature[3].output[0].value = 9;
+        assert_eq!(
+            transaction::check_authorizations(&wrong_signature),
+            BlockResult::WrongSignature(vec![1, 3])
+        );
+        assert_eq!(
+            check_signatures(&wrong_signature[..2], 0),
+            BlockResult::WrongSignature(vec![1])
+        );
+        assert_eq!(
+            check_signatures(&wrong_signature[2..], 2),
+            BlockResult::WrongSignature(vec![3])
+        );
+    }
+
+    #[test]
+    fn light_block() {
+        let config = config();
+        let genesis = config.proposer_genesis;
+        let proposer = mined_block(
+            genesis,
+            Content::Proposer(proposer::Content {
+                transaction_refs: vec![generate_random_hash()],
+                proposer_refs: vec![generate_random_hash()],
+                utxo_commitment: None,
+            }),
+            &config,
+        );
+        let voter = mined_block(
+            genesis,
+            Content::Voter(voter::Content {
+                chain_number: 3,
+                voter_parent: config.voter_genesis[3],
+                votes: vec![genesis],
+            }),
+            &config,
+        );
+        let transactions = vec![transaction(vec![input(1, 10)], &[10])];
+        let transaction_block = mined_block(
+            genesis,
+            Content::Transaction(transaction_block::Content { transactions }),
+            &config,
+        );
+        for block in &[&proposer, &voter, &transaction_block] {
+            let light = LightBlock::from_block(block);
+            assert_eq!(check_light_block(&light, &config), BlockResult::Pass);
+        }
+
+        // the header hash is above the difficulty
+        let mut light = LightBlock::from_block(&proposer);
+        light.header.difficulty = H256::default();
+        assert_eq!(check_light_block(&light, &config), BlockResult::WrongPoW);
+
+        // the content is of another type than the header hash sortitions to
+        let mut light = LightBlock::from_block(&proposer);
+        light.content = LightBlock::from_block(&voter).content;
+        assert_eq!(
+            check_light_block(&light, &config),
+            BlockResult::WrongSortitionId
+        );
+
+        // the sortition proof does not link the content to the header
+        let mut light = LightBlock::from_block(&proposer);
+        light.sortition_proof[0] = generate_random_hash();
+        assert_eq!(
+            check_light_block(&light, &config),
+            BlockResult::WrongSortitionProof
+        );
+        let mut light = LightBlock::from_block(&proposer);
+        if let LightContent::Proposer { proposer_refs, .. } = &mut light.content {
+            proposer_refs.push(generate_random_hash());
+        }
+        assert_eq!(
+            check_light_block(&light, &config),
+            BlockResult::WrongSortitionProof
+        );
+    }
+}
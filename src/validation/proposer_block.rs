@@ -2,6 +2,7 @@ use super::{check_proposer_block_exists, check_transaction_block_exists};
 use crate::block::proposer::Content;
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
+use crate::config::BlockchainConfig;
 use crate::crypto::hash::H256;
 
 pub fn get_missing_references(
@@ -30,6 +31,11 @@ pub fn get_missing_references(
     missing_blocks
 }
 
+/// Checks that the block refers to at most `proposer_tx_refs` transaction blocks
+pub fn check_transaction_ref_count(content: &Content, config: &BlockchainConfig) -> bool {
+    content.transaction_refs.len() <= config.proposer_tx_refs as usize
+}
+
 pub fn check_ref_proposer_level(parent: &H256, content: &Content, blockchain: &BlockChain) -> bool {
     let parent_level = blockchain.proposer_level(parent).unwrap();
     for prop_block_hash in content.proposer_refs.iter() {
@@ -40,3 +46,97 @@ pub fn check_ref_proposer_level(parent: &H256, content: &Content, blockchain: &B
     }
     true
 }
+
+/// Checks that the block neither replaces the newest checkpoint at or below its level nor
+/// descends from a block that does
+pub fn check_checkpoint(hash: &H256, parent: &H256, blockchain: &BlockChain) -> bool {
+    let level = blockchain.proposer_level(parent).unwrap() + 1;
+    match blockchain.last_checkpoint(level) {
+        Some((checkpoint_level, checkpoint)) if checkpoint_level == level => checkpoint == *hash,
+        Some((checkpoint_level, checkpoint)) => {
+            let mut ancestor = *parent;
+            for _ in checkpoint_level + 1..level {
+                ancestor = blockchain.proposer_parent(&ancestor).unwrap();
+            }
+            ancestor == checkpoint
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::tests::proposer_block;
+    use crate::block::Block;
+    use crate::crypto::hash::Hashable;
+
+    /// Create a blockchain with a proposer block at level 1 and a checkpoint at level 2 on top of
+    /// it, and return the chain with both blocks. The checkpoint is not inserted.
+    fn checkpointed_blockchain(name: &str) -> (BlockChain, Block, Block) {
+        let mut config = BlockchainConfig::new(1, 8000, 100, 0.1, 0.1, 0.3, 20.0);
+        let first = proposer_block(config.proposer_genesis, 0, vec![], vec![]);
+        let checkpoint = proposer_block(first.hash(), 0, vec![], vec![]);
+        config.checkpoints.insert(2, checkpoint.hash());
+        let chain = BlockChain::new(
+            format!("/tmp/prism_test_validation_checkpoint_{}.rocksdb", name),
+            config,
+        )
+        .unwrap();
+        chain.insert_block(&first).unwrap();
+        (chain, first, checkpoint)
+    }
+
+    #[test]
+    fn conflicting_at_checkpoint_level() {
+        let (chain, first, checkpoint) = checkpointed_blockchain("conflicting");
+        let genesis = first.header.parent;
+        // a block at level 1 is not constrained
+        let other_first = proposer_block(genesis, 0, vec![], vec![]);
+        assert!(check_checkpoint(&other_first.hash(), &genesis, &chain));
+        chain.insert_block(&other_first).unwrap();
+        // any other block at level 2 conflicts, whichever parent it has
+        let conflicting = proposer_block(first.hash(), 0, vec![], vec![]);
+        assert!(!check_checkpoint(
+            &conflicting.hash(),
+            &first.hash(),
+            &chain
+        ));
+        let conflicting = proposer_block(other_first.hash(), 0, vec![], vec![]);
+        assert!(!check_checkpoint(
+            &conflicting.hash(),
+            &other_first.hash(),
+            &chain
+        ));
+        assert!(check_checkpoint(&checkpoint.hash(), &first.hash(), &chain));
+    }
+
+    #[test]
+    fn descendant_of_conflicting() {
+        let (chain, first, _) = checkpointed_blockchain("descendant");
+        // a conflicting block that got in before the checkpoint was pinned
+        let conflicting = proposer_block(first.hash(), 0, vec![], vec![]);
+        chain.insert_block(&conflicting).unwrap();
+        let child = proposer_block(conflicting.hash(), 0, vec![], vec![]);
+        assert!(!check_checkpoint(
+            &child.hash(),
+            &conflicting.hash(),
+            &chain
+        ));
+        chain.insert_block(&child).unwrap();
+        let grandchild = proposer_block(child.hash(), 0, vec![], vec![]);
+        assert!(!check_checkpoint(&grandchild.hash(), &child.hash(), &chain));
+    }
+
+    #[test]
+    fn matching_checkpoint() {
+        let (chain, first, checkpoint) = checkpointed_blockchain("matching");
+        assert!(check_checkpoint(&checkpoint.hash(), &first.hash(), &chain));
+        chain.insert_block(&checkpoint).unwrap();
+        let child = proposer_block(checkpoint.hash(), 0, vec![], vec![]);
+        assert!(check_checkpoint(&child.hash(), &checkpoint.hash(), &chain));
+        chain.insert_block(&child).unwrap();
+        let grandchild = proposer_block(child.hash(), 0, vec![], vec![]);
+        assert!(check_checkpoint(&grandchild.hash(), &child.hash(), &chain));
+    }
+}
@@ -1,7 +1,23 @@
-use crate::transaction::Transaction;
+use super::BlockResult;
+use crate::block::transaction::Content;
+use crate::config::BlockchainConfig;
+use crate::crypto::hash::Hashable;
+use crate::transaction::{Address, Transaction};
 
 use ed25519_dalek::PublicKey;
 use ed25519_dalek::Signature;
+use std::collections::HashSet;
+
+/// Checks that the block has at most `tx_txs` transactions
+pub fn check_transaction_count(content: &Content, config: &BlockchainConfig) -> bool {
+    content.transactions.len() <= config.tx_txs as usize
+}
+
+/// Checks that the block does not include a transaction twice
+pub fn check_distinct_transactions(content: &Content) -> bool {
+    let mut hashes = HashSet::new();
+    content.transactions.iter().all(|t| hashes.insert(t.hash()))
+}
 
 /// Checks that input and output are non-empty
 pub fn check_non_empty(transaction: &Transaction) -> bool {
@@ -14,37 +30,126 @@ pub fn check_non_zero(transaction: &Transaction) -> bool {
         || transaction.output.iter().any(|x| x.value == 0))
 }
 
-/// Checks if input_sum >= output_sum
+/// Checks that the transaction does not spend a coin twice
+pub fn check_distinct_inputs(transaction: &Transaction) -> bool {
+    let mut coins = HashSet::new();
+    transaction.input.iter().all(|x| coins.insert(x.coin))
+}
+
+/// Sum up values, or return `None` on overflow.
+fn checked_sum<I: Iterator<Item = u64>>(mut values: I) -> Option<u64> {
+    values.try_fold(0u64, |sum, v| sum.checked_add(v))
+}
+
+/// Checks that input_sum and output_sum fit in u64
+pub fn check_no_overflow(transaction: &Transaction) -> bool {
+    checked_sum(transaction.input.iter().map(|x| x.value)).is_some()
+        && checked_sum(transaction.output.iter().map(|x| x.value)).is_some()
+}
+
+/// Checks if input_sum >= output_sum. Sums that overflow are insufficient
 pub fn check_sufficient_input(transaction: &Transaction) -> bool {
-    let input_sum: u64 = transaction.input.iter().map(|x| x.value).sum();
-    let output_sum: u64 = transaction.output.iter().map(|x| x.value).sum();
-    input_sum >= output_sum
+    let input_sum = checked_sum(transaction.input.iter().map(|x| x.value));
+    let output_sum = checked_sum(transaction.output.iter().map(|x| x.value));
+    match (input_sum, output_sum) {
+        (Some(input_sum), Some(output_sum)) => input_sum >= output_sum,
+        _ => false,
+    }
 }
 
-pub fn check_signature_batch(transactions: &[Transaction]) -> bool {
-    let mut raw_messages: Vec<Vec<u8>> = vec![];
-    let mut messages: Vec<&[u8]> = vec![];
-    let mut signatures: Vec<Signature> = vec![];
-    let mut public_keys: Vec<PublicKey> = vec![];
+/// The parsed authorizations of a transaction.
+struct Signed {
+    message: Vec<u8>,
+    public_keys: Vec<PublicKey>,
+    signatures: Vec<Signature>,
+}
 
-    for (_idx, tx) in transactions.iter().enumerate() {
+/// Checks the authorizations of the transactions: the public keys and signatures are well-formed,
+/// the signers are exactly the owners of the inputs, and the signatures are correct. Failures are
+/// reported with the indices of the offending transactions.
+pub fn check_authorizations(transactions: &[Transaction]) -> BlockResult {
+    let mut malformed = vec![];
+    let mut wrong_owners = vec![];
+    let mut signed = vec![];
+    for (idx, tx) in transactions.iter().enumerate() {
+        let mut public_keys = vec![];
+        let mut signatures = vec![];
+        for a in &tx.authorization {
+            match (
+                PublicKey::from_bytes(&a.pubkey),
+                Signature::from_bytes(&a.signature),
+            ) {
+                (Ok(public_key), Ok(signature)) => {
+                    public_keys.push(public_key);
+                    signatures.push(signature);
+                }
+                _ => {
+                    malformed.push(idx);
+                    break;
+                }
+            }
+        }
+        let signers: HashSet<Address> = tx
+            .authorization
+            .iter()
+            .map(|a| ring::digest::digest(&ring::digest::SHA256, &a.pubkey).into())
+            .collect();
+        let owners: HashSet<Address> = tx.input.iter().map(|x| x.owner).collect();
+        if signers != owners {
+            wrong_owners.push(idx);
+        }
         let raw_inputs = bincode::serialize(&tx.input).unwrap();
         let raw_outputs = bincode::serialize(&tx.output).unwrap();
-        let raw = [&raw_inputs[..], &raw_outputs[..]].concat();
-        raw_messages.push(raw);
+        signed.push(Signed {
+            message: [&raw_inputs[..], &raw_outputs[..]].concat(),
+            public_keys,
+            signatures,
+        });
+    }
+    if !malformed.is_empty() {
+        return BlockResult::MalformedAuthorization(malformed);
+    }
+    if !wrong_owners.is_empty() {
+        return BlockResult::WrongOwner(wrong_owners);
     }
 
-    for (idx, tx) in transactions.iter().enumerate() {
-        for a in &tx.authorization {
-            public_keys.push(PublicKey::from_bytes(&a.pubkey).unwrap());
-            signatures.push(Signature::from_bytes(&a.signature).unwrap());
-            messages.push(&raw_messages[idx]);
+    if verify_batch(&signed) {
+        return BlockResult::Pass;
+    }
+    let mut wrong_signatures = vec![];
+    bisect(&signed, 0, &mut wrong_signatures);
+    BlockResult::WrongSignature(wrong_signatures)
+}
+
+/// Verify the signatures of the transactions in one batch.
+fn verify_batch(signed: &[Signed]) -> bool {
+    let mut messages: Vec<&[u8]> = vec![];
+    let mut signatures: Vec<Signature> = vec![];
+    let mut public_keys: Vec<PublicKey> = vec![];
+    for tx in signed {
+        for (public_key, signature) in tx.public_keys.iter().zip(tx.signatures.iter()) {
+            public_keys.push(*public_key);
+            signatures.push(*signature);
+            messages.push(&tx.message);
         }
     }
+    if signatures.is_empty() {
+        return true;
+    }
+    ed25519_dalek::verify_batch(&messages, &signatures, &public_keys).is_ok()
+}
 
-    // TODO: tune the batch size
-    match ed25519_dalek::verify_batch(&messages, &signatures, &public_keys) {
-        Ok(()) => true,
-        Err(_) => false,
+/// Find the transactions with wrong signatures in a batch that fails verification, by verifying
+/// each half of it in turn. Indices are offset by the position of the batch in the block.
+fn bisect(signed: &[Signed], offset: usize, wrong: &mut Vec<usize>) {
+    if signed.len() == 1 {
+        wrong.push(offset);
+        return;
+    }
+    let mid = signed.len() / 2;
+    for (half, offset) in &[(&signed[..mid], offset), (&signed[mid..], offset + mid)] {
+        if !verify_batch(half) {
+            bisect(half, *offset, wrong);
+        }
     }
 }
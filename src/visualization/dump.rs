@@ -1,6 +1,7 @@
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::crypto::hash::H256;
+use crate::experiment::attribution::Attribution;
 use crate::utxodb::UtxoDatabase;
 
 /*
@@ -179,3 +180,10 @@ pub fn dump_voter_timestamp(blockchain: &BlockChain, blockdb: &BlockDatabase) ->
     }
     serde_json::to_string_pretty(&dump).unwrap()
 }
+
+pub fn dump_miners(blockchain: &BlockChain, blockdb: &BlockDatabase, limit: u64) -> String {
+    match Attribution::collect(blockchain, blockdb, limit) {
+        Ok(attribution) => serde_json::to_string_pretty(&attribution.shares()).unwrap(),
+        Err(_) => "database err".to_string(),
+    }
+}
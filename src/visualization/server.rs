@@ -1,4 +1,4 @@
-use super::dump::{dump_ledger, dump_voter_timestamp};
+use super::dump::{dump_ledger, dump_miners, dump_voter_timestamp};
 use crate::blockchain::BlockChain;
 use crate::blockdb::BlockDatabase;
 use crate::utxodb::UtxoDatabase;
@@ -117,6 +117,12 @@ impl Server {
                             "application/json",
                             addr
                         ),
+                        "/miners.json" => serve_dynamic_file!(
+                            req,
+                            dump_miners(&blockchain, &blockdb, limit),
+                            "application/json",
+                            addr
+                        ),
                         "/cytoscape.min.js" => {
                             serve_static_file!(req, "cytoscape.js", "application/javascript")
                         }
@@ -1,3 +1,5 @@
+use crate::crypto::hash::{Hashable, H256};
+use crate::transaction::address::Bech32Address;
 use crate::transaction::{Address, Authorization, CoinId, Input, Output, Transaction};
 use bincode::serialize;
 use ed25519_dalek::Keypair;
@@ -12,6 +14,7 @@ use std::{error, fmt};
 
 pub const COIN_CF: &str = "COIN";
 pub const KEYPAIR_CF: &str = "KEYPAIR"; // &Address to &KeyPairPKCS8
+pub const HISTORY_CF: &str = "HISTORY"; // Sequence number (u64, big endian) to &HistoryEntry
 
 pub type Result<T> = std::result::Result<T, WalletError>;
 
@@ -22,12 +25,31 @@ pub struct Wallet {
     /// Keep key pair (in pkcs8 bytes) in memory for performance, it's duplicated in database as well.
     keypairs: Mutex<HashMap<Address, Keypair>>,
     counter: AtomicUsize,
+    /// Sequence number of the next history entry.
+    history_counter: AtomicUsize,
+}
+
+/// A record of coins received or sent by the wallet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum HistoryEntry {
+    /// A coin owned by one of our addresses has been confirmed.
+    Received {
+        coin: CoinId,
+        value: u64,
+        recipient: Address,
+    },
+    /// We have created a transaction paying to the recipient.
+    Sent {
+        transaction: H256,
+        value: u64,
+        recipient: Address,
+    },
 }
 
 #[derive(Debug)]
 pub enum WalletError {
     InsufficientBalance,
-    MissingKeyPair,
+    MissingKeyPair(Address),
     DBError(rocksdb::Error),
 }
 
@@ -35,7 +57,9 @@ impl fmt::Display for WalletError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             WalletError::InsufficientBalance => write!(f, "insufficient balance"),
-            WalletError::MissingKeyPair => write!(f, "missing key pair for the requested address"),
+            WalletError::MissingKeyPair(addr) => {
+                write!(f, "missing key pair for address {}", Bech32Address(addr))
+            }
             WalletError::DBError(ref e) => e.fmt(f),
         }
     }
@@ -61,14 +85,21 @@ impl Wallet {
         let coin_cf = rocksdb::ColumnFamilyDescriptor::new(COIN_CF, rocksdb::Options::default());
         let keypair_cf =
             rocksdb::ColumnFamilyDescriptor::new(KEYPAIR_CF, rocksdb::Options::default());
+        let history_cf =
+            rocksdb::ColumnFamilyDescriptor::new(HISTORY_CF, rocksdb::Options::default());
         let mut db_opts = rocksdb::Options::default();
         db_opts.create_missing_column_families(true);
         db_opts.create_if_missing(true);
-        let handle = rocksdb::DB::open_cf_descriptors(&db_opts, path, vec![coin_cf, keypair_cf])?;
+        let handle = rocksdb::DB::open_cf_descriptors(
+            &db_opts,
+            path,
+            vec![coin_cf, keypair_cf, history_cf],
+        )?;
         Ok(Self {
             db: handle,
             keypairs: Mutex::new(HashMap::new()),
             counter: AtomicUsize::new(0),
+            history_counter: AtomicUsize::new(0),
         })
     }
 
@@ -114,15 +145,27 @@ impl Wallet {
         false
     }
 
+    fn history_key(&self) -> [u8; 8] {
+        let seq = self.history_counter.fetch_add(1, Ordering::Relaxed) as u64;
+        seq.to_be_bytes()
+    }
+
     pub fn apply_diff(&self, add: &[(CoinId, Output)], remove: &[CoinId]) -> Result<()> {
         let mut batch = rocksdb::WriteBatch::default();
         let cf = self.db.cf_handle(COIN_CF).unwrap();
+        let history_cf = self.db.cf_handle(HISTORY_CF).unwrap();
         for coin in add {
             if self.contains_keypair(&coin.1.recipient) {
                 let key = serialize(&coin.0).unwrap();
                 let val = serialize(&coin.1).unwrap();
                 batch.put_cf(cf, &key, &val)?;
                 self.counter.fetch_add(1, Ordering::Relaxed);
+                let entry = HistoryEntry::Received {
+                    coin: coin.0,
+                    value: coin.1.value,
+                    recipient: coin.1.recipient,
+                };
+                batch.put_cf(history_cf, &self.history_key(), &serialize(&entry).unwrap())?;
             }
         }
         for coin in remove {
@@ -223,16 +266,36 @@ impl Wallet {
                     signature: v.sign(&raw_unsigned).to_bytes().to_vec(),
                 });
             } else {
-                return Err(WalletError::MissingKeyPair);
+                return Err(WalletError::MissingKeyPair(*owner));
             }
             drop(keypairs);
         }
         self.counter
             .fetch_sub(unsigned.input.len(), Ordering::Relaxed);
-        Ok(Transaction {
+        let transaction = Transaction {
             authorization,
             ..unsigned
-        })
+        };
+        let entry = HistoryEntry::Sent {
+            transaction: transaction.hash(),
+            value,
+            recipient,
+        };
+        let history_cf = self.db.cf_handle(HISTORY_CF).unwrap();
+        self.db
+            .put_cf(history_cf, &self.history_key(), &serialize(&entry).unwrap())?;
+        Ok(transaction)
+    }
+
+    /// Get the most recent history entries, newest first.
+    pub fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let cf = self.db.cf_handle(HISTORY_CF).unwrap();
+        let iter = self.db.iterator_cf(cf, rocksdb::IteratorMode::End)?;
+        let entries = iter
+            .take(limit)
+            .map(|(_, v)| bincode::deserialize(v.as_ref()).unwrap())
+            .collect();
+        Ok(entries)
     }
 }
 